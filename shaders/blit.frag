@@ -0,0 +1,10 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform texture2D source_texture;
+layout(set = 0, binding = 1) uniform sampler point_sampler;
+
+layout(location = 0) out vec4 color;
+
+void main() {
+    color = texelFetch(sampler2D(source_texture, point_sampler), ivec2(gl_FragCoord.xy), 0);
+}
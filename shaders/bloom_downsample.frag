@@ -0,0 +1,78 @@
+#version 450
+
+layout(location = 0) in vec2 uv;
+layout(set = 0, binding = 0) uniform texture2D source_texture;
+layout(set = 0, binding = 1) uniform sampler source_sampler;
+layout(set = 0, binding = 2) uniform Bloom {
+    vec2 texel_size;
+    float threshold;
+    float knee;
+    float radius;
+    uint first_pass;
+};
+
+layout(location = 0) out vec4 color;
+
+vec3 sample_source(vec2 offset) {
+    return texture(sampler2D(source_texture, source_sampler), uv + offset * texel_size).rgb;
+}
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+// Weights a box of 4 samples by its inverse brightness so single very bright pixels don't flicker
+float karis_weight(vec3 box) {
+    return 1.0 / (1.0 + luminance(box));
+}
+
+// Quadratic soft threshold, fully passes everything when threshold is 0
+vec3 apply_threshold(vec3 color) {
+    float brightness = max(color.r, max(color.g, color.b));
+    float soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 0.00001);
+    float contribution = max(soft, brightness - threshold) / max(brightness, 0.00001);
+    return color * contribution;
+}
+
+// 13 tap downsample from "Next Generation Post Processing in Call of Duty: Advanced Warfare"
+void main() {
+    vec3 a = sample_source(vec2(-2.0, 2.0));
+    vec3 b = sample_source(vec2(0.0, 2.0));
+    vec3 c = sample_source(vec2(2.0, 2.0));
+    vec3 d = sample_source(vec2(-2.0, 0.0));
+    vec3 e = sample_source(vec2(0.0, 0.0));
+    vec3 f = sample_source(vec2(2.0, 0.0));
+    vec3 g = sample_source(vec2(-2.0, -2.0));
+    vec3 h = sample_source(vec2(0.0, -2.0));
+    vec3 i = sample_source(vec2(2.0, -2.0));
+    vec3 j = sample_source(vec2(-1.0, 1.0));
+    vec3 k = sample_source(vec2(1.0, 1.0));
+    vec3 l = sample_source(vec2(-1.0, -1.0));
+    vec3 m = sample_source(vec2(1.0, -1.0));
+
+    vec3 boxes[5] = vec3[](
+        (j + k + l + m) * 0.25,
+        (a + b + d + e) * 0.25,
+        (b + c + e + f) * 0.25,
+        (d + e + g + h) * 0.25,
+        (e + f + h + i) * 0.25);
+    float weights[5] = float[](0.5, 0.125, 0.125, 0.125, 0.125);
+
+    vec3 result = vec3(0.0);
+    if (first_pass != 0) {
+        float weight_sum = 0.0;
+        for (int box = 0; box < 5; box++) {
+            float weight = weights[box] * karis_weight(boxes[box]);
+            result += boxes[box] * weight;
+            weight_sum += weight;
+        }
+        result = apply_threshold(result / weight_sum);
+    } else {
+        for (int box = 0; box < 5; box++) {
+            result += boxes[box] * weights[box];
+        }
+    }
+
+    color = vec4(max(result, 0.0), 1.0);
+}
@@ -0,0 +1,29 @@
+#version 450
+
+layout(location = 0) in vec2 uv;
+layout(set = 0, binding = 0) uniform texture2D source_texture;
+layout(set = 0, binding = 1) uniform sampler source_sampler;
+layout(set = 0, binding = 2) uniform Bloom {
+    vec2 texel_size;
+    float threshold;
+    float knee;
+    float radius;
+    uint first_pass;
+};
+
+layout(location = 0) out vec4 color;
+
+vec3 sample_source(vec2 offset) {
+    return texture(sampler2D(source_texture, source_sampler), uv + offset * radius * texel_size)
+        .rgb;
+}
+
+// 3x3 tent filter, additively blended onto the next larger mip
+void main() {
+    vec3 result = sample_source(vec2(0.0, 0.0)) * 4.0;
+    result += (sample_source(vec2(0.0, 1.0)) + sample_source(vec2(-1.0, 0.0))
+        + sample_source(vec2(1.0, 0.0)) + sample_source(vec2(0.0, -1.0))) * 2.0;
+    result += sample_source(vec2(-1.0, 1.0)) + sample_source(vec2(1.0, 1.0))
+        + sample_source(vec2(-1.0, -1.0)) + sample_source(vec2(1.0, -1.0));
+    color = vec4(result / 16.0, 1.0);
+}
@@ -0,0 +1,17 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+    mat4 inverse_view_projection;
+    vec3 camera_position;
+};
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 color;
+
+layout(location = 0) out vec4 vertex_color;
+
+void main() {
+    vertex_color = vec4(color, 1.0);
+    gl_Position = view_projection * vec4(position, 1.0);
+}
@@ -0,0 +1,41 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+layout(location = 0) in vec2 uv;
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+    mat4 inverse_view_projection;
+    vec3 camera_position;
+};
+layout(set = 1, binding = 0) uniform texture2D albedo_texture;
+layout(set = 1, binding = 1) uniform texture2D normal_texture;
+layout(set = 1, binding = 2) uniform texture2D material_texture;
+layout(set = 1, binding = 3) uniform texture2D depth_texture;
+layout(set = 1, binding = 4) uniform sampler point_sampler;
+
+#include "lighting.glsl"
+
+layout(location = 0) out vec4 color;
+
+void main() {
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    float depth = texelFetch(sampler2D(depth_texture, point_sampler), pixel, 0).r;
+    // Nothing was drawn here, leave the skybox
+    if (depth == 1.0) {
+        discard;
+    }
+    vec4 position = inverse_view_projection * vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    vec3 albedo = texelFetch(sampler2D(albedo_texture, point_sampler), pixel, 0).rgb;
+    vec3 normal = texelFetch(sampler2D(normal_texture, point_sampler), pixel, 0).xyz;
+    vec2 material = texelFetch(sampler2D(material_texture, point_sampler), pixel, 0).rg;
+
+    color = vec4(
+        shade(
+            position.xyz / position.w,
+            normalize(normal),
+            albedo,
+            material.r,
+            material.g,
+            camera_position),
+        1.0);
+}
@@ -0,0 +1,140 @@
+#version 450
+
+layout(location = 0) in vec2 uv;
+layout(set = 0, binding = 0) uniform texture2D ldr_texture;
+layout(set = 0, binding = 1) uniform sampler ldr_sampler;
+layout(set = 0, binding = 2) uniform Fxaa {
+    vec2 texel_size;
+};
+
+layout(location = 0) out vec4 color;
+
+const float EDGE_THRESHOLD_MIN = 0.0312;
+const float EDGE_THRESHOLD_MAX = 0.125;
+const float SUBPIXEL_QUALITY = 0.75;
+const int SEARCH_STEPS = 12;
+const float SEARCH_STEP_SIZES[SEARCH_STEPS] =
+    float[](1.0, 1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 2.0, 4.0, 8.0);
+
+vec3 sample_ldr(vec2 position) {
+    return textureLod(sampler2D(ldr_texture, ldr_sampler), position, 0.0).rgb;
+}
+
+// The texture is sRGB so samples are linear, square root brings them close to perceptual
+float luma(vec3 color) {
+    return sqrt(dot(color, vec3(0.299, 0.587, 0.114)));
+}
+
+float luma_at(vec2 offset) {
+    return luma(sample_ldr(uv + offset * texel_size));
+}
+
+// FXAA 3.11 quality preset, after Timothy Lottes
+void main() {
+    vec3 center_color = sample_ldr(uv);
+    float luma_center = luma(center_color);
+    float luma_down = luma_at(vec2(0.0, 1.0));
+    float luma_up = luma_at(vec2(0.0, -1.0));
+    float luma_left = luma_at(vec2(-1.0, 0.0));
+    float luma_right = luma_at(vec2(1.0, 0.0));
+
+    float luma_min = min(luma_center, min(min(luma_down, luma_up), min(luma_left, luma_right)));
+    float luma_max = max(luma_center, max(max(luma_down, luma_up), max(luma_left, luma_right)));
+    float luma_range = luma_max - luma_min;
+    if (luma_range < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD_MAX)) {
+        color = vec4(center_color, 1.0);
+        return;
+    }
+
+    float luma_down_left = luma_at(vec2(-1.0, 1.0));
+    float luma_up_right = luma_at(vec2(1.0, -1.0));
+    float luma_up_left = luma_at(vec2(-1.0, -1.0));
+    float luma_down_right = luma_at(vec2(1.0, 1.0));
+
+    float luma_down_up = luma_down + luma_up;
+    float luma_left_right = luma_left + luma_right;
+    float luma_left_corners = luma_down_left + luma_up_left;
+    float luma_down_corners = luma_down_left + luma_down_right;
+    float luma_right_corners = luma_down_right + luma_up_right;
+    float luma_up_corners = luma_up_right + luma_up_left;
+
+    float edge_horizontal = abs(-2.0 * luma_left + luma_left_corners)
+        + abs(-2.0 * luma_center + luma_down_up) * 2.0
+        + abs(-2.0 * luma_right + luma_right_corners);
+    float edge_vertical = abs(-2.0 * luma_up + luma_up_corners)
+        + abs(-2.0 * luma_center + luma_left_right) * 2.0
+        + abs(-2.0 * luma_down + luma_down_corners);
+    bool is_horizontal = edge_horizontal >= edge_vertical;
+
+    float luma_1 = is_horizontal ? luma_up : luma_left;
+    float luma_2 = is_horizontal ? luma_down : luma_right;
+    float gradient_1 = luma_1 - luma_center;
+    float gradient_2 = luma_2 - luma_center;
+    bool is_1_steepest = abs(gradient_1) >= abs(gradient_2);
+    float gradient_scaled = 0.25 * max(abs(gradient_1), abs(gradient_2));
+
+    float step_length = is_horizontal ? texel_size.y : texel_size.x;
+    float luma_local_average;
+    if (is_1_steepest) {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma_1 + luma_center);
+    } else {
+        luma_local_average = 0.5 * (luma_2 + luma_center);
+    }
+
+    vec2 edge_uv = uv;
+    if (is_horizontal) {
+        edge_uv.y += step_length * 0.5;
+    } else {
+        edge_uv.x += step_length * 0.5;
+    }
+
+    // Walk along the edge in both directions until its end
+    vec2 edge_step = is_horizontal ? vec2(texel_size.x, 0.0) : vec2(0.0, texel_size.y);
+    vec2 uv_1 = edge_uv - edge_step;
+    vec2 uv_2 = edge_uv + edge_step;
+    float luma_end_1 = luma(sample_ldr(uv_1)) - luma_local_average;
+    float luma_end_2 = luma(sample_ldr(uv_2)) - luma_local_average;
+    bool reached_1 = abs(luma_end_1) >= gradient_scaled;
+    bool reached_2 = abs(luma_end_2) >= gradient_scaled;
+    for (int i = 1; i < SEARCH_STEPS && !(reached_1 && reached_2); i++) {
+        if (!reached_1) {
+            uv_1 -= edge_step * SEARCH_STEP_SIZES[i];
+            luma_end_1 = luma(sample_ldr(uv_1)) - luma_local_average;
+            reached_1 = abs(luma_end_1) >= gradient_scaled;
+        }
+        if (!reached_2) {
+            uv_2 += edge_step * SEARCH_STEP_SIZES[i];
+            luma_end_2 = luma(sample_ldr(uv_2)) - luma_local_average;
+            reached_2 = abs(luma_end_2) >= gradient_scaled;
+        }
+    }
+
+    float distance_1 = is_horizontal ? uv.x - uv_1.x : uv.y - uv_1.y;
+    float distance_2 = is_horizontal ? uv_2.x - uv.x : uv_2.y - uv.y;
+    bool is_direction_1 = distance_1 < distance_2;
+    float distance_final = min(distance_1, distance_2);
+    float edge_length = distance_1 + distance_2;
+    float pixel_offset = -distance_final / edge_length + 0.5;
+
+    // Only blend when the luma variation at the closer end agrees with the center
+    bool is_luma_center_smaller = luma_center < luma_local_average;
+    bool correct_variation =
+        ((is_direction_1 ? luma_end_1 : luma_end_2) < 0.0) != is_luma_center_smaller;
+    float final_offset = correct_variation ? pixel_offset : 0.0;
+
+    float luma_average = (1.0 / 12.0) * (2.0 * (luma_down_up + luma_left_right)
+        + luma_left_corners + luma_right_corners);
+    float subpixel_offset_1 = clamp(abs(luma_average - luma_center) / luma_range, 0.0, 1.0);
+    float subpixel_offset_2 = (-2.0 * subpixel_offset_1 + 3.0) * subpixel_offset_1 * subpixel_offset_1;
+    float subpixel_offset = subpixel_offset_2 * subpixel_offset_2 * SUBPIXEL_QUALITY;
+    final_offset = max(final_offset, subpixel_offset);
+
+    vec2 final_uv = uv;
+    if (is_horizontal) {
+        final_uv.y += final_offset * step_length;
+    } else {
+        final_uv.x += final_offset * step_length;
+    }
+    color = vec4(sample_ldr(final_uv), 1.0);
+}
@@ -0,0 +1,27 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 uv;
+layout(set = 1, binding = 1) uniform texture2D mesh_texture;
+layout(set = 1, binding = 2) uniform sampler texture_sampler;
+layout(set = 1, binding = 4) uniform Material { float alpha_cutoff; };
+
+layout(location = 0) out vec4 albedo_out;
+layout(location = 1) out vec4 normal_out;
+layout(location = 2) out vec4 material_out;
+
+// Keep in sync with lighting.glsl, there are no per mesh materials yet
+const float ROUGHNESS = 0.5;
+const float SPECULAR_REFLECTANCE = 0.04;
+
+void main() {
+    vec4 object_color = texture(sampler2D(mesh_texture, texture_sampler), uv);
+    if (object_color.a < alpha_cutoff) {
+        discard;
+    }
+
+    albedo_out = vec4(object_color.rgb, 1.0);
+    normal_out = vec4(normalize(normal), 0.0);
+    material_out = vec4(ROUGHNESS, SPECULAR_REFLECTANCE, 0.0, 0.0);
+}
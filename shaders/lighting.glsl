@@ -0,0 +1,101 @@
+// Shading shared by the forward and deferred paths, which both bind the lights at set 2 and the
+// environment at set 3
+
+struct PointLight {
+    vec3 position;
+    float range;
+    vec3 color;
+    float intensity;
+};
+
+layout(set = 2, binding = 0) readonly buffer Lights {
+    uint light_count;
+    PointLight lights[];
+};
+layout(set = 2, binding = 1) uniform Clusters {
+    mat4 inverse_projection;
+    mat4 view;
+    uvec4 grid_size;
+    vec2 screen_size;
+    float z_near;
+    float z_far;
+};
+// Each cluster is its light count followed by MAX_LIGHTS_PER_CLUSTER light indices, written by
+// light_clusters.comp
+layout(set = 2, binding = 2) readonly buffer ClusterLights { uint cluster_lights[]; };
+layout(set = 3, binding = 1) uniform textureCube irradiance_texture;
+layout(set = 3, binding = 2) uniform textureCube specular_texture;
+layout(set = 3, binding = 3) uniform sampler environment_sampler;
+layout(set = 3, binding = 4) uniform texture2D ambient_occlusion_texture;
+
+// Keep in sync with Environment::SPECULAR_MIP_COUNT
+const float SPECULAR_MIP_COUNT = 5.0;
+// Keep in sync with LightClusters::MAX_LIGHTS_PER_CLUSTER
+const uint MAX_LIGHTS_PER_CLUSTER = 128;
+// There are no per mesh materials yet
+const float ROUGHNESS = 0.5;
+const float SPECULAR_REFLECTANCE = 0.04;
+
+// Analytic fit of the split sum BRDF integral, from "Physically Based Shading on Mobile" by Karis
+vec3 environment_brdf(vec3 reflectance, float roughness, float n_dot_v) {
+    const vec4 c0 = vec4(-1.0, -0.0275, -0.572, 0.022);
+    const vec4 c1 = vec4(1.0, 0.0425, 1.04, -0.04);
+    vec4 r = roughness * c0 + c1;
+    float a004 = min(r.x * r.x, exp2(-9.28 * n_dot_v)) * r.x + r.y;
+    vec2 scale_bias = vec2(-1.04, 1.04) * a004 + r.zw;
+    return reflectance * scale_bias.x + scale_bias.y;
+}
+
+// Inverse square falloff, windowed to reach zero at the light's range
+float light_attenuation(float light_distance, float range) {
+    float ratio = light_distance / range;
+    float window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return window * window / max(light_distance * light_distance, 0.0001);
+}
+
+// Index of the cluster holding the fragment at `position`, slices are spaced exponentially
+uint cluster_index(vec3 position) {
+    float view_depth = -(view * vec4(position, 1.0)).z;
+    float slice = log(view_depth / z_near) / log(z_far / z_near) * float(grid_size.z);
+    uint z = uint(clamp(slice, 0.0, float(grid_size.z - 1)));
+    uvec2 tile = min(uvec2(gl_FragCoord.xy / screen_size * vec2(grid_size.xy)), grid_size.xy - 1);
+    return (z * grid_size.y + tile.y) * grid_size.x + tile.x;
+}
+
+vec3 shade(
+    vec3 position,
+    vec3 normal,
+    vec3 albedo,
+    float roughness,
+    float reflectance,
+    vec3 camera_position
+) {
+    vec3 view_direction = normalize(camera_position - position);
+    float ambient_occlusion = texelFetch(
+        sampler2D(ambient_occlusion_texture, environment_sampler), ivec2(gl_FragCoord.xy), 0).r;
+    vec3 irradiance = texture(samplerCube(irradiance_texture, environment_sampler), normal).rgb
+        * ambient_occlusion;
+    vec3 prefiltered = textureLod(
+        samplerCube(specular_texture, environment_sampler),
+        reflect(-view_direction, normal),
+        roughness * (SPECULAR_MIP_COUNT - 1.0)).rgb * ambient_occlusion;
+    vec3 specular_color = prefiltered * environment_brdf(
+        vec3(reflectance), roughness, max(dot(normal, view_direction), 0.0));
+
+    vec3 diffuse_color = vec3(0.0);
+    uint offset = cluster_index(position) * (MAX_LIGHTS_PER_CLUSTER + 1);
+    uint cluster_light_count = cluster_lights[offset];
+    for (uint i = 0; i < cluster_light_count; i++) {
+        PointLight light = lights[cluster_lights[offset + 1 + i]];
+        vec3 to_light = light.position - position;
+        float light_distance = length(to_light);
+        if (light_distance >= light.range) {
+            continue;
+        }
+        diffuse_color += light.color * light.intensity
+            * light_attenuation(light_distance, light.range)
+            * max(dot(normal, to_light / light_distance), 0.0);
+    }
+
+    return (irradiance + diffuse_color) * albedo + specular_color;
+}
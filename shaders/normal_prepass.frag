@@ -0,0 +1,17 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 uv;
+layout(set = 1, binding = 1) uniform texture2D mesh_texture;
+layout(set = 1, binding = 2) uniform sampler texture_sampler;
+layout(set = 1, binding = 4) uniform Material { float alpha_cutoff; };
+
+layout(location = 0) out vec4 normal_out;
+
+void main() {
+    if (texture(sampler2D(mesh_texture, texture_sampler), uv).a < alpha_cutoff) {
+        discard;
+    }
+    normal_out = vec4(normalize(normal), 1.0);
+}
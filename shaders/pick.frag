@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 2) in vec2 uv;
+layout(location = 3) flat in uint instance;
+layout(set = 1, binding = 1) uniform texture2D mesh_texture;
+layout(set = 1, binding = 2) uniform sampler texture_sampler;
+layout(set = 1, binding = 4) uniform Material { float alpha_cutoff; };
+// One more than the mesh's index, 0 is left for the background
+layout(set = 2, binding = 0) uniform Pick { uint mesh_id; };
+
+layout(location = 0) out uvec2 id;
+
+void main() {
+    if (texture(sampler2D(mesh_texture, texture_sampler), uv).a < alpha_cutoff) {
+        discard;
+    }
+    id = uvec2(mesh_id, instance);
+}
@@ -0,0 +1,27 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+layout(location = 0) in vec3 position_in;
+layout(location = 2) in vec2 uv_in;
+layout(location = 3) in uvec4 joints_in;
+layout(location = 4) in vec4 weights_in;
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+    mat4 inverse_view_projection;
+    vec3 camera_position;
+};
+layout(set = 1, binding = 0) buffer Instances { mat4 transforms[]; };
+layout(set = 1, binding = 3) buffer VisibleInstances { uint visible_instances[]; };
+
+#include "skinning.glsl"
+
+layout(location = 2) out vec2 uv_out;
+layout(location = 3) flat out uint instance_out;
+
+void main() {
+    uint instance = visible_instances[gl_InstanceIndex];
+    gl_Position = view_projection * skinned_transform(instance, joints_in, weights_in)
+        * vec4(position_in, 1.0);
+    uv_out = uv_in;
+    instance_out = instance;
+}
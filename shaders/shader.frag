@@ -1,24 +1,83 @@
 #version 450
+#extension GL_GOOGLE_include_directive : require
 
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec3 normal;
 layout(location = 2) in vec2 uv;
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+    mat4 inverse_view_projection;
+    vec3 camera_position;
+    uint debug_view;
+};
 layout(set = 1, binding = 1) uniform texture2D mesh_texture;
 layout(set = 1, binding = 2) uniform sampler texture_sampler;
-layout(set = 2, binding = 0) uniform Light { vec3 light_position; };
+layout(set = 1, binding = 4) uniform Material { float alpha_cutoff; };
+
+#include "lighting.glsl"
+
+// Keep in sync with DebugView
+const uint DEBUG_VIEW_NONE = 0;
+const uint DEBUG_VIEW_NORMALS = 1;
+const uint DEBUG_VIEW_UVS = 2;
+const uint DEBUG_VIEW_WORLD_POSITION = 3;
+const uint DEBUG_VIEW_DEPTH = 4;
+const uint DEBUG_VIEW_ALBEDO = 5;
+const uint DEBUG_VIEW_LIGHTING = 6;
+const uint DEBUG_VIEW_OVERDRAW = 7;
 
 layout(location = 0) out vec4 color;
 
 void main() {
     vec4 object_color = texture(sampler2D(mesh_texture, texture_sampler), uv);
+    if (object_color.a < alpha_cutoff) {
+        discard;
+    }
 
-    vec3 light_color = vec3(1.0, 1.0, 1.0);
-
-    vec3 ambient_color = light_color * 0.1;
-
-    vec3 light_direction = normalize(light_position - position);
-    float diffuse_strength = max(dot(normalize(normal), light_direction), 0.0);
-    vec3 diffuse_color = light_color * diffuse_strength;
-
-    color = vec4((ambient_color + diffuse_color) * object_color.rgb, object_color.a);
+    switch (debug_view) {
+    case DEBUG_VIEW_NONE:
+        color = vec4(
+            shade(
+                position,
+                normalize(normal),
+                object_color.rgb,
+                ROUGHNESS,
+                SPECULAR_REFLECTANCE,
+                camera_position),
+            object_color.a);
+        break;
+    case DEBUG_VIEW_NORMALS:
+        color = vec4(normalize(normal) * 0.5 + 0.5, 1.0);
+        break;
+    case DEBUG_VIEW_UVS:
+        color = vec4(fract(uv), 0.0, 1.0);
+        break;
+    // Repeats every unit, so the grid shows the scale
+    case DEBUG_VIEW_WORLD_POSITION:
+        color = vec4(fract(position), 1.0);
+        break;
+    // Logarithmic view space depth, the same distribution as the light cluster slices
+    case DEBUG_VIEW_DEPTH:
+        float view_depth = -(view * vec4(position, 1.0)).z;
+        color = vec4(vec3(log(view_depth / z_near) / log(z_far / z_near)), 1.0);
+        break;
+    case DEBUG_VIEW_ALBEDO:
+        color = vec4(object_color.rgb, 1.0);
+        break;
+    case DEBUG_VIEW_LIGHTING:
+        color = vec4(
+            shade(
+                position,
+                normalize(normal),
+                vec3(1.0),
+                ROUGHNESS,
+                SPECULAR_REFLECTANCE,
+                camera_position),
+            1.0);
+        break;
+    // Blended additively, so red, then yellow, then white after about 10, 25 and 50 layers
+    case DEBUG_VIEW_OVERDRAW:
+        color = vec4(0.1, 0.04, 0.02, 1.0);
+        break;
+    }
 }
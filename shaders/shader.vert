@@ -1,19 +1,34 @@
 #version 450
+#extension GL_GOOGLE_include_directive : require
 
 layout(location = 0) in vec3 position_in;
 layout(location = 1) in vec3 normal_in;
 layout(location = 2) in vec2 uv_in;
-layout(set = 0, binding = 0) uniform Camera { mat4 view_projection; };
+layout(location = 3) in uvec4 joints_in;
+layout(location = 4) in vec4 weights_in;
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+    mat4 inverse_view_projection;
+    vec3 camera_position;
+};
 layout(set = 1, binding = 0) buffer Instances { mat4 transforms[]; };
+layout(set = 1, binding = 3) buffer VisibleInstances { uint visible_instances[]; };
+
+#include "skinning.glsl"
 
 layout(location = 0) out vec3 position_out;
 layout(location = 1) out vec3 normal_out;
 layout(location = 2) out vec2 uv_out;
+// The wireframe overlay is depth tested against these positions
+invariant gl_Position;
 
 void main() {
-    gl_Position = view_projection * transforms[gl_InstanceIndex] * vec4(position_in, 1.0);
+    mat4 transform =
+        skinned_transform(visible_instances[gl_InstanceIndex], joints_in, weights_in);
+    vec4 world_position = transform * vec4(position_in, 1.0);
+    gl_Position = view_projection * world_position;
 
-    position_out = position_in;
-    normal_out = mat3(transpose(inverse(transforms[gl_InstanceIndex]))) * normal_in;
+    position_out = world_position.xyz;
+    normal_out = mat3(transpose(inverse(transform))) * normal_in;
     uv_out = uv_in;
 }
@@ -0,0 +1,23 @@
+// Keep in sync with MeshUniforms, the fragment shaders only declare the alpha cutoff
+layout(set = 1, binding = 4) uniform Mesh {
+    float alpha_cutoff;
+    uint joint_count;
+    // The joint matrices of each instance follow the instance transforms
+    uint first_joint;
+    uint skin_offset;
+};
+
+// Expects `transforms` to be declared. Vertices without weights, and every vertex of meshes
+// without a skin, only get the instance's transform.
+mat4 skinned_transform(uint instance, uvec4 joints, vec4 weights) {
+    mat4 transform = transforms[instance];
+    if (joint_count == 0 || weights == vec4(0.0)) {
+        return transform;
+    }
+    uint base = first_joint + instance * joint_count;
+    mat4 skin = weights.x * transforms[base + joints.x]
+        + weights.y * transforms[base + joints.y]
+        + weights.z * transforms[base + joints.z]
+        + weights.w * transforms[base + joints.w];
+    return transform * skin;
+}
@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) in vec2 uv;
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+    mat4 inverse_view_projection;
+    vec3 camera_position;
+};
+layout(set = 1, binding = 0) uniform textureCube environment_texture;
+layout(set = 1, binding = 3) uniform sampler environment_sampler;
+
+layout(location = 0) out vec4 color;
+
+void main() {
+    vec4 far_point = inverse_view_projection * vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 1.0, 1.0);
+    vec3 direction = far_point.xyz / far_point.w - camera_position;
+    color = vec4(texture(samplerCube(environment_texture, environment_sampler), direction).rgb, 1.0);
+}
@@ -0,0 +1,61 @@
+#version 450
+
+layout(location = 0) in vec2 uv;
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+    mat4 inverse_view_projection;
+    vec3 camera_position;
+};
+layout(set = 1, binding = 0) uniform texture2D depth_texture;
+layout(set = 1, binding = 1) uniform texture2D normal_texture;
+layout(set = 1, binding = 2) uniform sampler point_sampler;
+layout(set = 1, binding = 3) uniform Ssao {
+    vec4 kernel[16];
+    float radius;
+    float bias;
+    float intensity;
+};
+
+layout(location = 0) out float ambient_occlusion;
+
+const uint KERNEL_SIZE = 16;
+const float PI = 3.14159265359;
+
+vec3 world_position(vec2 position_uv) {
+    float depth = texture(sampler2D(depth_texture, point_sampler), position_uv).r;
+    vec4 position = inverse_view_projection
+        * vec4(position_uv.x * 2.0 - 1.0, 1.0 - position_uv.y * 2.0, depth, 1.0);
+    return position.xyz / position.w;
+}
+
+// Hemisphere sampling around the normal, with the kernel rotated by a 4x4 tiled pattern that the
+// blur pass averages out
+void main() {
+    if (texture(sampler2D(depth_texture, point_sampler), uv).r == 1.0) {
+        ambient_occlusion = 1.0;
+        return;
+    }
+    vec3 position = world_position(uv);
+    vec3 normal = normalize(texture(sampler2D(normal_texture, point_sampler), uv).xyz);
+
+    ivec2 tile = ivec2(gl_FragCoord.xy) % 4;
+    float angle = float((tile.x * 4 + tile.y) * 7 % 16) / 16.0 * 2.0 * PI;
+    vec3 random = vec3(cos(angle), sin(angle), 0.3);
+    vec3 tangent = normalize(random - normal * dot(random, normal));
+    mat3 tbn = mat3(tangent, cross(normal, tangent), normal);
+
+    float camera_distance = distance(camera_position, position);
+    float occlusion = 0.0;
+    for (uint i = 0; i < KERNEL_SIZE; i++) {
+        vec3 sample_position = position + tbn * kernel[i].xyz * radius;
+        vec4 clip = view_projection * vec4(sample_position, 1.0);
+        vec2 sample_uv = clip.xy / clip.w * vec2(0.5, -0.5) + 0.5;
+        vec3 scene_position = world_position(sample_uv);
+
+        bool occluded = distance(camera_position, scene_position)
+            < distance(camera_position, sample_position) - bias;
+        float range = smoothstep(0.0, 1.0, radius / max(distance(position, scene_position), 0.0001));
+        occlusion += occluded ? range : 0.0;
+    }
+    ambient_occlusion = pow(1.0 - occlusion / float(KERNEL_SIZE), intensity);
+}
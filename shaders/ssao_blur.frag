@@ -0,0 +1,19 @@
+#version 450
+
+layout(location = 0) in vec2 uv;
+layout(set = 0, binding = 0) uniform texture2D ssao_texture;
+layout(set = 0, binding = 1) uniform sampler point_sampler;
+
+layout(location = 0) out float ambient_occlusion;
+
+// Averages the 4x4 kernel rotation pattern
+void main() {
+    vec2 texel_size = 1.0 / vec2(textureSize(sampler2D(ssao_texture, point_sampler), 0));
+    float sum = 0.0;
+    for (int y = -2; y < 2; y++) {
+        for (int x = -2; x < 2; x++) {
+            sum += texture(sampler2D(ssao_texture, point_sampler), uv + vec2(x, y) * texel_size).r;
+        }
+    }
+    ambient_occlusion = sum / 16.0;
+}
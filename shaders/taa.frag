@@ -0,0 +1,50 @@
+#version 450
+
+layout(location = 0) in vec2 uv;
+layout(set = 0, binding = 0) uniform texture2D current_texture;
+layout(set = 0, binding = 1) uniform texture2D history_texture;
+layout(set = 0, binding = 2) uniform sampler linear_sampler;
+layout(set = 0, binding = 3) uniform Taa {
+    vec2 texel_size;
+    float history_weight;
+};
+
+layout(location = 0) out vec4 color;
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+// Blending in a compressed range keeps single bright samples from dominating the average
+vec3 compress(vec3 color) {
+    return color / (1.0 + luminance(color));
+}
+
+vec3 uncompress(vec3 color) {
+    return color / max(1.0 - luminance(color), 0.0001);
+}
+
+void main() {
+    vec3 current = vec3(0.0);
+    vec3 neighborhood_min = vec3(1.0);
+    vec3 neighborhood_max = vec3(0.0);
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            vec2 offset = vec2(x, y) * texel_size;
+            vec3 neighbor =
+                compress(texture(sampler2D(current_texture, linear_sampler), uv + offset).rgb);
+            neighborhood_min = min(neighborhood_min, neighbor);
+            neighborhood_max = max(neighborhood_max, neighbor);
+            if (x == 0 && y == 0) {
+                current = neighbor;
+            }
+        }
+    }
+
+    // The history isn't reprojected, it's sampled at the same position and discarded whenever the
+    // camera moves. Clamping it to the current neighborhood rejects the history of moving objects
+    vec3 history = compress(texture(sampler2D(history_texture, linear_sampler), uv).rgb);
+    history = clamp(history, neighborhood_min, neighborhood_max);
+
+    color = vec4(uncompress(mix(current, history, history_weight)), 1.0);
+}
@@ -0,0 +1,78 @@
+#version 450
+
+layout(location = 0) in vec2 uv;
+layout(set = 0, binding = 0) uniform texture2D hdr_texture;
+layout(set = 0, binding = 1) uniform sampler hdr_sampler;
+layout(set = 0, binding = 2) uniform Tonemap {
+    float exposure;
+    uint auto_exposure;
+    uint tonemapper;
+    float gamma;
+    float bloom_intensity;
+};
+layout(set = 0, binding = 3) readonly buffer AutoExposure {
+    float average_luminance;
+    float auto_exposure_scale;
+};
+layout(set = 0, binding = 4) uniform texture2D bloom_texture;
+
+layout(location = 0) out vec4 color;
+
+const uint TONEMAPPER_NONE = 0;
+const uint TONEMAPPER_ACES = 1;
+const uint TONEMAPPER_AGX = 2;
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+// Benjamin Wrensch's polynomial approximation of the AgX base contrast curve
+vec3 agx_contrast(vec3 x) {
+    vec3 x2 = x * x;
+    vec3 x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2
+        + 0.1191 * x - 0.00232;
+}
+
+vec3 agx(vec3 x) {
+    const mat3 inset = mat3(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104);
+    const mat3 outset = mat3(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116);
+    const float min_ev = -12.47393;
+    const float max_ev = 4.026069;
+
+    x = clamp(log2(max(inset * x, 1e-10)), min_ev, max_ev);
+    x = agx_contrast((x - min_ev) / (max_ev - min_ev));
+    // The curve outputs display encoded values, decode them since the target is sRGB
+    return pow(max(outset * x, 0.0), vec3(2.2));
+}
+
+void main() {
+    vec3 hdr_color = texture(sampler2D(hdr_texture, hdr_sampler), uv).rgb;
+    if (bloom_intensity > 0.0) {
+        vec3 bloom = texture(sampler2D(bloom_texture, hdr_sampler), uv).rgb;
+        hdr_color = mix(hdr_color, bloom, bloom_intensity);
+    }
+
+    hdr_color *= exposure;
+    if (auto_exposure != 0) {
+        hdr_color *= auto_exposure_scale;
+    }
+
+    vec3 ldr_color;
+    if (tonemapper == TONEMAPPER_ACES) {
+        ldr_color = aces(hdr_color);
+    } else if (tonemapper == TONEMAPPER_AGX) {
+        ldr_color = agx(hdr_color);
+    } else {
+        ldr_color = clamp(hdr_color, 0.0, 1.0);
+    }
+
+    color = vec4(pow(ldr_color, vec3(1.0 / gamma)), 1.0);
+}
@@ -0,0 +1,13 @@
+#version 450
+
+layout(set = 0, binding = 1) uniform texture2D font_texture;
+layout(set = 0, binding = 2) uniform sampler font_sampler;
+
+layout(location = 0) in vec2 vertex_uv;
+layout(location = 1) in vec4 vertex_color;
+
+layout(location = 0) out vec4 color;
+
+void main() {
+    color = vertex_color * texture(sampler2D(font_texture, font_sampler), vertex_uv);
+}
@@ -0,0 +1,23 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform Screen {
+    vec2 screen_size;
+};
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 uv;
+layout(location = 2) in vec4 color;
+
+layout(location = 0) out vec2 vertex_uv;
+layout(location = 1) out vec4 vertex_color;
+
+// egui colors are sRGB encoded, blending happens in linear space
+vec3 srgb_to_linear(vec3 srgb) {
+    return mix(srgb / 12.92, pow((srgb + 0.055) / 1.055, vec3(2.4)), step(0.04045, srgb));
+}
+
+void main() {
+    vertex_uv = uv;
+    vertex_color = vec4(srgb_to_linear(color.rgb), color.a);
+    gl_Position = vec4(position / screen_size * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+}
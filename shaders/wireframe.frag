@@ -0,0 +1,17 @@
+#version 450
+
+layout(location = 0) in vec3 barycentric;
+
+layout(location = 0) out vec4 color;
+
+// Line width in pixels
+const float LINE_WIDTH = 1.0;
+
+void main() {
+    // Distance to the nearest edge, in pixels
+    vec3 edge_distance = barycentric / fwidth(barycentric);
+    if (min(min(edge_distance.x, edge_distance.y), edge_distance.z) > LINE_WIDTH) {
+        discard;
+    }
+    color = vec4(1.0);
+}
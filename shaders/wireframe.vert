@@ -0,0 +1,51 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+// Pulls the vertices from storage buffers, so each vertex of a triangle knows which corner it is
+// without a barycentric attribute in the vertex buffer
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+    mat4 inverse_view_projection;
+    vec3 camera_position;
+};
+layout(set = 1, binding = 0) buffer Instances { mat4 transforms[]; };
+layout(set = 1, binding = 3) buffer VisibleInstances { uint visible_instances[]; };
+// Keep in sync with Vertex and SkinVertex, skinned meshes store their skin vertices after the
+// vertices
+layout(set = 2, binding = 0) readonly buffer Vertices { float vertices[]; };
+// 16-bit indices, two to a word
+layout(set = 2, binding = 1) readonly buffer Indices { uint indices[]; };
+
+#include "skinning.glsl"
+
+const uint VERTEX_SIZE = 8;
+
+layout(location = 0) out vec3 barycentric;
+// Computed the same way as in shader.vert, so the overlay can be depth tested against the scene
+invariant gl_Position;
+
+void main() {
+    uint packed = indices[gl_VertexIndex / 2];
+    uint index = gl_VertexIndex % 2 == 0 ? packed & 0xFFFF : packed >> 16;
+    vec3 position = vec3(
+        vertices[index * VERTEX_SIZE],
+        vertices[index * VERTEX_SIZE + 1],
+        vertices[index * VERTEX_SIZE + 2]);
+
+    uint skin = (skin_offset + index) * VERTEX_SIZE;
+    uvec4 joints = uvec4(
+        floatBitsToUint(vertices[skin]),
+        floatBitsToUint(vertices[skin + 1]),
+        floatBitsToUint(vertices[skin + 2]),
+        floatBitsToUint(vertices[skin + 3]));
+    vec4 weights = vec4(
+        vertices[skin + 4],
+        vertices[skin + 5],
+        vertices[skin + 6],
+        vertices[skin + 7]);
+    mat4 transform = skinned_transform(visible_instances[gl_InstanceIndex], joints, weights);
+    vec4 world_position = transform * vec4(position, 1.0);
+    gl_Position = view_projection * world_position;
+    barycentric = vec3(equal(uvec3(gl_VertexIndex % 3), uvec3(0, 1, 2)));
+}
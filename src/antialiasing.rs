@@ -0,0 +1,374 @@
+use crate::postprocess::{create_fullscreen_pipeline, create_linear_sampler, HDR_FORMAT};
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Antialiasing {
+    /// Multisampling with the given number of samples per pixel, 1 disables antialiasing.
+    Msaa(u32),
+    /// Fast approximate antialiasing on the tonemapped image.
+    Fxaa,
+    /// Temporal antialiasing, jitters the projection every frame and accumulates the results.
+    Taa,
+}
+
+impl Antialiasing {
+    pub fn sample_count(self) -> u32 {
+        match self {
+            Self::Msaa(sample_count) => sample_count,
+            Self::Fxaa | Self::Taa => 1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct FxaaUniforms {
+    texel_size: [f32; 2],
+    _padding: [u32; 2],
+}
+
+/// Smooths edges of the tonemapped image, which has to be rendered to `ldr_texture` first.
+pub struct Fxaa {
+    ldr_texture: TextureView,
+    format: TextureFormat,
+    width: u32,
+    height: u32,
+    sampler: Sampler,
+    bind_group_layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+}
+
+impl Fxaa {
+    pub fn new(device: &Device, format: TextureFormat, width: u32, height: u32) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::SampledTexture {
+                        dimension: TextureViewDimension::D2,
+                        component_type: TextureComponentType::Float,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Sampler { comparison: false },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_fullscreen_pipeline(
+            device,
+            &pipeline_layout,
+            &device.create_shader_module(include_spirv!("../shaders/fxaa.spv")),
+            format,
+            BlendDescriptor::REPLACE,
+            1,
+        );
+
+        Self {
+            ldr_texture: create_texture(device, format, width, height),
+            format,
+            width,
+            height,
+            sampler: create_linear_sampler(device),
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    pub fn ldr_texture(&self) -> &TextureView {
+        &self.ldr_texture
+    }
+
+    pub fn set_screen_size(&mut self, device: &Device, width: u32, height: u32) {
+        self.ldr_texture = create_texture(device, self.format, width, height);
+        self.width = width;
+        self.height = height;
+    }
+
+    pub fn render(&self, device: &Device, encoder: &mut CommandEncoder, target: &TextureView) {
+        let uniforms = FxaaUniforms {
+            texel_size: [1.0 / self.width as f32, 1.0 / self.height as f32],
+            _padding: [0; 2],
+        };
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::bytes_of(&uniforms),
+            usage: BufferUsage::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&self.ldr_texture),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&self.sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Buffer(uniform_buffer.slice(..)),
+                },
+            ],
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[RenderPassColorAttachmentDescriptor {
+                attachment: target,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct TaaUniforms {
+    texel_size: [f32; 2],
+    history_weight: f32,
+    _padding: u32,
+}
+
+/// Blends each jittered HDR frame into an accumulated history, ping-ponging between two
+/// textures so the previous result can be read while the next one is written.
+pub struct Taa {
+    history: [TextureView; 2],
+    /// Index of the most recently written history texture.
+    current: usize,
+    history_valid: bool,
+    frame: u32,
+    width: u32,
+    height: u32,
+    sampler: Sampler,
+    bind_group_layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+}
+
+impl Taa {
+    const JITTER_SAMPLE_COUNT: u32 = 8;
+    const HISTORY_WEIGHT: f32 = 0.9;
+
+    pub fn new(device: &Device, width: u32, height: u32) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::SampledTexture {
+                        dimension: TextureViewDimension::D2,
+                        component_type: TextureComponentType::Float,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::SampledTexture {
+                        dimension: TextureViewDimension::D2,
+                        component_type: TextureComponentType::Float,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Sampler { comparison: false },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_fullscreen_pipeline(
+            device,
+            &pipeline_layout,
+            &device.create_shader_module(include_spirv!("../shaders/taa.spv")),
+            HDR_FORMAT,
+            BlendDescriptor::REPLACE,
+            1,
+        );
+
+        Self {
+            history: create_history(device, width, height),
+            current: 0,
+            history_valid: false,
+            frame: 0,
+            width,
+            height,
+            sampler: create_linear_sampler(device),
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    /// Subpixel offset of the current frame in pixels, following the Halton (2, 3) sequence.
+    pub fn jitter(&self) -> (f32, f32) {
+        let index = self.frame % Self::JITTER_SAMPLE_COUNT + 1;
+        (halton(index, 2) - 0.5, halton(index, 3) - 0.5)
+    }
+
+    /// The antialiased result of the last `resolve`.
+    pub fn output(&self) -> &TextureView {
+        &self.history[self.current]
+    }
+
+    /// Discards the accumulated frames, for when the previous frames weren't jittered or the
+    /// camera moved.
+    pub fn invalidate_history(&mut self) {
+        self.history_valid = false;
+    }
+
+    pub fn set_screen_size(&mut self, device: &Device, width: u32, height: u32) {
+        self.history = create_history(device, width, height);
+        self.history_valid = false;
+        self.width = width;
+        self.height = height;
+    }
+
+    pub fn resolve(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        current_frame: &TextureView,
+    ) {
+        let (previous, current) = (self.current, 1 - self.current);
+        let uniforms = TaaUniforms {
+            texel_size: [1.0 / self.width as f32, 1.0 / self.height as f32],
+            history_weight: if self.history_valid {
+                Self::HISTORY_WEIGHT
+            } else {
+                0.0
+            },
+            _padding: 0,
+        };
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::bytes_of(&uniforms),
+            usage: BufferUsage::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(current_frame),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&self.history[previous]),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Sampler(&self.sampler),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Buffer(uniform_buffer.slice(..)),
+                },
+            ],
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[RenderPassColorAttachmentDescriptor {
+                attachment: &self.history[current],
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        self.current = current;
+        self.history_valid = true;
+        self.frame = self.frame.wrapping_add(1);
+    }
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+fn create_history(device: &Device, width: u32, height: u32) -> [TextureView; 2] {
+    [
+        create_texture(device, HDR_FORMAT, width, height),
+        create_texture(device, HDR_FORMAT, width, height),
+    ]
+}
+
+fn create_texture(device: &Device, format: TextureFormat, width: u32, height: u32) -> TextureView {
+    device
+        .create_texture(&TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::SAMPLED,
+        })
+        .create_view(&TextureViewDescriptor::default())
+}
@@ -0,0 +1,263 @@
+use crate::postprocess::{create_fullscreen_pipeline, create_linear_sampler, HDR_FORMAT};
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BloomSettings {
+    /// Brightness below which pixels don't bloom, 0 lets everything contribute.
+    pub threshold: f32,
+    /// How much of the blurred image is blended over the scene.
+    pub intensity: f32,
+    /// Spread of each upsampling step in texels.
+    pub radius: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            intensity: 0.05,
+            radius: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct BloomUniforms {
+    texel_size: [f32; 2],
+    threshold: f32,
+    knee: f32,
+    radius: f32,
+    first_pass: u32,
+    _padding: [u32; 2],
+}
+
+struct Mip {
+    view: TextureView,
+    width: u32,
+    height: u32,
+}
+
+/// Blurs the HDR scene by downsampling it through a chain of half sized textures, then
+/// upsampling and accumulating back up to the first one.
+pub struct Bloom {
+    width: u32,
+    height: u32,
+    mips: Vec<Mip>,
+    sampler: Sampler,
+    bind_group_layout: BindGroupLayout,
+    downsample_pipeline: RenderPipeline,
+    upsample_pipeline: RenderPipeline,
+}
+
+impl Bloom {
+    const MAX_MIP_COUNT: usize = 6;
+    const MIN_MIP_SIZE: u32 = 8;
+
+    pub fn new(device: &Device, width: u32, height: u32) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::SampledTexture {
+                        dimension: TextureViewDimension::D2,
+                        component_type: TextureComponentType::Float,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Sampler { comparison: false },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let downsample_pipeline = create_fullscreen_pipeline(
+            device,
+            &pipeline_layout,
+            &device.create_shader_module(include_spirv!("../shaders/bloom_downsample.spv")),
+            HDR_FORMAT,
+            BlendDescriptor::REPLACE,
+            1,
+        );
+        let upsample_pipeline = create_fullscreen_pipeline(
+            device,
+            &pipeline_layout,
+            &device.create_shader_module(include_spirv!("../shaders/bloom_upsample.spv")),
+            HDR_FORMAT,
+            BlendDescriptor {
+                src_factor: BlendFactor::One,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Add,
+            },
+            1,
+        );
+
+        Self {
+            width,
+            height,
+            mips: create_mips(device, width, height),
+            sampler: create_linear_sampler(device),
+            bind_group_layout,
+            downsample_pipeline,
+            upsample_pipeline,
+        }
+    }
+
+    /// Half resolution texture holding the blurred scene after `render`.
+    pub fn texture(&self) -> &TextureView {
+        &self.mips[0].view
+    }
+
+    pub fn set_screen_size(&mut self, device: &Device, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.mips = create_mips(device, width, height);
+    }
+
+    pub fn render(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        settings: &BloomSettings,
+        hdr_texture: &TextureView,
+    ) {
+        let uniforms = |source_width: u32, source_height: u32, first_pass: bool| BloomUniforms {
+            texel_size: [1.0 / source_width as f32, 1.0 / source_height as f32],
+            threshold: settings.threshold,
+            knee: settings.threshold * 0.5,
+            radius: settings.radius,
+            first_pass: first_pass as u32,
+            _padding: [0; 2],
+        };
+
+        let mut source = hdr_texture;
+        let mut source_size = (self.width, self.height);
+        for (i, mip) in self.mips.iter().enumerate() {
+            self.filter_pass(
+                device,
+                encoder,
+                &self.downsample_pipeline,
+                &uniforms(source_size.0, source_size.1, i == 0),
+                source,
+                &mip.view,
+            );
+            source = &mip.view;
+            source_size = (mip.width, mip.height);
+        }
+
+        for pair in self.mips.windows(2).rev() {
+            let (target, source) = (&pair[0], &pair[1]);
+            self.filter_pass(
+                device,
+                encoder,
+                &self.upsample_pipeline,
+                &uniforms(source.width, source.height, false),
+                &source.view,
+                &target.view,
+            );
+        }
+    }
+
+    fn filter_pass(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        pipeline: &RenderPipeline,
+        uniforms: &BloomUniforms,
+        source: &TextureView,
+        target: &TextureView,
+    ) {
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::bytes_of(uniforms),
+            usage: BufferUsage::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(source),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&self.sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Buffer(uniform_buffer.slice(..)),
+                },
+            ],
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[RenderPassColorAttachmentDescriptor {
+                attachment: target,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+/// Each mip is a separate texture so one can be sampled while the next is rendered to.
+fn create_mips(device: &Device, width: u32, height: u32) -> Vec<Mip> {
+    let mut mips = Vec::with_capacity(Bloom::MAX_MIP_COUNT);
+    let (mut width, mut height) = ((width / 2).max(1), (height / 2).max(1));
+    while mips.is_empty()
+        || (mips.len() < Bloom::MAX_MIP_COUNT && width.min(height) >= Bloom::MIN_MIP_SIZE)
+    {
+        let view = device
+            .create_texture(&TextureDescriptor {
+                label: None,
+                size: Extent3d {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: HDR_FORMAT,
+                usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::SAMPLED,
+            })
+            .create_view(&TextureViewDescriptor::default());
+        mips.push(Mip {
+            view,
+            width,
+            height,
+        });
+        width = (width / 2).max(1);
+        height = (height / 2).max(1);
+    }
+    mips
+}
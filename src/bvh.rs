@@ -0,0 +1,469 @@
+use crate::objects::Vertex;
+use ultraviolet::{Similarity3, Vec3};
+
+/// Primitives per leaf, below which nodes aren't split any further.
+const MAX_LEAF_SIZE: usize = 4;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn empty() -> Self {
+        Self {
+            min: Vec3::broadcast(f32::MAX),
+            max: Vec3::broadcast(f32::MIN),
+        }
+    }
+
+    pub fn from_points(points: &[Vec3]) -> Self {
+        points.iter().fold(Self::empty(), |aabb, &point| Self {
+            min: aabb.min.min_by_component(point),
+            max: aabb.max.max_by_component(point),
+        })
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.min_by_component(other.min),
+            max: self.max.max_by_component(other.max),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// The box around the transformed corners, which is looser than the transformed geometry's.
+    pub fn transformed(&self, transform: &Similarity3) -> Self {
+        let corners = (0..8)
+            .map(|i| {
+                transform.transform_vec(Vec3::new(
+                    if i & 1 == 0 { self.min.x } else { self.max.x },
+                    if i & 2 == 0 { self.min.y } else { self.max.y },
+                    if i & 4 == 0 { self.min.z } else { self.max.z },
+                ))
+            })
+            .collect::<Vec<Vec3>>();
+        Self::from_points(&corners)
+    }
+
+    /// Distance along the ray to where it enters the box, if it does before `max_t`. Slab test,
+    /// `inverse_direction` is one over each component of the direction.
+    fn intersect_ray(&self, origin: Vec3, inverse_direction: Vec3, max_t: f32) -> Option<f32> {
+        let t0 = (self.min - origin) * inverse_direction;
+        let t1 = (self.max - origin) * inverse_direction;
+        let near = t0.min_by_component(t1).component_max().max(0.0);
+        let far = t0.max_by_component(t1).component_min().min(max_t);
+        if near <= far {
+            Some(near)
+        } else {
+            None
+        }
+    }
+}
+
+/// Children of internal nodes are next to each other, leaves own a range of `Bvh::primitives`.
+#[derive(Copy, Clone)]
+struct Node {
+    bounds: Aabb,
+    /// The first child of internal nodes, the first primitive of leaves.
+    start: u32,
+    /// 0 for internal nodes.
+    count: u32,
+}
+
+/// Bounding volume hierarchy over any primitives with bounding boxes, split at the median along
+/// the longest axis of the primitives' centers.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    /// Primitive indices, ordered so each leaf's are contiguous.
+    primitives: Vec<u32>,
+}
+
+impl Bvh {
+    pub fn new(bounds: &[Aabb]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(bounds.len() * 2),
+            primitives: (0..bounds.len() as u32).collect(),
+        };
+        bvh.nodes.push(Node {
+            bounds: Aabb::empty(),
+            start: 0,
+            count: bounds.len() as u32,
+        });
+        bvh.split(0, bounds);
+        bvh
+    }
+
+    fn split(&mut self, node: usize, bounds: &[Aabb]) {
+        let Node { start, count, .. } = self.nodes[node];
+        let primitives = &mut self.primitives[start as usize..(start + count) as usize];
+        self.nodes[node].bounds = primitives
+            .iter()
+            .fold(Aabb::empty(), |aabb, &i| aabb.union(&bounds[i as usize]));
+        if primitives.len() <= MAX_LEAF_SIZE {
+            return;
+        }
+
+        let centers = Aabb::from_points(
+            &primitives
+                .iter()
+                .map(|&i| bounds[i as usize].center())
+                .collect::<Vec<Vec3>>(),
+        );
+        let extent = centers.max - centers.min;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+        let middle = primitives.len() / 2;
+        primitives.select_nth_unstable_by(middle, |&a, &b| {
+            let a = bounds[a as usize].center()[axis];
+            let b = bounds[b as usize].center()[axis];
+            a.total_cmp(&b)
+        });
+
+        let first_child = self.nodes.len();
+        self.nodes[node].start = first_child as u32;
+        self.nodes[node].count = 0;
+        self.nodes.push(Node {
+            bounds: Aabb::empty(),
+            start,
+            count: middle as u32,
+        });
+        self.nodes.push(Node {
+            bounds: Aabb::empty(),
+            start: start + middle as u32,
+            count: count - middle as u32,
+        });
+        self.split(first_child, bounds);
+        self.split(first_child + 1, bounds);
+    }
+
+    /// Updates the node bounds to the primitives' new `bounds`, keeping the tree as it is. Cheaper
+    /// than building a new one, but the tree gets worse the further primitives move.
+    pub fn refit(&mut self, bounds: &[Aabb]) {
+        if self.primitives.is_empty() {
+            return;
+        }
+        // Children come after their parents
+        for node in (0..self.nodes.len()).rev() {
+            let Node { start, count, .. } = self.nodes[node];
+            self.nodes[node].bounds = if count > 0 {
+                self.primitives[start as usize..(start + count) as usize]
+                    .iter()
+                    .fold(Aabb::empty(), |aabb, &i| aabb.union(&bounds[i as usize]))
+            } else {
+                let (first, second) = (start as usize, start as usize + 1);
+                self.nodes[first].bounds.union(&self.nodes[second].bounds)
+            };
+        }
+    }
+
+    /// Calls `intersect` with the primitives in the leaves the ray reaches before `max_t`, nearest
+    /// nodes first. `intersect` returns the distance to a hit, which leaves out anything farther.
+    /// Returns the nearest hit's primitive and the value `intersect` returned for it.
+    pub fn raycast<T>(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        mut max_t: f32,
+        mut intersect: impl FnMut(u32, f32) -> Option<(f32, T)>,
+    ) -> Option<(u32, T)> {
+        // Without primitives the root isn't a leaf, but has no children either
+        if self.primitives.is_empty() {
+            return None;
+        }
+        let inverse_direction = Vec3::one() / direction;
+        let mut nearest = None;
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let Node {
+                bounds,
+                start,
+                count,
+            } = self.nodes[node];
+            if bounds
+                .intersect_ray(origin, inverse_direction, max_t)
+                .is_none()
+            {
+                continue;
+            }
+            if count > 0 {
+                for &primitive in &self.primitives[start as usize..(start + count) as usize] {
+                    if let Some((t, hit)) = intersect(primitive, max_t) {
+                        max_t = t;
+                        nearest = Some((primitive, hit));
+                    }
+                }
+                continue;
+            }
+
+            // Pushes the farther child first, so the nearer one is visited first and shrinks
+            // `max_t` for the other
+            let (first, second) = (start as usize, start as usize + 1);
+            let distance = |child: usize| {
+                self.nodes[child]
+                    .bounds
+                    .intersect_ray(origin, inverse_direction, max_t)
+            };
+            match (distance(first), distance(second)) {
+                (Some(a), Some(b)) if a <= b => stack.extend_from_slice(&[second, first]),
+                (Some(_), Some(_)) => stack.extend_from_slice(&[first, second]),
+                (Some(_), None) => stack.push(first),
+                (None, Some(_)) => stack.push(second),
+                (None, None) => {}
+            }
+        }
+        nearest
+    }
+}
+
+/// Ray intersection of a triangle.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TriangleHit {
+    /// Index of the triangle in the most detailed level of detail.
+    pub triangle: u32,
+    /// Distance along the ray, in multiples of its direction.
+    pub t: f32,
+    /// Weights of the triangle's three vertices at the hit.
+    pub barycentrics: Vec3,
+}
+
+/// BVH over the triangles of a mesh's most detailed level of detail, with its own copy of the
+/// vertex positions.
+pub struct MeshBvh {
+    bvh: Bvh,
+    positions: Vec<Vec3>,
+    indices: Vec<u16>,
+    bounds: Aabb,
+}
+
+impl MeshBvh {
+    /// `indices` are the triangles of a single level of detail.
+    pub fn new(vertices: &[Vertex], indices: &[u16]) -> Self {
+        let positions = positions(vertices);
+        let bvh = Bvh::new(&triangle_bounds(&positions, indices));
+        let bounds = bvh.nodes[0].bounds;
+        Self {
+            bvh,
+            positions,
+            indices: indices.to_vec(),
+            bounds,
+        }
+    }
+
+    /// Moves the triangles to the new positions of `vertices`, which the indices still have to
+    /// be in range of.
+    pub fn refit(&mut self, vertices: &[Vertex]) {
+        self.positions = positions(vertices);
+        self.bvh
+            .refit(&triangle_bounds(&self.positions, &self.indices));
+        self.bounds = self.bvh.nodes[0].bounds;
+    }
+
+    /// Bounds of the triangles in the mesh's space.
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    /// The nearest triangle the ray hits before `max_t`, from either side.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_t: f32) -> Option<TriangleHit> {
+        self.bvh
+            .raycast(origin, direction, max_t, |triangle, max_t| {
+                let i = triangle as usize * 3;
+                intersect_triangle(
+                    origin,
+                    direction,
+                    [
+                        self.positions[self.indices[i] as usize],
+                        self.positions[self.indices[i + 1] as usize],
+                        self.positions[self.indices[i + 2] as usize],
+                    ],
+                )
+                .filter(|(t, _)| *t < max_t)
+                .map(|hit| (hit.0, hit))
+            })
+            .map(|(triangle, (t, barycentrics))| TriangleHit {
+                triangle,
+                t,
+                barycentrics,
+            })
+    }
+}
+
+fn positions(vertices: &[Vertex]) -> Vec<Vec3> {
+    vertices
+        .iter()
+        .map(|vertex| Vec3::from(vertex.position))
+        .collect()
+}
+
+fn triangle_bounds(positions: &[Vec3], indices: &[u16]) -> Vec<Aabb> {
+    indices
+        .chunks(3)
+        .map(|triangle| {
+            Aabb::from_points(&[
+                positions[triangle[0] as usize],
+                positions[triangle[1] as usize],
+                positions[triangle[2] as usize],
+            ])
+        })
+        .collect()
+}
+
+/// Möller-Trumbore intersection, returns the distance and barycentric coordinates of the hit.
+fn intersect_triangle(origin: Vec3, direction: Vec3, [a, b, c]: [Vec3; 3]) -> Option<(f32, Vec3)> {
+    let (ab, ac) = (b - a, c - a);
+    let p = direction.cross(ac);
+    let determinant = ab.dot(p);
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+    let inverse_determinant = 1.0 / determinant;
+    let to_origin = origin - a;
+    let u = to_origin.dot(p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = to_origin.cross(ab);
+    let v = direction.dot(q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = ac.dot(q) * inverse_determinant;
+    if t < 0.0 {
+        return None;
+    }
+    Some((t, Vec3::new(1.0 - u - v, u, v)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects;
+
+    /// Nearest hit of any of the triangles, without the BVH.
+    fn brute_force(
+        vertices: &[Vertex],
+        indices: &[u16],
+        origin: Vec3,
+        direction: Vec3,
+    ) -> Option<(u32, f32)> {
+        indices
+            .chunks(3)
+            .enumerate()
+            .filter_map(|(i, triangle)| {
+                let corner = |j: usize| Vec3::from(vertices[triangle[j] as usize].position);
+                intersect_triangle(origin, direction, [corner(0), corner(1), corner(2)])
+                    .map(|(t, _)| (i as u32, t))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    /// Rays from points around the mesh towards points inside of its bounds.
+    fn rays() -> impl Iterator<Item = (Vec3, Vec3)> {
+        let mut state = 0x2545_f491_u32;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32 * 2.0 - 1.0
+        };
+        (0..500).map(move |_| {
+            let origin = Vec3::new(random(), random(), random()) * 3.0;
+            let target = Vec3::new(random(), random(), random());
+            (origin, target - origin)
+        })
+    }
+
+    #[test]
+    fn raycast_matches_brute_force() {
+        let (vertices, indices) = objects::test_sphere(32, 16);
+        let bvh = MeshBvh::new(&vertices, &indices);
+        let mut hits = 0;
+        for (origin, direction) in rays() {
+            let hit = bvh.raycast(origin, direction, f32::INFINITY);
+            let expected = brute_force(&vertices, &indices, origin, direction);
+            match (hit, expected) {
+                (Some(hit), Some((_, t))) => {
+                    assert!(
+                        (hit.t - t).abs() < 1e-5,
+                        "hit at {} instead of {}",
+                        hit.t,
+                        t
+                    );
+                    hits += 1;
+                }
+                (None, None) => {}
+                _ => panic!("{:?} instead of {:?}", hit, expected),
+            }
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn raycast_respects_max_t() {
+        let vertex = |x: f32, z: f32| Vertex {
+            position: [x, 0.0, z],
+            normal: [0.0, 1.0, 0.0],
+            uv: [0.0, 0.0],
+        };
+        let vertices = [
+            vertex(-1.0, -1.0),
+            vertex(1.0, -1.0),
+            vertex(1.0, 1.0),
+            vertex(-1.0, 1.0),
+        ];
+        let bvh = MeshBvh::new(&vertices, &[0, 1, 2, 0, 2, 3]);
+        let (origin, direction) = (Vec3::new(0.1, 1.0, 0.1), Vec3::new(0.0, -1.0, 0.0));
+        assert!(bvh.raycast(origin, direction, 0.5).is_none());
+        let hit = bvh.raycast(origin, direction, 2.0).unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn raycast_without_primitives_misses() {
+        let (origin, direction) = (Vec3::zero(), Vec3::new(0.0, 0.0, 1.0));
+        let bvh = Bvh::new(&[]);
+        assert!(bvh
+            .raycast(origin, direction, f32::INFINITY, |_, _| Some((0.0, ())))
+            .is_none());
+        let mesh = MeshBvh::new(&[], &[]);
+        assert!(mesh.raycast(origin, direction, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    fn refit_matches_rebuild() {
+        let (vertices, indices) = objects::test_sphere(32, 16);
+        let mut bvh = MeshBvh::new(&vertices, &indices);
+        let moved = vertices
+            .iter()
+            .map(|vertex| {
+                let [x, y, z] = vertex.position;
+                Vertex {
+                    position: [x, y + 0.25 * (3.0 * x).sin() * z, z],
+                    ..*vertex
+                }
+            })
+            .collect::<Vec<Vertex>>();
+        bvh.refit(&moved);
+        let rebuilt = MeshBvh::new(&moved, &indices);
+        assert_eq!(bvh.bounds(), rebuilt.bounds());
+        for (origin, direction) in rays() {
+            let refit_t = bvh
+                .raycast(origin, direction, f32::INFINITY)
+                .map(|hit| hit.t);
+            let rebuilt_t = rebuilt
+                .raycast(origin, direction, f32::INFINITY)
+                .map(|hit| hit.t);
+            assert_eq!(refit_t, rebuilt_t);
+        }
+    }
+}
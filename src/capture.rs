@@ -0,0 +1,441 @@
+use crate::postprocess::{create_fullscreen_pipeline, HDR_FORMAT};
+use exr::prelude::f16;
+use std::error::Error;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread;
+use wgpu::*;
+
+/// Frames waiting for the writer thread, beyond which `write_pending` blocks.
+const MAX_QUEUED_CAPTURES: usize = 4;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CaptureFormat {
+    /// The final tonemapped frame in 8-bit sRGB.
+    Png,
+    /// The linear HDR scene before exposure, bloom and tonemapping, as 16-bit floats.
+    Exr,
+}
+
+impl CaptureFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Exr => "exr",
+        }
+    }
+
+    fn texture_format(self, ldr_format: TextureFormat) -> TextureFormat {
+        match self {
+            Self::Png => ldr_format,
+            Self::Exr => HDR_FORMAT,
+        }
+    }
+}
+
+struct Recording {
+    directory: PathBuf,
+    format: CaptureFormat,
+    frame: u32,
+    frame_count: u32,
+}
+
+/// Resolves once a capture's buffer is mapped.
+type Mapping = Pin<Box<dyn Future<Output = Result<(), BufferAsyncError>> + Send>>;
+
+/// A frame copied into a buffer, waiting for the GPU to finish before it can be written.
+struct PendingCapture {
+    buffer: Buffer,
+    path: PathBuf,
+    format: CaptureFormat,
+    texture_format: TextureFormat,
+    width: u32,
+    height: u32,
+    padded_bytes_per_row: u32,
+}
+
+/// Copies rendered frames into buffers and writes them to image files on a writer thread, either
+/// single screenshots or numbered sequences of consecutive frames.
+pub struct FrameCapture {
+    ldr_format: TextureFormat,
+    width: u32,
+    height: u32,
+    /// Copyable textures for each format, the swapchain can't be copied from.
+    ldr_texture: Texture,
+    ldr_view: TextureView,
+    hdr_texture: Texture,
+    hdr_view: TextureView,
+    sampler: Sampler,
+    bind_group_layout: BindGroupLayout,
+    ldr_blit_pipeline: RenderPipeline,
+    hdr_blit_pipeline: RenderPipeline,
+
+    requests: Vec<(PathBuf, CaptureFormat)>,
+    recording: Option<Recording>,
+    pending: Vec<PendingCapture>,
+    writer: SyncSender<(PendingCapture, Mapping)>,
+}
+
+impl FrameCapture {
+    pub fn new(device: &Device, ldr_format: TextureFormat, width: u32, height: u32) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::SampledTexture {
+                        dimension: TextureViewDimension::D2,
+                        component_type: TextureComponentType::Float,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Sampler { comparison: false },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let blit_shader = device.create_shader_module(include_spirv!("../shaders/blit.spv"));
+        let ldr_blit_pipeline = create_fullscreen_pipeline(
+            device,
+            &pipeline_layout,
+            &blit_shader,
+            ldr_format,
+            BlendDescriptor::REPLACE,
+            1,
+        );
+        let hdr_blit_pipeline = create_fullscreen_pipeline(
+            device,
+            &pipeline_layout,
+            &blit_shader,
+            HDR_FORMAT,
+            BlendDescriptor::REPLACE,
+            1,
+        );
+        let (ldr_texture, ldr_view) = create_texture(device, ldr_format, width, height);
+        let (hdr_texture, hdr_view) = create_texture(device, HDR_FORMAT, width, height);
+
+        let (writer, captures) =
+            mpsc::sync_channel::<(PendingCapture, Mapping)>(MAX_QUEUED_CAPTURES);
+        thread::spawn(move || {
+            for (capture, mapping) in captures {
+                if let Err(error) = capture.write(mapping) {
+                    log::error!("Failed to write {}: {}", capture.path.display(), error);
+                }
+            }
+        });
+
+        Self {
+            ldr_format,
+            width,
+            height,
+            ldr_texture,
+            ldr_view,
+            hdr_texture,
+            hdr_view,
+            sampler: device.create_sampler(&SamplerDescriptor::default()),
+            bind_group_layout,
+            ldr_blit_pipeline,
+            hdr_blit_pipeline,
+
+            requests: Vec::new(),
+            recording: None,
+            pending: Vec::new(),
+            writer,
+        }
+    }
+
+    pub fn set_screen_size(&mut self, device: &Device, width: u32, height: u32) {
+        let (ldr_texture, ldr_view) = create_texture(device, self.ldr_format, width, height);
+        let (hdr_texture, hdr_view) = create_texture(device, HDR_FORMAT, width, height);
+        self.ldr_texture = ldr_texture;
+        self.ldr_view = ldr_view;
+        self.hdr_texture = hdr_texture;
+        self.hdr_view = hdr_view;
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Captures the next rendered frame to `path`.
+    pub fn capture(&mut self, path: PathBuf, format: CaptureFormat) {
+        self.requests.push((path, format));
+    }
+
+    /// Captures the next `frame_count` frames into `directory` as frame_00000, frame_00001...
+    /// Replaces any recording in progress, unless `frame_count` is 0 which does nothing.
+    pub fn record(&mut self, directory: PathBuf, frame_count: u32, format: CaptureFormat) {
+        if frame_count == 0 {
+            return;
+        }
+        self.recording = Some(Recording {
+            directory,
+            format,
+            frame: 0,
+            frame_count,
+        });
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Takes the captures requested for the frame about to be rendered. If any of them is a
+    /// PNG, the frame has to be rendered into `ldr_target` instead of the swapchain.
+    pub fn begin_frame(&mut self) -> Vec<(PathBuf, CaptureFormat)> {
+        let mut captures = self.requests.split_off(0);
+        if let Some(recording) = &mut self.recording {
+            captures.push((
+                recording.directory.join(format!(
+                    "frame_{:05}.{}",
+                    recording.frame,
+                    recording.format.extension()
+                )),
+                recording.format,
+            ));
+            recording.frame += 1;
+            if recording.frame >= recording.frame_count {
+                self.recording = None;
+            }
+        }
+        captures
+    }
+
+    pub fn ldr_target(&self) -> &TextureView {
+        &self.ldr_view
+    }
+
+    /// Records copies of the frame for each of `captures`, and the copy of the frame rendered
+    /// into `ldr_target` to the swapchain if needed.
+    pub fn copy_frame(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        captures: Vec<(PathBuf, CaptureFormat)>,
+        hdr_scene: &TextureView,
+        render_target: &TextureView,
+    ) {
+        let has_format = |format| captures.iter().any(|(_, f)| *f == format);
+        if has_format(CaptureFormat::Png) {
+            self.blit(
+                device,
+                encoder,
+                &self.ldr_blit_pipeline,
+                &self.ldr_view,
+                render_target,
+            );
+        }
+        if has_format(CaptureFormat::Exr) {
+            self.blit(
+                device,
+                encoder,
+                &self.hdr_blit_pipeline,
+                hdr_scene,
+                &self.hdr_view,
+            );
+        }
+
+        for (path, format) in captures {
+            let texture_format = format.texture_format(self.ldr_format);
+            let bytes_per_row = self.width * bytes_per_pixel(texture_format);
+            let padded_bytes_per_row =
+                bytes_per_row.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT) * COPY_BYTES_PER_ROW_ALIGNMENT;
+            let buffer = device.create_buffer(&BufferDescriptor {
+                label: None,
+                size: (padded_bytes_per_row * self.height) as BufferAddress,
+                usage: BufferUsage::MAP_READ | BufferUsage::COPY_DST,
+                mapped_at_creation: false,
+            });
+            encoder.copy_texture_to_buffer(
+                TextureCopyView {
+                    texture: match format {
+                        CaptureFormat::Png => &self.ldr_texture,
+                        CaptureFormat::Exr => &self.hdr_texture,
+                    },
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                },
+                BufferCopyView {
+                    buffer: &buffer,
+                    layout: TextureDataLayout {
+                        offset: 0,
+                        bytes_per_row: padded_bytes_per_row,
+                        rows_per_image: 0,
+                    },
+                },
+                Extent3d {
+                    width: self.width,
+                    height: self.height,
+                    depth: 1,
+                },
+            );
+            self.pending.push(PendingCapture {
+                buffer,
+                path,
+                format,
+                texture_format,
+                width: self.width,
+                height: self.height,
+                padded_bytes_per_row,
+            });
+        }
+    }
+
+    /// Starts writing the frames copied so far, has to be called after the command buffer with
+    /// the copies was submitted. The files are written in order on the writer thread once the GPU
+    /// is done, if it falls behind this waits until it catches up.
+    pub fn write_pending(&mut self, device: &Device) {
+        for capture in self.pending.drain(..) {
+            let mapping: Mapping = Box::pin(capture.buffer.slice(..).map_async(MapMode::Read));
+            // The writer only goes away with the renderer
+            if let Err(TrySendError::Full(capture)) = self.writer.try_send((capture, mapping)) {
+                // Buffers are only mapped when the device is polled, so the writer could wait
+                // on the queued ones forever without this
+                device.poll(Maintain::Wait);
+                let _ = self.writer.send(capture);
+            }
+        }
+    }
+
+    fn blit(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        pipeline: &RenderPipeline,
+        source: &TextureView,
+        target: &TextureView,
+    ) {
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(source),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[RenderPassColorAttachmentDescriptor {
+                attachment: target,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+impl PendingCapture {
+    /// Waits for `mapping`, which resolves when the device is next polled or submitted to after
+    /// the copy finished.
+    fn write(&self, mapping: Mapping) -> Result<(), Box<dyn Error>> {
+        pollster::block_on(mapping)?;
+        let slice = self.buffer.slice(..);
+        let data = slice.get_mapped_range();
+        let bytes_per_row = (self.width * bytes_per_pixel(self.texture_format)) as usize;
+        let rows = data
+            .chunks(self.padded_bytes_per_row as usize)
+            .map(|row| &row[..bytes_per_row]);
+        if let Some(directory) = self.path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+
+        match self.format {
+            CaptureFormat::Png => {
+                let swap_red_blue = matches!(
+                    self.texture_format,
+                    TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb
+                );
+                let mut pixels = Vec::with_capacity(bytes_per_row * self.height as usize);
+                for row in rows {
+                    for pixel in row.chunks(4) {
+                        if swap_red_blue {
+                            pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+                        } else {
+                            pixels.extend_from_slice(pixel);
+                        }
+                    }
+                }
+                image::save_buffer(
+                    &self.path,
+                    &pixels,
+                    self.width,
+                    self.height,
+                    image::ColorType::Rgba8,
+                )?;
+            }
+            CaptureFormat::Exr => {
+                let samples = rows
+                    .flat_map(|row| row.chunks(2))
+                    .map(|bytes| f16::from_bits(u16::from_le_bytes([bytes[0], bytes[1]])))
+                    .collect::<Vec<f16>>();
+                write_exr(
+                    &self.path,
+                    &samples,
+                    self.width as usize,
+                    self.height as usize,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_exr(path: &Path, samples: &[f16], width: usize, height: usize) -> exr::error::Result<()> {
+    exr::prelude::write_rgba_file(path, width, height, |x, y| {
+        let i = (y * width + x) * 4;
+        (samples[i], samples[i + 1], samples[i + 2], samples[i + 3])
+    })
+}
+
+fn bytes_per_pixel(format: TextureFormat) -> u32 {
+    match format {
+        HDR_FORMAT => 8,
+        _ => 4,
+    }
+}
+
+fn create_texture(
+    device: &Device,
+    format: TextureFormat,
+    width: u32,
+    height: u32,
+) -> (Texture, TextureView) {
+    let texture = device.create_texture(&TextureDescriptor {
+        label: None,
+        size: Extent3d {
+            width,
+            height,
+            depth: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format,
+        usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::SAMPLED | TextureUsage::COPY_SRC,
+    });
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    (texture, view)
+}
@@ -0,0 +1,44 @@
+use crate::gltf_loader;
+use crate::mesh_file;
+use crate::objects::Geometry;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+const USAGE: &str =
+    "usage: meshweaver convert <input.obj|input.gltf|input.glb> <texture.dds> <output.mwm>";
+
+/// Entry point of `meshweaver convert`, which precompiles a mesh into the binary mesh format.
+/// The texture path is stored as given, relative to the output file.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (input_path, texture_path, output_path) = match args {
+        [input, texture, output] => (Path::new(input), texture, Path::new(output)),
+        _ => return Err(USAGE.into()),
+    };
+
+    let extension = input_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    let geometry = match extension.as_deref() {
+        Some("obj") => Geometry::from_obj(BufReader::new(File::open(input_path)?), true),
+        Some("gltf") | Some("glb") => {
+            let (vertices, indices) = gltf_loader::load_gltf(input_path)?;
+            Geometry::from_vertices_and_indices(vertices, indices, true)
+        }
+        _ => return Err(USAGE.into()),
+    };
+    if let Some(stats) = geometry.optimization_stats {
+        log::info!("{}", stats);
+    }
+    log::info!(
+        "{} levels of detail: {:?}",
+        geometry.lods.len(),
+        geometry.lods
+    );
+
+    let mut output = BufWriter::new(File::create(output_path)?);
+    mesh_file::write(&mut output, &geometry, texture_path)?;
+    Ok(())
+}
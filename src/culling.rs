@@ -0,0 +1,318 @@
+use crate::objects::{Mesh, Vertex};
+use bytemuck::{Pod, Zeroable};
+use std::mem;
+use ultraviolet::{Mat4, Similarity3, Vec3, Vec4};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CullingMode {
+    /// Instances are tested with rayon and drawn with `draw_indexed`.
+    Cpu,
+    /// Instances are tested in a compute pass and drawn with `draw_indexed_indirect`.
+    Gpu,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Bounds {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl Bounds {
+    pub fn from_vertices(vertices: &[Vertex]) -> Self {
+        if vertices.is_empty() {
+            return Self {
+                center: Vec3::zero(),
+                radius: 0.0,
+            };
+        }
+
+        let (min, max) = vertices.iter().fold(
+            (Vec3::broadcast(f32::MAX), Vec3::broadcast(f32::MIN)),
+            |(min, max), vertex| {
+                let position = Vec3::from(vertex.position);
+                (
+                    min.min_by_component(position),
+                    max.max_by_component(position),
+                )
+            },
+        );
+
+        let center = (min + max) * 0.5;
+        let radius = vertices
+            .iter()
+            .map(|vertex| (Vec3::from(vertex.position) - center).mag())
+            .fold(0.0, f32::max);
+        Self { center, radius }
+    }
+
+    /// Returns the bounding sphere (center, radius) after applying an instance transform.
+    pub fn transformed_sphere(&self, transform: &Similarity3) -> (Vec3, f32) {
+        (
+            transform.transform_vec(self.center),
+            self.radius * transform.scale.abs(),
+        )
+    }
+}
+
+pub struct Frustum {
+    /// Left, right, bottom, top, near, far. Normals point inwards.
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the frustum planes from a view projection matrix with a 0..1 depth range.
+    pub fn from_view_projection(view_projection: Mat4) -> Self {
+        let row = |i: usize| {
+            Vec4::new(
+                view_projection.cols[0][i],
+                view_projection.cols[1][i],
+                view_projection.cols[2][i],
+                view_projection.cols[3][i],
+            )
+        };
+        let (row0, row1, row2, row3) = (row(0), row(1), row(2), row(3));
+        let normalize = |plane: Vec4| plane / plane.xyz().mag();
+        Self {
+            planes: [
+                normalize(row3 + row0),
+                normalize(row3 - row0),
+                normalize(row3 + row1),
+                normalize(row3 - row1),
+                normalize(row2),
+                normalize(row3 - row2),
+            ],
+        }
+    }
+
+    pub fn planes(&self) -> &[Vec4; 6] {
+        &self.planes
+    }
+
+    pub fn intersects_sphere(&self, center: Vec3, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.xyz().dot(center) + plane.w >= -radius)
+    }
+}
+
+/// Picks an instance's level of detail from the fraction of the screen height its bounding
+/// sphere covers. Keep in sync with `select_lod` in `cull.comp`.
+pub struct LodSelector {
+    camera_position: Vec3,
+    projection_scale: f32,
+}
+
+impl LodSelector {
+    /// Instances covering at least this much of the screen are drawn at full detail, and every
+    /// time the coverage halves the next level of detail is used.
+    const FULL_DETAIL_COVERAGE: f32 = 0.5;
+
+    pub fn new(camera_position: Vec3, projection_matrix: Mat4) -> Self {
+        Self {
+            camera_position,
+            projection_scale: projection_matrix.cols[1].y.abs(),
+        }
+    }
+
+    pub fn select(&self, center: Vec3, radius: f32, lod_count: usize) -> usize {
+        let distance = (center - self.camera_position).mag();
+        if distance <= radius {
+            return 0;
+        }
+        let coverage = radius * self.projection_scale / distance;
+        let lod = (Self::FULL_DETAIL_COVERAGE / coverage).log2().max(0.0) as usize;
+        lod.min(lod_count - 1)
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct CullUniforms {
+    frustum_planes: [[f32; 4]; 6],
+    bounding_sphere: [f32; 4],
+    /// xyz is the camera position, w the projection's vertical scale.
+    camera: [f32; 4],
+    instance_count: u32,
+    lod_count: u32,
+    _padding: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct DrawIndexedIndirectArgs {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+/// Output of culling a mesh on the GPU, consumed by `RenderPass::draw_indexed_indirect`. The
+/// visible instances of each level of detail are stored starting at `lod * instance_count`.
+pub struct GpuCulledInstances {
+    pub visible_instances_buffer: Buffer,
+    pub draw_args_buffer: Buffer,
+}
+
+pub struct GpuCulling {
+    bind_group_layout: BindGroupLayout,
+    pipeline: ComputePipeline,
+}
+
+impl GpuCulling {
+    const WORKGROUP_SIZE: u32 = 64;
+
+    pub fn new(device: &Device) -> Self {
+        let storage_buffer_entry = |binding, readonly| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStage::COMPUTE,
+            ty: BindingType::StorageBuffer {
+                dynamic: false,
+                min_binding_size: None,
+                readonly,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStage::COMPUTE,
+                    ty: BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_buffer_entry(1, true),
+                storage_buffer_entry(2, false),
+                storage_buffer_entry(3, false),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            compute_stage: ProgrammableStageDescriptor {
+                module: &device.create_shader_module(include_spirv!("../shaders/cull.spv")),
+                entry_point: "main",
+            },
+        });
+
+        Self {
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    /// Size in bytes of the indirect draw arguments of one level of detail.
+    pub const DRAW_ARGS_STRIDE: BufferAddress = mem::size_of::<DrawIndexedIndirectArgs>() as _;
+
+    /// Records a compute pass that writes the indices of the visible instances of `mesh`
+    /// and the indirect draw arguments for each of its levels of detail.
+    pub fn cull(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        mesh: &Mesh,
+        frustum: &Frustum,
+        lod_selector: &LodSelector,
+        transforms_buffer: &Buffer,
+    ) -> GpuCulledInstances {
+        let instance_count = mesh.instances.len() as u32;
+        let bounds = mesh.bounds();
+        let mut frustum_planes = [[0.0; 4]; 6];
+        for (dst, plane) in frustum_planes.iter_mut().zip(frustum.planes()) {
+            *dst = [plane.x, plane.y, plane.z, plane.w];
+        }
+        let uniforms = CullUniforms {
+            frustum_planes,
+            bounding_sphere: [
+                bounds.center.x,
+                bounds.center.y,
+                bounds.center.z,
+                bounds.radius,
+            ],
+            camera: [
+                lod_selector.camera_position.x,
+                lod_selector.camera_position.y,
+                lod_selector.camera_position.z,
+                lod_selector.projection_scale,
+            ],
+            instance_count,
+            lod_count: mesh.lods().len() as u32,
+            _padding: [0; 2],
+        };
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::bytes_of(&uniforms),
+            usage: BufferUsage::UNIFORM,
+        });
+        let visible_instances_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: (mesh.lods().len() * instance_count as usize * mem::size_of::<u32>())
+                as BufferAddress,
+            usage: BufferUsage::STORAGE,
+            mapped_at_creation: false,
+        });
+        let draw_args = mesh
+            .lods()
+            .iter()
+            .enumerate()
+            .map(|(lod, indices)| DrawIndexedIndirectArgs {
+                index_count: indices.end - indices.start,
+                instance_count: 0,
+                first_index: indices.start,
+                base_vertex: 0,
+                first_instance: lod as u32 * instance_count,
+            })
+            .collect::<Vec<DrawIndexedIndirectArgs>>();
+        let draw_args_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&draw_args),
+            usage: BufferUsage::STORAGE | BufferUsage::INDIRECT,
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Buffer(uniform_buffer.slice(..)),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Buffer(transforms_buffer.slice(..)),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Buffer(visible_instances_buffer.slice(..)),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Buffer(draw_args_buffer.slice(..)),
+                },
+            ],
+        });
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass();
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.dispatch(instance_count.div_ceil(Self::WORKGROUP_SIZE), 1, 1);
+        }
+
+        GpuCulledInstances {
+            visible_instances_buffer,
+            draw_args_buffer,
+        }
+    }
+}
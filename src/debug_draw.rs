@@ -0,0 +1,225 @@
+use crate::postprocess::HDR_FORMAT;
+use bytemuck::{Pod, Zeroable};
+use std::f32::consts::TAU;
+use std::mem;
+use ultraviolet::{Mat4, Similarity3, Vec3, Vec4};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct LineVertex {
+    position: Vec3,
+    color: Vec3,
+}
+
+/// Immediate mode line drawing for debugging, the lines queued during a frame are drawn into the
+/// scene by the next `Renderer::render` and then cleared. Colors are linear HDR values.
+pub struct DebugDraw {
+    /// Whether lines are hidden behind the meshes in front of them.
+    pub depth_test: bool,
+    vertices: Vec<LineVertex>,
+    vertex_buffer: Option<(Buffer, u32)>,
+
+    pipeline_layout: PipelineLayout,
+    pipeline: RenderPipeline,
+    overlay_pipeline: RenderPipeline,
+}
+
+impl DebugDraw {
+    const CIRCLE_SEGMENTS: usize = 32;
+
+    pub fn new(
+        device: &Device,
+        camera_bind_group_layout: &BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        Self {
+            depth_test: true,
+            vertices: Vec::new(),
+            vertex_buffer: None,
+            pipeline: create_line_pipeline(device, &pipeline_layout, sample_count, true),
+            overlay_pipeline: create_line_pipeline(device, &pipeline_layout, sample_count, false),
+            pipeline_layout,
+        }
+    }
+
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        self.pipeline = create_line_pipeline(device, &self.pipeline_layout, sample_count, true);
+        self.overlay_pipeline =
+            create_line_pipeline(device, &self.pipeline_layout, sample_count, false);
+    }
+
+    pub fn line(&mut self, start: Vec3, end: Vec3, color: Vec3) {
+        self.vertices.extend_from_slice(&[
+            LineVertex {
+                position: start,
+                color,
+            },
+            LineVertex {
+                position: end,
+                color,
+            },
+        ]);
+    }
+
+    pub fn aabb(&mut self, min: Vec3, max: Vec3, color: Vec3) {
+        let corner = |i: usize| {
+            Vec3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+        self.box_edges([0, 1, 2, 3, 4, 5, 6, 7].map(corner), color);
+    }
+
+    /// Three circles around the axes.
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: Vec3) {
+        let axes = [Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z()];
+        for i in 0..3 {
+            let (u, v) = (axes[(i + 1) % 3] * radius, axes[(i + 2) % 3] * radius);
+            let point = |segment: usize| {
+                let angle = segment as f32 / Self::CIRCLE_SEGMENTS as f32 * TAU;
+                center + u * angle.cos() + v * angle.sin()
+            };
+            for segment in 0..Self::CIRCLE_SEGMENTS {
+                self.line(point(segment), point(segment + 1), color);
+            }
+        }
+    }
+
+    /// The transform's X, Y and Z axes in red, green and blue, `length` long before scaling.
+    pub fn axes(&mut self, transform: &Similarity3, length: f32) {
+        let origin = transform.translation;
+        for (axis, color) in [
+            (Vec3::unit_x(), Vec3::unit_x()),
+            (Vec3::unit_y(), Vec3::unit_y()),
+            (Vec3::unit_z(), Vec3::unit_z()),
+        ]
+        .iter()
+        {
+            self.line(origin, transform.transform_vec(*axis * length), *color);
+        }
+    }
+
+    /// The volume a view projection matrix with a 0..1 depth range maps to the screen.
+    pub fn frustum(&mut self, view_projection: Mat4, color: Vec3) {
+        let inverse = view_projection.inversed();
+        let corner = |i: usize| {
+            let corner = inverse
+                * Vec4::new(
+                    if i & 1 == 0 { -1.0 } else { 1.0 },
+                    if i & 2 == 0 { -1.0 } else { 1.0 },
+                    if i & 4 == 0 { 0.0 } else { 1.0 },
+                    1.0,
+                );
+            corner.xyz() / corner.w
+        };
+        self.box_edges([0, 1, 2, 3, 4, 5, 6, 7].map(corner), color);
+    }
+
+    /// Corners are indexed by their X, Y and Z in the first, second and third bit.
+    fn box_edges(&mut self, corners: [Vec3; 8], color: Vec3) {
+        for i in 0..8 {
+            for bit in &[1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corners[i], corners[i | bit], color);
+                }
+            }
+        }
+    }
+
+    /// Uploads the lines queued since the last call and clears them. Returns whether there is
+    /// anything to draw.
+    pub fn prepare(&mut self, device: &Device) -> bool {
+        self.vertex_buffer = if self.vertices.is_empty() {
+            None
+        } else {
+            Some((
+                device.create_buffer_init(&BufferInitDescriptor {
+                    label: None,
+                    contents: bytemuck::cast_slice(&self.vertices),
+                    usage: BufferUsage::VERTEX,
+                }),
+                self.vertices.len() as u32,
+            ))
+        };
+        self.vertices.clear();
+        self.vertex_buffer.is_some()
+    }
+
+    /// Draws the lines uploaded by `prepare`, the caller binds the camera at group 0.
+    pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        if let Some((vertex_buffer, vertex_count)) = &self.vertex_buffer {
+            render_pass.set_pipeline(if self.depth_test {
+                &self.pipeline
+            } else {
+                &self.overlay_pipeline
+            });
+            render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+            render_pass.draw(0..*vertex_count, 0..1);
+        }
+    }
+}
+
+fn create_line_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    sample_count: u32,
+    depth_test: bool,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        vertex_stage: ProgrammableStageDescriptor {
+            module: &device.create_shader_module(include_spirv!("../shaders/debug_line.spv")),
+            entry_point: "main",
+        },
+        fragment_stage: Some(ProgrammableStageDescriptor {
+            module: &device.create_shader_module(include_spirv!("../shaders/vertex_color.spv")),
+            entry_point: "main",
+        }),
+        rasterization_state: Some(RasterizationStateDescriptor {
+            front_face: FrontFace::Ccw,
+            cull_mode: CullMode::None,
+            clamp_depth: false,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+            depth_bias_clamp: 0.0,
+        }),
+        primitive_topology: PrimitiveTopology::LineList,
+        color_states: &[ColorStateDescriptor {
+            format: HDR_FORMAT,
+            alpha_blend: BlendDescriptor::REPLACE,
+            color_blend: BlendDescriptor::REPLACE,
+            write_mask: ColorWrite::ALL,
+        }],
+        depth_stencil_state: Some(DepthStencilStateDescriptor {
+            format: TextureFormat::Depth32Float,
+            depth_write_enabled: false,
+            depth_compare: if depth_test {
+                CompareFunction::Less
+            } else {
+                CompareFunction::Always
+            },
+            stencil: StencilStateDescriptor::default(),
+        }),
+        vertex_state: VertexStateDescriptor {
+            index_format: IndexFormat::Uint16,
+            vertex_buffers: &[VertexBufferDescriptor {
+                stride: mem::size_of::<LineVertex>() as BufferAddress,
+                step_mode: InputStepMode::Vertex,
+                attributes: &vertex_attr_array![0 => Float3, 1 => Float3],
+            }],
+        },
+        sample_count,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: false,
+    })
+}
@@ -0,0 +1,282 @@
+use crate::objects::{SkinVertex, Vertex};
+use crate::postprocess::{create_fullscreen_pipeline, HDR_FORMAT};
+use std::mem;
+use wgpu::*;
+
+const ALBEDO_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+const NORMAL_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+/// Roughness and specular reflectance.
+const MATERIAL_FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+
+/// Deferred shading, the opaque meshes are drawn into a G-buffer holding their albedo, normal,
+/// material and depth, which a fullscreen pass then lights. It doesn't support multisampling.
+pub struct Deferred {
+    albedo_texture: TextureView,
+    normal_texture: TextureView,
+    material_texture: TextureView,
+    depth_texture: TextureView,
+    sampler: Sampler,
+
+    geometry_pipeline: RenderPipeline,
+    gbuffer_bind_group_layout: BindGroupLayout,
+    lighting_pipeline: RenderPipeline,
+}
+
+impl Deferred {
+    /// Takes the bind group layouts of the forward pipeline, the camera, instances, lights and
+    /// environment. The lighting pass binds the G-buffer in place of the instances.
+    pub fn new(
+        device: &Device,
+        bind_group_layouts: [&BindGroupLayout; 4],
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let [camera_bind_group_layout, instances_bind_group_layout, lights_bind_group_layout, environment_bind_group_layout] =
+            bind_group_layouts;
+        let geometry_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[camera_bind_group_layout, instances_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let color_state = |format| ColorStateDescriptor {
+            format,
+            alpha_blend: BlendDescriptor::REPLACE,
+            color_blend: BlendDescriptor::REPLACE,
+            write_mask: ColorWrite::ALL,
+        };
+        let geometry_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&geometry_pipeline_layout),
+            vertex_stage: ProgrammableStageDescriptor {
+                module: &device.create_shader_module(include_spirv!("../shaders/vert.spv")),
+                entry_point: "main",
+            },
+            fragment_stage: Some(ProgrammableStageDescriptor {
+                module: &device.create_shader_module(include_spirv!("../shaders/gbuffer.spv")),
+                entry_point: "main",
+            }),
+            rasterization_state: Some(RasterizationStateDescriptor {
+                front_face: FrontFace::Ccw,
+                cull_mode: CullMode::Back,
+                clamp_depth: false,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: PrimitiveTopology::TriangleList,
+            color_states: &[
+                color_state(ALBEDO_FORMAT),
+                color_state(NORMAL_FORMAT),
+                color_state(MATERIAL_FORMAT),
+            ],
+            depth_stencil_state: Some(DepthStencilStateDescriptor {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Less,
+                stencil: StencilStateDescriptor::default(),
+            }),
+            vertex_state: VertexStateDescriptor {
+                index_format: IndexFormat::Uint16,
+                vertex_buffers: &[
+                    VertexBufferDescriptor {
+                        stride: mem::size_of::<Vertex>() as BufferAddress,
+                        step_mode: InputStepMode::Vertex,
+                        attributes: &vertex_attr_array![0 => Float3, 1 => Float3, 2 => Float3],
+                    },
+                    VertexBufferDescriptor {
+                        stride: mem::size_of::<SkinVertex>() as BufferAddress,
+                        step_mode: InputStepMode::Vertex,
+                        attributes: &vertex_attr_array![3 => Uint4, 4 => Float4],
+                    },
+                ],
+            },
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+
+        let texture_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStage::FRAGMENT,
+            ty: BindingType::SampledTexture {
+                dimension: TextureViewDimension::D2,
+                component_type: TextureComponentType::Float,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let gbuffer_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    texture_entry(0),
+                    texture_entry(1),
+                    texture_entry(2),
+                    texture_entry(3),
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStage::FRAGMENT,
+                        ty: BindingType::Sampler { comparison: false },
+                        count: None,
+                    },
+                ],
+            });
+        let lighting_pipeline = create_fullscreen_pipeline(
+            device,
+            &device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[
+                    camera_bind_group_layout,
+                    &gbuffer_bind_group_layout,
+                    lights_bind_group_layout,
+                    environment_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            }),
+            &device.create_shader_module(include_spirv!("../shaders/deferred_lighting.spv")),
+            HDR_FORMAT,
+            BlendDescriptor::REPLACE,
+            1,
+        );
+
+        Self {
+            albedo_texture: create_texture(device, ALBEDO_FORMAT, width, height),
+            normal_texture: create_texture(device, NORMAL_FORMAT, width, height),
+            material_texture: create_texture(device, MATERIAL_FORMAT, width, height),
+            depth_texture: create_texture(device, TextureFormat::Depth32Float, width, height),
+            sampler: device.create_sampler(&SamplerDescriptor {
+                label: None,
+                address_mode_u: AddressMode::ClampToEdge,
+                address_mode_v: AddressMode::ClampToEdge,
+                address_mode_w: AddressMode::ClampToEdge,
+                mag_filter: FilterMode::Nearest,
+                min_filter: FilterMode::Nearest,
+                mipmap_filter: FilterMode::Nearest,
+                lod_min_clamp: 0.0,
+                lod_max_clamp: f32::MAX,
+                compare: None,
+                anisotropy_clamp: None,
+            }),
+
+            geometry_pipeline,
+            gbuffer_bind_group_layout,
+            lighting_pipeline,
+        }
+    }
+
+    /// Depth of the opaque meshes, for drawing blended ones on top after lighting.
+    pub fn depth_texture(&self) -> &TextureView {
+        &self.depth_texture
+    }
+
+    pub fn set_screen_size(&mut self, device: &Device, width: u32, height: u32) {
+        self.albedo_texture = create_texture(device, ALBEDO_FORMAT, width, height);
+        self.normal_texture = create_texture(device, NORMAL_FORMAT, width, height);
+        self.material_texture = create_texture(device, MATERIAL_FORMAT, width, height);
+        self.depth_texture = create_texture(device, TextureFormat::Depth32Float, width, height);
+    }
+
+    /// Starts filling the G-buffer, the caller binds the camera at group 0 and each mesh's
+    /// instances at group 1 before drawing it.
+    pub fn begin_geometry_pass<'a>(&'a self, encoder: &'a mut CommandEncoder) -> RenderPass<'a> {
+        let color_attachment = |attachment| RenderPassColorAttachmentDescriptor {
+            attachment,
+            resolve_target: None,
+            ops: Operations {
+                load: LoadOp::Clear(Color::TRANSPARENT),
+                store: true,
+            },
+        };
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[
+                color_attachment(&self.albedo_texture),
+                color_attachment(&self.normal_texture),
+                color_attachment(&self.material_texture),
+            ],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachmentDescriptor {
+                attachment: &self.depth_texture,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.geometry_pipeline);
+        render_pass
+    }
+
+    /// Lights the G-buffer into `target`, leaving the pixels no mesh covers untouched.
+    pub fn render_lighting(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        camera_bind_group: &BindGroup,
+        lights_bind_group: &BindGroup,
+        environment_bind_group: &BindGroup,
+        target: &TextureView,
+    ) {
+        let gbuffer_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.gbuffer_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&self.albedo_texture),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&self.normal_texture),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&self.material_texture),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&self.depth_texture),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[RenderPassColorAttachmentDescriptor {
+                attachment: target,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.lighting_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &gbuffer_bind_group, &[]);
+        render_pass.set_bind_group(2, lights_bind_group, &[]);
+        render_pass.set_bind_group(3, environment_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_texture(device: &Device, format: TextureFormat, width: u32, height: u32) -> TextureView {
+    device
+        .create_texture(&TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::SAMPLED,
+        })
+        .create_view(&TextureViewDescriptor::default())
+}
@@ -0,0 +1,226 @@
+use crate::objects::{Mesh, Vertex};
+use std::mem;
+use std::ops::Range;
+use wgpu::*;
+
+/// How much room to make when the geometry outgrows its buffers, so geometry that keeps growing
+/// doesn't reallocate every frame.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Growth {
+    /// Multiple of the old capacity to grow to at least, 1 to only make room for what's needed.
+    pub factor: f32,
+    /// Capacities are rounded up to a multiple of this.
+    pub granularity: usize,
+}
+
+impl Growth {
+    fn capacity(self, current: usize, needed: usize) -> usize {
+        let grown = needed.max((current as f32 * self.factor).ceil() as usize);
+        let granularity = self.granularity.max(1);
+        // Empty buffers can't be bound
+        (grown.div_ceil(granularity) * granularity).max(1)
+    }
+}
+
+/// CPU side geometry for meshes generated at runtime, which is rebuilt or partially rewritten
+/// and then uploaded into a `Mesh` created by `create_mesh`. Only the ranges written since the
+/// last upload are copied, unless the geometry outgrew the mesh's buffers.
+pub struct DynamicMesh {
+    pub growth: Growth,
+    vertices: Vec<Vertex>,
+    indices: Vec<u16>,
+    vertex_capacity: usize,
+    index_capacity: usize,
+    dirty_vertices: Option<Range<usize>>,
+    dirty_indices: Option<Range<usize>>,
+}
+
+impl DynamicMesh {
+    pub fn new(growth: Growth) -> Self {
+        Self {
+            growth,
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            vertex_capacity: 0,
+            index_capacity: 0,
+            dirty_vertices: None,
+            dirty_indices: None,
+        }
+    }
+
+    pub fn vertices(&self) -> &[Vertex] {
+        &self.vertices
+    }
+
+    pub fn indices(&self) -> &[u16] {
+        &self.indices
+    }
+
+    /// Replaces all of the geometry.
+    pub fn rebuild(&mut self, vertices: Vec<Vertex>, indices: Vec<u16>) {
+        self.dirty_vertices = Some(0..vertices.len());
+        self.dirty_indices = Some(0..indices.len());
+        self.vertices = vertices;
+        self.indices = indices;
+    }
+
+    /// Overwrites the vertices from `offset` on, appending any past the end. `offset` can be at
+    /// most the current vertex count.
+    pub fn write_vertices(&mut self, offset: usize, vertices: &[Vertex]) {
+        write(
+            &mut self.vertices,
+            &mut self.dirty_vertices,
+            offset,
+            vertices,
+        );
+    }
+
+    /// Overwrites the indices from `offset` on, appending any past the end. `offset` can be at
+    /// most the current index count.
+    pub fn write_indices(&mut self, offset: usize, indices: &[u16]) {
+        write(&mut self.indices, &mut self.dirty_indices, offset, indices);
+    }
+
+    /// A mesh with buffers for the current geometry, which later uploads have to go to.
+    pub fn create_mesh(&mut self, queue: &Queue, device: &Device) -> Mesh {
+        self.vertex_capacity = self.growth.capacity(0, self.vertices.len());
+        self.index_capacity = self.growth.capacity(0, self.indices.len());
+        let mut mesh =
+            Mesh::with_capacity(queue, device, self.vertex_capacity, self.index_capacity);
+        self.dirty_vertices = Some(0..self.vertices.len());
+        self.dirty_indices = Some(0..self.indices.len());
+        self.upload(queue, device, &mut mesh);
+        mesh
+    }
+
+    /// Copies the geometry written since the last upload into `mesh`, which has to come from
+    /// `create_mesh`. Its buffers are replaced with larger ones if the geometry outgrew them. Its
+    /// bounds are recomputed, and its BVH is rebuilt if the indices changed and only refit to the
+    /// new vertex positions otherwise.
+    pub fn upload(&mut self, queue: &Queue, device: &Device, mesh: &mut Mesh) {
+        if self.dirty_vertices.is_none() && self.dirty_indices.is_none() {
+            return;
+        }
+        if self.vertices.len() > self.vertex_capacity || self.indices.len() > self.index_capacity {
+            self.vertex_capacity = self
+                .growth
+                .capacity(self.vertex_capacity, self.vertices.len());
+            self.index_capacity = self
+                .growth
+                .capacity(self.index_capacity, self.indices.len());
+            mesh.reallocate_buffers(device, self.vertex_capacity, self.index_capacity);
+            self.dirty_vertices = Some(0..self.vertices.len());
+            self.dirty_indices = Some(0..self.indices.len());
+        }
+
+        if let Some(range) = self.dirty_vertices.take().filter(|range| !range.is_empty()) {
+            queue.write_buffer(
+                mesh.vertex_buffer(),
+                (range.start * mem::size_of::<Vertex>()) as BufferAddress,
+                bytemuck::cast_slice(&self.vertices[range]),
+            );
+        }
+        let indices_changed = self.dirty_indices.is_some();
+        if let Some(range) = self.dirty_indices.take().filter(|range| !range.is_empty()) {
+            // Copies have to be a multiple of 4 bytes, so whole pairs of indices are written. An
+            // odd last one is paired with the index after it, or padded past the end, which the
+            // buffer has room for
+            let start = range.start / 2 * 2;
+            let mut indices = self.indices[start..range.end].to_vec();
+            if range.end % 2 == 1 {
+                indices.push(self.indices.get(range.end).copied().unwrap_or(0));
+            }
+            queue.write_buffer(
+                mesh.index_buffer(),
+                (start * mem::size_of::<u16>()) as BufferAddress,
+                bytemuck::cast_slice(&indices),
+            );
+        }
+        if indices_changed {
+            mesh.set_written_geometry(&self.vertices, &self.indices);
+        } else {
+            mesh.set_written_vertices(&self.vertices);
+        }
+    }
+}
+
+/// Overwrites `data` from `offset` on and grows `dirty` to cover what was written.
+fn write<T: Copy>(
+    data: &mut Vec<T>,
+    dirty: &mut Option<Range<usize>>,
+    offset: usize,
+    values: &[T],
+) {
+    assert!(offset <= data.len(), "writing past the end");
+    let end = offset + values.len();
+    let overwritten = end.min(data.len());
+    data[offset..overwritten].copy_from_slice(&values[..overwritten - offset]);
+    data.extend_from_slice(&values[overwritten - offset..]);
+    *dirty = Some(match dirty.take() {
+        Some(dirty) => dirty.start.min(offset)..dirty.end.max(end),
+        None => offset..end,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytemuck::Zeroable;
+
+    fn empty_mesh() -> DynamicMesh {
+        DynamicMesh::new(Growth {
+            factor: 1.0,
+            granularity: 1,
+        })
+    }
+
+    #[test]
+    fn capacity_grows_by_factor_and_granularity() {
+        let growth = Growth {
+            factor: 1.5,
+            granularity: 64,
+        };
+        assert_eq!(growth.capacity(0, 0), 1);
+        assert_eq!(growth.capacity(0, 10), 64);
+        assert_eq!(growth.capacity(0, 64), 64);
+        assert_eq!(growth.capacity(64, 65), 128);
+        assert_eq!(growth.capacity(256, 257), 384);
+        assert_eq!(growth.capacity(256, 1000), 1024);
+
+        let exact = Growth {
+            factor: 1.0,
+            granularity: 0,
+        };
+        assert_eq!(exact.capacity(100, 101), 101);
+    }
+
+    #[test]
+    fn vertex_writes_merge_dirty_ranges_and_append() {
+        let mut mesh = empty_mesh();
+        mesh.rebuild(vec![Vertex::zeroed(); 4], vec![0, 1, 2, 0, 2, 3]);
+        mesh.dirty_vertices = None;
+
+        mesh.write_vertices(3, &[Vertex::zeroed(); 3]);
+        mesh.write_vertices(1, &[Vertex::zeroed()]);
+        assert_eq!(mesh.vertices().len(), 6);
+        assert_eq!(mesh.dirty_vertices, Some(1..6));
+    }
+
+    #[test]
+    #[should_panic(expected = "writing past the end")]
+    fn writing_past_the_end_panics() {
+        empty_mesh().write_vertices(1, &[Vertex::zeroed()]);
+    }
+
+    #[test]
+    fn index_writes_merge_dirty_ranges_and_append() {
+        let mut mesh = empty_mesh();
+        mesh.rebuild(vec![Vertex::zeroed(); 6], vec![0, 1, 2]);
+        mesh.dirty_indices = None;
+
+        mesh.write_indices(3, &[3, 4, 5]);
+        mesh.write_indices(1, &[1]);
+        assert_eq!(mesh.indices(), &[0, 1, 2, 3, 4, 5]);
+        assert_eq!(mesh.dirty_indices, Some(1..6));
+    }
+}
@@ -0,0 +1,566 @@
+use bytemuck::{Pod, Zeroable};
+use ddsfile::{Caps2, Dds, DxgiFormat};
+use exr::prelude::f16;
+use image::codecs::hdr::HdrDecoder;
+use std::error::Error;
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+use std::iter;
+use std::num::NonZeroU32;
+use std::path::Path;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+
+const CUBE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct FilterUniforms {
+    roughness: f32,
+    output_size: u32,
+    source_size: u32,
+    _padding: u32,
+}
+
+/// Cubemap lighting the scene and drawn behind it, along with its irradiance for diffuse
+/// lighting and its prefiltered mips for specular lighting.
+pub struct Environment {
+    environment: TextureView,
+    irradiance: TextureView,
+    specular: TextureView,
+}
+
+impl Environment {
+    const MAX_SIZE: u32 = 1024;
+    const IRRADIANCE_SIZE: u32 = 32;
+    const SPECULAR_SIZE: u32 = 128;
+    /// Keep in sync with shader.frag
+    const SPECULAR_MIP_COUNT: u32 = 5;
+    const WORKGROUP_SIZE: u32 = 8;
+
+    /// Loads an equirectangular Radiance `.hdr` image or a `.dds` cubemap with 16 or 32 bit float
+    /// RGBA texels.
+    pub fn load(device: &Device, queue: &Queue, path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file = BufReader::new(File::open(path)?);
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("hdr") => {
+                let decoder = HdrDecoder::new(file)?;
+                let metadata = decoder.metadata();
+                let texels = decoder
+                    .read_image_hdr()?
+                    .iter()
+                    .map(|texel| {
+                        let [r, g, b] = texel.0;
+                        [f32_to_f16(r), f32_to_f16(g), f32_to_f16(b), f32_to_f16(1.0)]
+                    })
+                    .collect::<Vec<[u16; 4]>>();
+                Ok(Self::from_equirectangular(
+                    device,
+                    queue,
+                    metadata.width,
+                    metadata.height,
+                    &texels,
+                ))
+            }
+            Some("dds") => {
+                let dds = Dds::read(file)?;
+                if !dds.header.caps2.contains(Caps2::CUBEMAP) || dds.get_width() != dds.get_height()
+                {
+                    return Err("DDS environment is not a cubemap".into());
+                }
+                let size = dds.get_width();
+                let face_texel_count = (size * size) as usize;
+                let face_stride = dds.get_array_stride()? as usize;
+                let texel_size = match dds.get_dxgi_format() {
+                    Some(DxgiFormat::R16G16B16A16_Float) => 8,
+                    Some(DxgiFormat::R32G32B32A32_Float) => 16,
+                    _ => return Err("DDS environment must be RGBA16F or RGBA32F".into()),
+                };
+                let faces = (0..6)
+                    .map(|face| {
+                        dds.data
+                            .get(face * face_stride..)
+                            .and_then(|data| data.get(..face_texel_count * texel_size))
+                            .ok_or("DDS environment is truncated")
+                    })
+                    .collect::<Result<Vec<&[u8]>, _>>()?;
+                // The texel data isn't necessarily aligned, so channels are read byte by byte
+                let texels = faces
+                    .iter()
+                    .flat_map(|face| face.chunks_exact(texel_size))
+                    .map(|texel| {
+                        let channel = |i: usize| {
+                            if texel_size == 8 {
+                                u16::from_le_bytes([texel[i * 2], texel[i * 2 + 1]])
+                            } else {
+                                let mut bytes = [0; 4];
+                                bytes.copy_from_slice(&texel[i * 4..i * 4 + 4]);
+                                f32_to_f16(f32::from_le_bytes(bytes))
+                            }
+                        };
+                        [channel(0), channel(1), channel(2), channel(3)]
+                    })
+                    .collect::<Vec<[u16; 4]>>();
+                Ok(Self::from_cube_faces(device, queue, size, &texels))
+            }
+            _ => Err(format!("unsupported environment file {}", path.display()).into()),
+        }
+    }
+
+    /// Simple sky gradient over a dark ground, for when no environment is loaded.
+    pub fn default_sky(device: &Device, queue: &Queue) -> Self {
+        let (width, height) = (256, 128);
+        let texels = (0..height)
+            .flat_map(|y| {
+                let elevation = (0.5 - (y as f32 + 0.5) / height as f32) * PI;
+                let color = if elevation > 0.0 {
+                    let t = elevation.sin();
+                    [0.9 - 0.6 * t, 1.0 - 0.4 * t, 1.1]
+                } else {
+                    [0.25, 0.22, 0.2]
+                };
+                let texel = [
+                    f32_to_f16(color[0]),
+                    f32_to_f16(color[1]),
+                    f32_to_f16(color[2]),
+                    f32_to_f16(1.0),
+                ];
+                vec![texel; width as usize]
+            })
+            .collect::<Vec<[u16; 4]>>();
+        Self::from_equirectangular(device, queue, width, height, &texels)
+    }
+
+    fn from_equirectangular(
+        device: &Device,
+        queue: &Queue,
+        width: u32,
+        height: u32,
+        texels: &[[u16; 4]],
+    ) -> Self {
+        let size = Extent3d {
+            width,
+            height,
+            depth: 1,
+        };
+        let equirectangular_texture = device.create_texture(&TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: CUBE_FORMAT,
+            usage: TextureUsage::SAMPLED | TextureUsage::COPY_DST,
+        });
+        queue.write_texture(
+            TextureCopyView {
+                texture: &equirectangular_texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(texels),
+            TextureDataLayout {
+                offset: 0,
+                bytes_per_row: width * 8,
+                rows_per_image: 0,
+            },
+            size,
+        );
+
+        let cube_size = (width / 4).next_power_of_two().min(Self::MAX_SIZE);
+        let cube_texture = create_cube_texture(device, cube_size, mip_count(cube_size));
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        let filter = Filter::new(device);
+        filter.dispatch(
+            device,
+            &mut encoder,
+            &filter.equirectangular_to_cube_pipeline,
+            &equirectangular_texture.create_view(&TextureViewDescriptor::default()),
+            &mip_view(&cube_texture, 0),
+            FilterUniforms {
+                roughness: 0.0,
+                output_size: cube_size,
+                source_size: width,
+                _padding: 0,
+            },
+        );
+        queue.submit(iter::once(encoder.finish()));
+
+        Self::from_cube_texture(device, queue, &filter, cube_texture, cube_size)
+    }
+
+    fn from_cube_faces(device: &Device, queue: &Queue, size: u32, texels: &[[u16; 4]]) -> Self {
+        let cube_texture = create_cube_texture(device, size, mip_count(size));
+        queue.write_texture(
+            TextureCopyView {
+                texture: &cube_texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(texels),
+            TextureDataLayout {
+                offset: 0,
+                bytes_per_row: size * 8,
+                rows_per_image: size,
+            },
+            Extent3d {
+                width: size,
+                height: size,
+                depth: 6,
+            },
+        );
+        Self::from_cube_texture(device, queue, &Filter::new(device), cube_texture, size)
+    }
+
+    /// Generates the environment's mips, then convolves it into the irradiance and specular maps.
+    fn from_cube_texture(
+        device: &Device,
+        queue: &Queue,
+        filter: &Filter,
+        cube_texture: Texture,
+        size: u32,
+    ) -> Self {
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        for mip in 1..mip_count(size) {
+            let output_size = (size >> mip).max(1);
+            filter.dispatch(
+                device,
+                &mut encoder,
+                &filter.downsample_pipeline,
+                &mip_view(&cube_texture, mip - 1),
+                &mip_view(&cube_texture, mip),
+                FilterUniforms {
+                    roughness: 0.0,
+                    output_size,
+                    source_size: output_size * 2,
+                    _padding: 0,
+                },
+            );
+        }
+        let environment = cube_texture.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::Cube),
+            ..TextureViewDescriptor::default()
+        });
+
+        let irradiance_texture = create_cube_texture(device, Self::IRRADIANCE_SIZE, 1);
+        filter.dispatch(
+            device,
+            &mut encoder,
+            &filter.irradiance_pipeline,
+            &environment,
+            &mip_view(&irradiance_texture, 0),
+            FilterUniforms {
+                roughness: 0.0,
+                output_size: Self::IRRADIANCE_SIZE,
+                source_size: size,
+                _padding: 0,
+            },
+        );
+
+        let specular_texture =
+            create_cube_texture(device, Self::SPECULAR_SIZE, Self::SPECULAR_MIP_COUNT);
+        for mip in 0..Self::SPECULAR_MIP_COUNT {
+            filter.dispatch(
+                device,
+                &mut encoder,
+                &filter.specular_pipeline,
+                &environment,
+                &mip_view(&specular_texture, mip),
+                FilterUniforms {
+                    roughness: mip as f32 / (Self::SPECULAR_MIP_COUNT - 1) as f32,
+                    output_size: Self::SPECULAR_SIZE >> mip,
+                    source_size: size,
+                    _padding: 0,
+                },
+            );
+        }
+        queue.submit(iter::once(encoder.finish()));
+
+        let cube_view = |texture: &Texture| {
+            texture.create_view(&TextureViewDescriptor {
+                dimension: Some(TextureViewDimension::Cube),
+                ..TextureViewDescriptor::default()
+            })
+        };
+        Self {
+            environment,
+            irradiance: cube_view(&irradiance_texture),
+            specular: cube_view(&specular_texture),
+        }
+    }
+
+    pub fn create_bind_group_layout(device: &Device) -> BindGroupLayout {
+        let cube_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStage::FRAGMENT,
+            ty: BindingType::SampledTexture {
+                dimension: TextureViewDimension::Cube,
+                component_type: TextureComponentType::Float,
+                multisampled: false,
+            },
+            count: None,
+        };
+        device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                cube_entry(0),
+                cube_entry(1),
+                cube_entry(2),
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Sampler { comparison: false },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::SampledTexture {
+                        dimension: TextureViewDimension::D2,
+                        component_type: TextureComponentType::Float,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    /// `ambient_occlusion` is a screen sized texture scaling the ambient lighting.
+    pub fn create_bind_group(
+        &self,
+        device: &Device,
+        layout: &BindGroupLayout,
+        sampler: &Sampler,
+        ambient_occlusion: &TextureView,
+    ) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&self.environment),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&self.irradiance),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&self.specular),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Sampler(sampler),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(ambient_occlusion),
+                },
+            ],
+        })
+    }
+}
+
+/// Compute pipelines writing into the faces of a cubemap mip, each reading a differently
+/// shaped source texture.
+struct Filter {
+    equirectangular_to_cube_pipeline: ComputePipeline,
+    downsample_pipeline: ComputePipeline,
+    irradiance_pipeline: ComputePipeline,
+    specular_pipeline: ComputePipeline,
+    sampler: Sampler,
+}
+
+impl Filter {
+    fn new(device: &Device) -> Self {
+        let create_pipeline = |source_dimension, module| {
+            let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStage::COMPUTE,
+                        ty: BindingType::SampledTexture {
+                            dimension: source_dimension,
+                            component_type: TextureComponentType::Float,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStage::COMPUTE,
+                        ty: BindingType::Sampler { comparison: false },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStage::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            dimension: TextureViewDimension::D2Array,
+                            format: CUBE_FORMAT,
+                            readonly: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStage::COMPUTE,
+                        ty: BindingType::UniformBuffer {
+                            dynamic: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                    label: None,
+                    bind_group_layouts: &[&bind_group_layout],
+                    push_constant_ranges: &[],
+                })),
+                compute_stage: ProgrammableStageDescriptor {
+                    module: &device.create_shader_module(module),
+                    entry_point: "main",
+                },
+            })
+        };
+
+        Self {
+            equirectangular_to_cube_pipeline: create_pipeline(
+                TextureViewDimension::D2,
+                include_spirv!("../shaders/equirectangular_to_cube.spv"),
+            ),
+            downsample_pipeline: create_pipeline(
+                TextureViewDimension::D2Array,
+                include_spirv!("../shaders/cube_downsample.spv"),
+            ),
+            irradiance_pipeline: create_pipeline(
+                TextureViewDimension::Cube,
+                include_spirv!("../shaders/irradiance.spv"),
+            ),
+            specular_pipeline: create_pipeline(
+                TextureViewDimension::Cube,
+                include_spirv!("../shaders/specular.spv"),
+            ),
+            sampler: device.create_sampler(&SamplerDescriptor {
+                label: None,
+                address_mode_u: AddressMode::ClampToEdge,
+                address_mode_v: AddressMode::ClampToEdge,
+                address_mode_w: AddressMode::ClampToEdge,
+                mag_filter: FilterMode::Linear,
+                min_filter: FilterMode::Linear,
+                mipmap_filter: FilterMode::Linear,
+                lod_min_clamp: 0.0,
+                lod_max_clamp: f32::MAX,
+                compare: None,
+                anisotropy_clamp: None,
+            }),
+        }
+    }
+
+    fn dispatch(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        pipeline: &ComputePipeline,
+        source: &TextureView,
+        output: &TextureView,
+        uniforms: FilterUniforms,
+    ) {
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::bytes_of(&uniforms),
+            usage: BufferUsage::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(source),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&self.sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(output),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Buffer(uniform_buffer.slice(..)),
+                },
+            ],
+        });
+
+        let mut compute_pass = encoder.begin_compute_pass();
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        let workgroups = uniforms.output_size.div_ceil(Environment::WORKGROUP_SIZE);
+        compute_pass.dispatch(workgroups, workgroups, 6);
+    }
+}
+
+fn mip_count(size: u32) -> u32 {
+    32 - size.leading_zeros()
+}
+
+fn create_cube_texture(device: &Device, size: u32, mip_level_count: u32) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: None,
+        size: Extent3d {
+            width: size,
+            height: size,
+            depth: 6,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: CUBE_FORMAT,
+        usage: TextureUsage::SAMPLED | TextureUsage::STORAGE | TextureUsage::COPY_DST,
+    })
+}
+
+/// All six faces of a single mip, as bound to the filter shaders.
+fn mip_view(texture: &Texture, mip: u32) -> TextureView {
+    texture.create_view(&TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::D2Array),
+        base_mip_level: mip,
+        level_count: NonZeroU32::new(1),
+        ..TextureViewDescriptor::default()
+    })
+}
+
+/// Converts to the bits of the nearest half float, keeping infinities and NaN.
+fn f32_to_f16(value: f32) -> u16 {
+    f16::from_f32(value).to_bits()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn f32_to_f16_rounds_to_nearest() {
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        // Halfway between 1 and the next half float rounds to even, just above it rounds up
+        assert_eq!(f32_to_f16(1.0 + 2f32.powi(-11)), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + 2f32.powi(-11) + 2f32.powi(-20)), 0x3c01);
+        assert_eq!(f32_to_f16(-0.1), 0xae66);
+        // Subnormal
+        assert_eq!(f32_to_f16(2f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_f16(1e6), 0x7c00);
+        let nan = f32_to_f16(f32::NAN);
+        assert_eq!(nan & 0x7c00, 0x7c00);
+        assert_ne!(nan & 0x3ff, 0);
+    }
+}
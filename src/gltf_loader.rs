@@ -0,0 +1,378 @@
+use crate::objects::{SkinVertex, Vertex};
+use crate::skinning::{
+    AnimationClip, Channel, Interpolation, Joint, JointPose, Keyframes, Skeleton, Skin,
+};
+use bytemuck::Zeroable;
+use gltf::animation::util::ReadOutputs;
+use gltf::buffer::Source;
+use gltf::mesh::Mode;
+use gltf::{animation, Gltf, Node, Scene};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use ultraviolet::{Mat3, Mat4, Rotor3, Vec3, Vec4};
+
+/// Loads the triangles of every mesh in the default scene (or the first scene) of a glTF or GLB
+/// file into a single vertex and index list, with node transforms applied.
+pub fn load_gltf(path: &Path) -> Result<(Vec<Vertex>, Vec<u16>), Box<dyn Error>> {
+    let gltf = Gltf::open(path)?;
+    let buffers = load_buffers(&gltf, path)?;
+    let mut geometry = SceneGeometry::default();
+    for node in default_scene(&gltf)?.nodes() {
+        load_node(&node, Mat4::identity(), &buffers, &mut geometry)?;
+    }
+    Ok((geometry.vertices, geometry.indices))
+}
+
+/// A glTF scene loaded with its skin.
+pub struct SkinnedScene {
+    pub vertices: Vec<Vertex>,
+    /// One per vertex, with no weights for the vertices of meshes without the skin. Empty if the
+    /// scene has no skin.
+    pub skin_vertices: Vec<SkinVertex>,
+    pub indices: Vec<u16>,
+    pub skin: Option<Skin>,
+}
+
+/// Like `load_gltf`, but the meshes using a skin are kept in their bind pose and the skin is loaded
+/// with every animation of its joints. Only a single skin is supported.
+pub fn load_skinned_gltf(path: &Path) -> Result<SkinnedScene, Box<dyn Error>> {
+    let gltf = Gltf::open(path)?;
+    let buffers = load_buffers(&gltf, path)?;
+    let scene = default_scene(&gltf)?;
+    let mut geometry = SceneGeometry {
+        skin_vertices: Some(Vec::new()),
+        ..SceneGeometry::default()
+    };
+    for node in scene.nodes() {
+        load_node(&node, Mat4::identity(), &buffers, &mut geometry)?;
+    }
+
+    let skin = match geometry.skin {
+        Some(skin) => gltf.skins().nth(skin).unwrap(),
+        None => {
+            return Ok(SkinnedScene {
+                vertices: geometry.vertices,
+                skin_vertices: Vec::new(),
+                indices: geometry.indices,
+                skin: None,
+            })
+        }
+    };
+    let joint_indices = skin
+        .joints()
+        .enumerate()
+        .map(|(i, node)| (node.index(), i))
+        .collect::<HashMap<usize, usize>>();
+    if joint_indices.is_empty() {
+        return Err("glTF skin has no joints".into());
+    }
+    let inverse_bind_matrices = skin
+        .reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice))
+        .read_inverse_bind_matrices()
+        .map(|matrices| matrices.map(Mat4::from).collect::<Vec<Mat4>>())
+        .unwrap_or_else(|| vec![Mat4::identity(); joint_indices.len()]);
+    let mut joints = skin
+        .joints()
+        .zip(inverse_bind_matrices)
+        .map(|(node, inverse_bind_matrix)| Joint {
+            parent: None,
+            parent_offset: Mat4::identity(),
+            rest_pose: joint_pose(&node),
+            inverse_bind_matrix,
+        })
+        .collect::<Vec<Joint>>();
+    for node in scene.nodes() {
+        find_joint_parents(&node, None, Mat4::identity(), &joint_indices, &mut joints);
+    }
+
+    let clips = gltf
+        .animations()
+        .map(|animation| load_animation(&animation, &buffers, &joint_indices))
+        .collect::<Result<Vec<AnimationClip>, Box<dyn Error>>>()?
+        .into_iter()
+        .filter(|clip| !clip.channels.is_empty())
+        .collect();
+    Ok(SkinnedScene {
+        vertices: geometry.vertices,
+        skin_vertices: geometry.skin_vertices.unwrap(),
+        indices: geometry.indices,
+        skin: Some(Skin::new(Skeleton::new(joints), clips)),
+    })
+}
+
+fn default_scene(gltf: &Gltf) -> Result<Scene<'_>, Box<dyn Error>> {
+    Ok(gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .ok_or("glTF file has no scenes")?)
+}
+
+/// Reads every buffer of the file, either from the GLB binary chunk or from files next to it.
+pub fn load_buffers(gltf: &Gltf, path: &Path) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    gltf.buffers()
+        .map(|buffer| match buffer.source() {
+            Source::Bin => gltf
+                .blob
+                .clone()
+                .ok_or_else(|| "glTF file is missing its binary chunk".into()),
+            Source::Uri(uri) if uri.starts_with("data:") => {
+                Err("embedded glTF buffers are not supported".into())
+            }
+            Source::Uri(uri) => Ok(fs::read(
+                path.parent().unwrap_or_else(|| Path::new("")).join(uri),
+            )?),
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct SceneGeometry {
+    vertices: Vec<Vertex>,
+    indices: Vec<u16>,
+    /// Only loaded along with the skin.
+    skin_vertices: Option<Vec<SkinVertex>>,
+    /// Index of the skin the skinned meshes use.
+    skin: Option<usize>,
+}
+
+fn load_node(
+    node: &Node,
+    parent_transform: Mat4,
+    buffers: &[Vec<u8>],
+    geometry: &mut SceneGeometry,
+) -> Result<(), Box<dyn Error>> {
+    let transform = parent_transform * Mat4::from(node.transform().matrix());
+    if let Some(mesh) = node.mesh() {
+        let skin = node.skin().filter(|_| geometry.skin_vertices.is_some());
+        if let Some(skin) = &skin {
+            match geometry.skin {
+                Some(index) if index != skin.index() => {
+                    return Err("glTF scenes with more than one skin are not supported".into());
+                }
+                _ => geometry.skin = Some(skin.index()),
+            }
+        }
+        // Skinned meshes are placed by their joints, glTF ignores their node's transform
+        let vertex_transform = match skin {
+            Some(_) => Mat4::identity(),
+            None => transform,
+        };
+        let normal_transform = transform_to_mat3(vertex_transform).inversed().transposed();
+        for primitive in mesh.primitives() {
+            if primitive.mode() != Mode::Triangles {
+                continue;
+            }
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+            let positions = reader
+                .read_positions()
+                .ok_or("glTF primitive has no positions")?
+                .collect::<Vec<[f32; 3]>>();
+            let normals = reader
+                .read_normals()
+                .map(|normals| normals.collect::<Vec<[f32; 3]>>())
+                .unwrap_or_else(|| vec![[0.0, 1.0, 0.0]; positions.len()]);
+            let uvs = reader
+                .read_tex_coords(0)
+                .map(|uvs| uvs.into_f32().collect::<Vec<[f32; 2]>>())
+                .unwrap_or_else(|| vec![[0.0, 0.0]; positions.len()]);
+
+            let base_vertex = geometry.vertices.len();
+            if base_vertex + positions.len() > u16::MAX as usize + 1 {
+                return Err("glTF scene has more vertices than fit in 16 bit indices".into());
+            }
+            geometry
+                .vertices
+                .extend(positions.iter().zip(&normals).zip(&uvs).map(
+                    |((&position, &normal), &uv)| {
+                        let position = vertex_transform
+                            * Vec4::new(position[0], position[1], position[2], 1.0);
+                        let normal = (normal_transform * Vec3::from(normal)).normalized();
+                        Vertex {
+                            position: [position.x, position.y, position.z],
+                            normal: [normal.x, normal.y, normal.z],
+                            uv,
+                        }
+                    },
+                ));
+            if let Some(skin_vertices) = &mut geometry.skin_vertices {
+                let joints = reader.read_joints(0).filter(|_| skin.is_some());
+                let weights = reader.read_weights(0).filter(|_| skin.is_some());
+                match (joints, weights, &skin) {
+                    (Some(joints), Some(weights), Some(skin)) => {
+                        let joint_count = skin.joints().count();
+                        for (joints, weights) in joints.into_u16().zip(weights.into_f32()) {
+                            skin_vertices.push(skin_vertex(joints, weights, joint_count)?);
+                        }
+                    }
+                    _ => skin_vertices.resize(geometry.vertices.len(), SkinVertex::zeroed()),
+                }
+            }
+            match reader.read_indices() {
+                Some(primitive_indices) => geometry.indices.extend(
+                    primitive_indices
+                        .into_u32()
+                        .map(|index| (base_vertex + index as usize) as u16),
+                ),
+                None => geometry
+                    .indices
+                    .extend((base_vertex..geometry.vertices.len()).map(|index| index as u16)),
+            }
+        }
+    }
+
+    for child in node.children() {
+        load_node(&child, transform, buffers, geometry)?;
+    }
+    Ok(())
+}
+
+/// Normalizes the weights, exporters don't always make them add up to exactly 1. Every joint index
+/// has to be in range, even with a weight of 0, as the shaders read all four joints' matrices.
+fn skin_vertex(
+    joints: [u16; 4],
+    weights: [f32; 4],
+    joint_count: usize,
+) -> Result<SkinVertex, Box<dyn Error>> {
+    if joints.iter().any(|&joint| joint as usize >= joint_count) {
+        return Err("glTF joint index out of range".into());
+    }
+    let total = weights.iter().sum::<f32>();
+    let scale = if total > 0.0 { 1.0 / total } else { 0.0 };
+    Ok(SkinVertex {
+        joints: [
+            joints[0] as u32,
+            joints[1] as u32,
+            joints[2] as u32,
+            joints[3] as u32,
+        ],
+        weights: [
+            weights[0] * scale,
+            weights[1] * scale,
+            weights[2] * scale,
+            weights[3] * scale,
+        ],
+    })
+}
+
+fn joint_pose(node: &Node) -> JointPose {
+    let (translation, rotation, scale) = node.transform().decomposed();
+    JointPose {
+        translation: Vec3::from(translation),
+        rotation: Rotor3::from_quaternion_array(rotation),
+        scale: Vec3::from(scale),
+    }
+}
+
+/// Walks the scene to find each joint's closest ancestor that is a joint too, and the transforms
+/// of any other nodes in between.
+fn find_joint_parents(
+    node: &Node,
+    parent: Option<usize>,
+    parent_offset: Mat4,
+    joint_indices: &HashMap<usize, usize>,
+    joints: &mut [Joint],
+) {
+    let (parent, parent_offset) = match joint_indices.get(&node.index()) {
+        Some(&joint) => {
+            joints[joint].parent = parent;
+            joints[joint].parent_offset = parent_offset;
+            (Some(joint), Mat4::identity())
+        }
+        None => (
+            parent,
+            parent_offset * Mat4::from(node.transform().matrix()),
+        ),
+    };
+    for child in node.children() {
+        find_joint_parents(&child, parent, parent_offset, joint_indices, joints);
+    }
+}
+
+/// The channels of an animation that move joints, other nodes and morph targets are skipped.
+fn load_animation(
+    animation: &animation::Animation,
+    buffers: &[Vec<u8>],
+    joint_indices: &HashMap<usize, usize>,
+) -> Result<AnimationClip, Box<dyn Error>> {
+    let mut channels = Vec::new();
+    for channel in animation.channels() {
+        let joint = match joint_indices.get(&channel.target().node().index()) {
+            Some(&joint) => joint,
+            None => continue,
+        };
+        let reader = channel.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+        let times = reader
+            .read_inputs()
+            .ok_or("glTF animation channel has no keyframe times")?
+            .collect::<Vec<f32>>();
+        let interpolation = channel.sampler().interpolation();
+        // Cubic splines store an in tangent, the value and an out tangent for each keyframe
+        let values = |count: usize| match interpolation {
+            animation::Interpolation::CubicSpline => (1..count).step_by(3).collect::<Vec<usize>>(),
+            _ => (0..count).collect(),
+        };
+        let keyframes = match reader
+            .read_outputs()
+            .ok_or("glTF animation channel has no keyframe values")?
+        {
+            ReadOutputs::Translations(translations) => {
+                let translations = translations.map(Vec3::from).collect::<Vec<Vec3>>();
+                Keyframes::Translations(
+                    values(translations.len())
+                        .into_iter()
+                        .map(|i| translations[i])
+                        .collect(),
+                )
+            }
+            ReadOutputs::Rotations(rotations) => {
+                let rotations = rotations
+                    .into_f32()
+                    .map(Rotor3::from_quaternion_array)
+                    .collect::<Vec<Rotor3>>();
+                Keyframes::Rotations(
+                    values(rotations.len())
+                        .into_iter()
+                        .map(|i| rotations[i].normalized())
+                        .collect(),
+                )
+            }
+            ReadOutputs::Scales(scales) => {
+                let scales = scales.map(Vec3::from).collect::<Vec<Vec3>>();
+                Keyframes::Scales(
+                    values(scales.len())
+                        .into_iter()
+                        .map(|i| scales[i])
+                        .collect(),
+                )
+            }
+            ReadOutputs::MorphTargetWeights(_) => continue,
+        };
+        let keyframe_count = match &keyframes {
+            Keyframes::Translations(values) | Keyframes::Scales(values) => values.len(),
+            Keyframes::Rotations(values) => values.len(),
+        };
+        if keyframe_count != times.len() {
+            return Err("glTF animation channel has a different number of times and values".into());
+        }
+        channels.push(Channel {
+            joint,
+            interpolation: match interpolation {
+                animation::Interpolation::Step => Interpolation::Step,
+                _ => Interpolation::Linear,
+            },
+            times,
+            keyframes,
+        });
+    }
+    Ok(AnimationClip::new(channels))
+}
+
+fn transform_to_mat3(transform: Mat4) -> Mat3 {
+    Mat3::new(
+        transform.cols[0].xyz(),
+        transform.cols[1].xyz(),
+        transform.cols[2].xyz(),
+    )
+}
@@ -0,0 +1,201 @@
+use crate::renderer::{Z_FAR, Z_NEAR};
+use bytemuck::{Pod, Zeroable};
+use std::mem;
+use ultraviolet::{Mat4, Vec3};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+
+/// Matches `PointLight` in lighting.glsl.
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable, Debug)]
+pub struct PointLight {
+    pub position: Vec3,
+    /// Distance at which the light's contribution fades out completely.
+    pub range: f32,
+    pub color: Vec3,
+    pub intensity: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct LightsHeader {
+    light_count: u32,
+    _padding: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct ClusterUniforms {
+    inverse_projection: Mat4,
+    view: Mat4,
+    grid_size: [u32; 4],
+    screen_size: [f32; 2],
+    z_near: f32,
+    z_far: f32,
+}
+
+/// Bins the lights into a grid of clusters, screen space tiles split into exponentially spaced
+/// depth slices, so shading only loops over the lights that can reach each fragment.
+pub struct LightClusters {
+    screen_width: u32,
+    screen_height: u32,
+    cluster_lights_buffer: Buffer,
+    bind_group_layout: BindGroupLayout,
+    compute_bind_group_layout: BindGroupLayout,
+    pipeline: ComputePipeline,
+}
+
+impl LightClusters {
+    const GRID_SIZE: [u32; 3] = [16, 9, 24];
+    /// Lights beyond this are dropped from a cluster. Keep in sync with lighting.glsl and
+    /// light_clusters.comp.
+    const MAX_LIGHTS_PER_CLUSTER: u32 = 128;
+    const WORKGROUP_SIZE: u32 = 64;
+
+    pub fn new(device: &Device, screen_width: u32, screen_height: u32) -> Self {
+        let layout_entries = |visibility, cluster_lights_readonly| {
+            let storage_buffer_entry = |binding, readonly| BindGroupLayoutEntry {
+                binding,
+                visibility,
+                ty: BindingType::StorageBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                    readonly,
+                },
+                count: None,
+            };
+            [
+                storage_buffer_entry(0, true),
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility,
+                    ty: BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_buffer_entry(2, cluster_lights_readonly),
+            ]
+        };
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &layout_entries(ShaderStage::FRAGMENT, true),
+        });
+        let compute_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &layout_entries(ShaderStage::COMPUTE, false),
+            });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&compute_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            compute_stage: ProgrammableStageDescriptor {
+                module: &device
+                    .create_shader_module(include_spirv!("../shaders/light_clusters.spv")),
+                entry_point: "main",
+            },
+        });
+
+        let [x, y, z] = Self::GRID_SIZE;
+        let cluster_lights_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: (x * y * z * (Self::MAX_LIGHTS_PER_CLUSTER + 1)) as BufferAddress
+                * mem::size_of::<u32>() as BufferAddress,
+            usage: BufferUsage::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            screen_width,
+            screen_height,
+            cluster_lights_buffer,
+            bind_group_layout,
+            compute_bind_group_layout,
+            pipeline,
+        }
+    }
+
+    /// Layout of the bind group returned by `update`, holding the lights, the grid parameters
+    /// and each cluster's light indices.
+    pub fn bind_group_layout(&self) -> &BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn set_screen_size(&mut self, width: u32, height: u32) {
+        self.screen_width = width;
+        self.screen_height = height;
+    }
+
+    /// Uploads the lights and records a compute pass assigning them to clusters.
+    pub fn update(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        lights: &[PointLight],
+        view: Mat4,
+        projection: Mat4,
+    ) -> BindGroup {
+        let header = LightsHeader {
+            light_count: lights.len() as u32,
+            _padding: [0; 3],
+        };
+        let mut lights_data = bytemuck::bytes_of(&header).to_vec();
+        lights_data.extend_from_slice(bytemuck::cast_slice(lights));
+        let lights_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: &lights_data,
+            usage: BufferUsage::STORAGE,
+        });
+        let [x, y, z] = Self::GRID_SIZE;
+        let uniforms = ClusterUniforms {
+            inverse_projection: projection.inversed(),
+            view,
+            grid_size: [x, y, z, 0],
+            screen_size: [self.screen_width as f32, self.screen_height as f32],
+            z_near: Z_NEAR,
+            z_far: Z_FAR,
+        };
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::bytes_of(&uniforms),
+            usage: BufferUsage::UNIFORM,
+        });
+
+        let create_bind_group = |layout| {
+            device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::Buffer(lights_buffer.slice(..)),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::Buffer(uniform_buffer.slice(..)),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::Buffer(self.cluster_lights_buffer.slice(..)),
+                    },
+                ],
+            })
+        };
+        let compute_bind_group = create_bind_group(&self.compute_bind_group_layout);
+        {
+            let mut compute_pass = encoder.begin_compute_pass();
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &compute_bind_group, &[]);
+            compute_pass.dispatch((x * y * z).div_ceil(Self::WORKGROUP_SIZE), 1, 1);
+        }
+
+        create_bind_group(&self.bind_group_layout)
+    }
+}
@@ -1,19 +1,86 @@
+mod antialiasing;
+mod bloom;
+mod bvh;
+mod capture;
+mod convert;
+mod culling;
+mod debug_draw;
+mod deferred;
+mod dynamic_mesh;
+mod environment;
+mod gltf_loader;
+mod lights;
+mod mesh_file;
 mod objects;
+mod optimize;
+mod picking;
+mod postprocess;
+mod primitives;
+mod profiler;
+mod raycast;
 mod renderer;
+mod simplify;
+mod skinning;
+mod ssao;
+mod ui;
 
-use crate::objects::Mesh;
-use crate::renderer::Renderer;
-use rayon::iter::{IntoParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
-use std::iter;
-use std::time::{Duration, Instant};
-use ultraviolet::{Rotor3, Similarity3, Vec3};
+use crate::antialiasing::Antialiasing;
+use crate::capture::CaptureFormat;
+use crate::culling::CullingMode;
+use crate::dynamic_mesh::{DynamicMesh, Growth};
+use crate::environment::Environment;
+use crate::lights::PointLight;
+use crate::objects::{AlphaMode, Geometry, Mesh, Vertex};
+use crate::picking::Pick;
+use crate::postprocess::{Exposure, PostProcessSettings, Tonemapper};
+use crate::primitives::Primitive;
+use crate::raycast::Scene;
+use crate::renderer::{DebugView, RenderPath, RenderSettings, Renderer};
+use crate::ui::DebugUi;
+use rayon::iter::{
+    IntoParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator,
+};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{env, iter, process};
+use ultraviolet::{Mat4, Rotor3, Similarity3, Vec3};
 use wgpu::*;
-use winit::event::{ElementState, Event, VirtualKeyCode, WindowEvent};
+use winit::event::{ElementState, Event, MouseButton, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::{Fullscreen, WindowBuilder};
 
+/// Length of the image sequence F10 records, 5 seconds at 60 FPS.
+const RECORDED_FRAMES: u32 = 300;
+
 fn main() {
-    env_logger::init();
+    // Status messages are logged at the info level, which is shown without setting RUST_LOG
+    env_logger::from_env(env_logger::Env::default().default_filter_or("meshweaver=info")).init();
+
+    // `meshweaver convert ...` precompiles a mesh, otherwise `--environment <file>` picks the
+    // environment map, `--unvalidated-msaa` allows 2x and 8x MSAA and any other arguments are
+    // mesh files to display, either converted meshes or glTF files, which are loaded with their
+    // skin and animations
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    if args.first().map(String::as_str) == Some("convert") {
+        if let Err(error) = convert::run(&args[1..]) {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+        return;
+    }
+    let mut environment_path = None;
+    let mut allow_unvalidated_msaa = false;
+    let mut mesh_paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--environment" {
+            environment_path = args.next();
+        } else if arg == "--unvalidated-msaa" {
+            allow_unvalidated_msaa = true;
+        } else {
+            mesh_paths.push(arg);
+        }
+    }
 
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
@@ -55,24 +122,60 @@ fn main() {
     let mut swapchain = device.create_swap_chain(&surface, &swapchain_descriptor);
 
     let mut renderer = Renderer::new(
+        &queue,
         &device,
+        allow_unvalidated_msaa,
         swapchain_descriptor.width as f32,
         swapchain_descriptor.height as f32,
     );
+    let default_lights = renderer.lights.clone();
+    let mut show_gizmos = false;
+    // Culling frustum frozen in place for inspecting it from elsewhere
+    let mut frozen_frustum: Option<Mat4> = None;
+    let mut cursor_position = (0, 0);
+    // Instance picked by clicking on it, highlighted with its bounding sphere
+    let mut selection: Option<Pick> = None;
+    let mut debug_ui = DebugUi::new(
+        &device,
+        swapchain_descriptor.format,
+        swapchain_descriptor.width,
+        swapchain_descriptor.height,
+        window.scale_factor() as f32,
+    );
+    let moss_texture = &include_bytes!("../textures/Moss001_4K/Moss001_4K_Color.dds")[..];
+    // The monkey is read from its OBJ, the sphere is generated while it loads
     let mut meshes = vec![
-        &include_bytes!("../meshes/monkey.obj")[..],
-        &include_bytes!("../meshes/uvsphere.obj")[..],
+        None,
+        Some(Primitive::UvSphere {
+            radius: 1.0,
+            segments: 32,
+            rings: 16,
+        }),
     ]
     .into_par_iter()
-    .map(|obj| {
-        Mesh::from_obj_and_texture(
+    .map(|primitive| match primitive {
+        None => Mesh::from_obj_and_texture(
+            &queue,
+            &device,
+            &include_bytes!("../meshes/monkey.obj")[..],
+            &mut &moss_texture[..],
+            true,
+            true,
+        ),
+        Some(primitive) => Mesh::from_geometry_and_texture(
             &queue,
             &device,
-            obj,
-            &mut &include_bytes!("../textures/Moss001_4K/Moss001_4K_Color.dds")[..],
-        )
+            primitive.geometry(),
+            &mut &moss_texture[..],
+            true,
+        ),
     })
     .collect::<Vec<Mesh>>();
+    for (i, mesh) in meshes.iter().enumerate() {
+        if let Some(stats) = mesh.optimization_stats() {
+            log::info!("Mesh {}: {}", i, stats);
+        }
+    }
     meshes[0].instances.push(Similarity3::new(
         Vec3::new(-1.0, -0.5, 0.0),
         Rotor3::identity(),
@@ -88,6 +191,60 @@ fn main() {
         Rotor3::identity(),
         0.5,
     ));
+    // Rippling water below the other meshes, rewritten every update, rebuilt when its resolution
+    // changes and with its indices rewritten when its quads' diagonals are flipped
+    let mut wave_resolution = 32;
+    let mut wave_time = 0.0;
+    let mut waves = DynamicMesh::new(Growth {
+        factor: 1.5,
+        granularity: 1024,
+    });
+    let plane = wave_plane(wave_resolution);
+    waves.rebuild(plane.vertices, plane.indices);
+    let waves_mesh = meshes.len();
+    meshes.push(waves.create_mesh(&queue, &device));
+    meshes[waves_mesh].instances.push(Similarity3::new(
+        Vec3::new(0.0, -1.25, 0.0),
+        Rotor3::identity(),
+        1.5,
+    ));
+    if let Some(path) = environment_path {
+        let environment =
+            Environment::load(&device, &queue, Path::new(path)).unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                process::exit(1);
+            });
+        renderer.set_environment(environment);
+    }
+    let loaded_meshes = mesh_paths
+        .par_iter()
+        .map(|path| {
+            let path = Path::new(path);
+            let extension = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .map(|extension| extension.to_ascii_lowercase());
+            match extension.as_deref() {
+                Some("gltf") | Some("glb") => {
+                    Mesh::from_gltf(&queue, &device, path).map_err(|error| error.to_string())
+                }
+                _ => Mesh::from_binary(&queue, &device, path, false)
+                    .map_err(|error| error.to_string()),
+            }
+        })
+        .collect::<Result<Vec<Mesh>, String>>()
+        .unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            process::exit(1);
+        });
+    for (i, mut mesh) in loaded_meshes.into_iter().enumerate() {
+        mesh.instances.push(Similarity3::new(
+            Vec3::new(i as f32 - 1.0, 0.0, -1.5),
+            Rotor3::identity(),
+            0.5,
+        ));
+        meshes.push(mesh);
+    }
 
     event_loop.run(move |event, _, control_flow| match event {
         Event::NewEvents(_) => {
@@ -96,6 +253,8 @@ fn main() {
             last_frame = now;
         }
 
+        // Input the debug UI uses doesn't reach the scene
+        Event::WindowEvent { event, .. } if debug_ui.handle_event(&event) => {}
         Event::WindowEvent { event, .. } => match event {
             WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
 
@@ -103,6 +262,11 @@ fn main() {
                 swapchain_descriptor.width = new_inner_size.width;
                 swapchain_descriptor.height = new_inner_size.height;
                 swapchain = device.create_swap_chain(&surface, &swapchain_descriptor);
+                debug_ui.set_screen_size(
+                    new_inner_size.width,
+                    new_inner_size.height,
+                    window.scale_factor() as f32,
+                );
                 renderer.set_screen_size(
                     &queue,
                     &device,
@@ -110,10 +274,18 @@ fn main() {
                     new_inner_size.height as f32,
                 );
             }
-            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+            WindowEvent::ScaleFactorChanged {
+                scale_factor,
+                new_inner_size,
+            } => {
                 swapchain_descriptor.width = new_inner_size.width;
                 swapchain_descriptor.height = new_inner_size.height;
                 swapchain = device.create_swap_chain(&surface, &swapchain_descriptor);
+                debug_ui.set_screen_size(
+                    new_inner_size.width,
+                    new_inner_size.height,
+                    scale_factor as f32,
+                );
                 renderer.set_screen_size(
                     &queue,
                     &device,
@@ -122,6 +294,38 @@ fn main() {
                 );
             }
 
+            WindowEvent::CursorMoved { position, .. } => {
+                cursor_position = (position.x as u32, position.y as u32);
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => renderer.pick(cursor_position.0, cursor_position.1),
+            // Selects the same as picking, but on the CPU
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Right,
+                ..
+            } => {
+                let (origin, direction) = renderer.screen_ray(cursor_position.0, cursor_position.1);
+                let hit = Scene::new(&meshes).raycast(origin, direction);
+                match hit {
+                    Some(hit) => log::info!(
+                        "Hit triangle {} of instance {} of mesh {} at {:?}",
+                        hit.triangle,
+                        hit.instance,
+                        hit.mesh,
+                        origin + direction * hit.t
+                    ),
+                    None => log::info!("Hit nothing"),
+                }
+                selection = hit.map(|hit| Pick {
+                    mesh: hit.mesh,
+                    instance: hit.instance,
+                });
+            }
+
             WindowEvent::KeyboardInput { input, .. } => {
                 if input.state == ElementState::Pressed {
                     match input.virtual_keycode {
@@ -133,6 +337,185 @@ fn main() {
                             };
                             window.set_fullscreen(fullscreen);
                         }
+                        Some(VirtualKeyCode::C) => {
+                            renderer.culling_mode = match renderer.culling_mode {
+                                CullingMode::Cpu => CullingMode::Gpu,
+                                CullingMode::Gpu => CullingMode::Cpu,
+                            };
+                        }
+                        Some(VirtualKeyCode::M) => {
+                            // Cycle through the antialiasing modes, skipping unsupported ones
+                            const MODES: [Antialiasing; 6] = [
+                                Antialiasing::Msaa(1),
+                                Antialiasing::Msaa(2),
+                                Antialiasing::Msaa(4),
+                                Antialiasing::Msaa(8),
+                                Antialiasing::Fxaa,
+                                Antialiasing::Taa,
+                            ];
+                            let current = renderer.render_settings().antialiasing;
+                            let current_index =
+                                MODES.iter().position(|&mode| mode == current).unwrap_or(0);
+                            let next = (1..MODES.len())
+                                .map(|offset| RenderSettings {
+                                    antialiasing: MODES[(current_index + offset) % MODES.len()],
+                                    ..renderer.render_settings()
+                                })
+                                .find(|settings| settings.validate(allow_unvalidated_msaa).is_ok());
+                            if let Some(settings) = next {
+                                renderer.set_render_settings(&device, settings).unwrap();
+                                log::info!("Antialiasing: {:?}", settings.antialiasing);
+                            }
+                        }
+                        Some(VirtualKeyCode::T) => {
+                            let settings = &mut renderer.post_process_settings;
+                            settings.tonemapper = match settings.tonemapper {
+                                Tonemapper::None => Tonemapper::Aces,
+                                Tonemapper::Aces => Tonemapper::Agx,
+                                Tonemapper::Agx => Tonemapper::None,
+                            };
+                        }
+                        Some(VirtualKeyCode::E) => {
+                            let settings = &mut renderer.post_process_settings;
+                            settings.exposure = match settings.exposure {
+                                Exposure::Manual(_) => PostProcessSettings::default().exposure,
+                                Exposure::Auto { .. } => Exposure::Manual(0.0),
+                            };
+                        }
+                        Some(VirtualKeyCode::B) => {
+                            let settings = &mut renderer.post_process_settings;
+                            settings.bloom = match settings.bloom {
+                                Some(_) => None,
+                                None => PostProcessSettings::default().bloom,
+                            };
+                        }
+                        Some(VirtualKeyCode::A) => {
+                            let mesh = &mut meshes[1];
+                            mesh.alpha_mode = match mesh.alpha_mode {
+                                AlphaMode::Opaque => AlphaMode::Mask { cutoff: 0.5 },
+                                AlphaMode::Mask { .. } => AlphaMode::AlphaToCoverage,
+                                AlphaMode::AlphaToCoverage => AlphaMode::Blend,
+                                AlphaMode::Blend => AlphaMode::Opaque,
+                            };
+                            log::info!("Alpha mode: {:?}", mesh.alpha_mode);
+                        }
+                        Some(VirtualKeyCode::O) => {
+                            let mut settings = renderer.render_settings();
+                            settings.ambient_occlusion = match settings.ambient_occlusion {
+                                Some(_) => None,
+                                None => RenderSettings::default().ambient_occlusion,
+                            };
+                            renderer.set_render_settings(&device, settings).unwrap();
+                        }
+                        Some(VirtualKeyCode::P) => {
+                            let mut settings = renderer.render_settings();
+                            settings.render_path = match settings.render_path {
+                                RenderPath::Forward => RenderPath::Deferred,
+                                RenderPath::Deferred => RenderPath::Forward,
+                            };
+                            // The deferred path doesn't support MSAA
+                            if settings.validate(allow_unvalidated_msaa).is_err() {
+                                settings.antialiasing = Antialiasing::Taa;
+                            }
+                            renderer.set_render_settings(&device, settings).unwrap();
+                            log::info!(
+                                "Render path: {:?}, antialiasing: {:?}",
+                                settings.render_path,
+                                settings.antialiasing
+                            );
+                        }
+                        Some(VirtualKeyCode::L) => {
+                            renderer.lights = if renderer.lights.len() > 1 {
+                                default_lights.clone()
+                            } else {
+                                light_grid()
+                            };
+                            log::info!("Lights: {}", renderer.lights.len());
+                        }
+                        Some(key @ VirtualKeyCode::F11) | Some(key @ VirtualKeyCode::F12) => {
+                            let format = if key == VirtualKeyCode::F12 {
+                                CaptureFormat::Png
+                            } else {
+                                CaptureFormat::Exr
+                            };
+                            let seconds = SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap()
+                                .as_secs();
+                            let path = PathBuf::from(format!(
+                                "screenshot-{}.{}",
+                                seconds,
+                                format.extension()
+                            ));
+                            log::info!("Saving {}", path.display());
+                            renderer.capture_frame(path, format);
+                        }
+                        Some(VirtualKeyCode::F1) => debug_ui.visible = !debug_ui.visible,
+                        Some(VirtualKeyCode::G) => show_gizmos = !show_gizmos,
+                        Some(VirtualKeyCode::V) => {
+                            let mut settings = renderer.render_settings();
+                            let current = DebugView::ALL
+                                .iter()
+                                .position(|&view| view == settings.debug_view)
+                                .unwrap();
+                            settings.debug_view =
+                                DebugView::ALL[(current + 1) % DebugView::ALL.len()];
+                            renderer.set_render_settings(&device, settings).unwrap();
+                            log::info!("Debug view: {:?}", settings.debug_view);
+                        }
+                        Some(VirtualKeyCode::W) => {
+                            let mut settings = renderer.render_settings();
+                            settings.wireframe_overlay = !settings.wireframe_overlay;
+                            renderer.set_render_settings(&device, settings).unwrap();
+                        }
+                        Some(VirtualKeyCode::H) => {
+                            wave_resolution = if wave_resolution >= 64 {
+                                8
+                            } else {
+                                wave_resolution * 2
+                            };
+                            let plane = wave_plane(wave_resolution);
+                            waves.rebuild(plane.vertices, plane.indices);
+                        }
+                        Some(VirtualKeyCode::J) => {
+                            let flipped = flip_diagonals(waves.indices());
+                            waves.write_indices(0, &flipped);
+                        }
+                        Some(VirtualKeyCode::F) => {
+                            frozen_frustum = match frozen_frustum {
+                                Some(_) => None,
+                                None => Some(renderer.view_projection()),
+                            };
+                        }
+                        Some(VirtualKeyCode::F3) => {
+                            renderer.profiler.show_overlay = !renderer.profiler.show_overlay;
+                        }
+                        Some(VirtualKeyCode::F4) => {
+                            log::info!("{}", renderer.profiler.summary());
+                            let path = format!(
+                                "trace-{}.json",
+                                SystemTime::now()
+                                    .duration_since(UNIX_EPOCH)
+                                    .unwrap()
+                                    .as_secs()
+                            );
+                            match renderer.profiler.write_chrome_trace(Path::new(&path)) {
+                                Ok(()) => log::info!("Saved {}", path),
+                                Err(error) => log::error!("{}", error),
+                            }
+                        }
+                        Some(VirtualKeyCode::F10) => {
+                            if renderer.is_recording() {
+                                log::warn!("Already recording");
+                            } else {
+                                log::info!("Recording {} frames to frames/", RECORDED_FRAMES);
+                                renderer.record_frames(
+                                    PathBuf::from("frames"),
+                                    RECORDED_FRAMES,
+                                    CaptureFormat::Png,
+                                );
+                            }
+                        }
                         _ => {}
                     }
                 }
@@ -142,25 +525,164 @@ fn main() {
 
         Event::MainEventsCleared => {
             const TARGET_TIME: Duration = Duration::from_nanos(16666670);
+            renderer.profiler.begin_frame();
+            renderer.profiler.begin_scope("update");
             while time_accumulator >= TARGET_TIME {
                 meshes.par_iter_mut().for_each(|mesh| {
                     mesh.instances.par_iter_mut().for_each(|transform| {
                         transform.rotation =
                             Rotor3::from_rotation_xz(0.5f32.to_radians()) * transform.rotation;
                     });
+                    mesh.animate(TARGET_TIME.as_secs_f32());
                 });
+                wave_time += TARGET_TIME.as_secs_f32();
+                let rippled = ripple(waves.vertices(), wave_time);
+                waves.write_vertices(0, &rippled);
                 time_accumulator -= TARGET_TIME;
             }
+            waves.upload(&queue, &device, &mut meshes[waves_mesh]);
+            renderer.profiler.end_scope();
             window.request_redraw();
         }
 
         Event::RedrawRequested(_) => {
+            // Blocks while the GPU is behind, so this is where GPU bound frames spend their time
+            renderer.profiler.begin_scope("acquire frame");
             let frame = swapchain.get_current_frame().unwrap().output;
+            renderer.profiler.end_scope();
+            for result in renderer.picks() {
+                selection = result.pick;
+                match result.pick {
+                    Some(pick) => {
+                        log::info!("Picked instance {} of mesh {}", pick.instance, pick.mesh)
+                    }
+                    None => log::info!("Picked nothing"),
+                }
+            }
+            if show_gizmos {
+                draw_gizmos(&mut renderer, &meshes);
+            }
+            // The selected mesh or instance may have been removed since
+            if let Some(instance) = selection.and_then(|pick| {
+                let mesh = meshes.get(pick.mesh)?;
+                Some((mesh, mesh.instances.get(pick.instance as usize)?))
+            }) {
+                let (center, radius) = instance.0.bounds().transformed_sphere(instance.1);
+                renderer
+                    .debug_draw
+                    .sphere(center, radius, Vec3::new(1.0, 1.0, 0.0));
+            }
+            if let Some(view_projection) = frozen_frustum {
+                renderer
+                    .debug_draw
+                    .frustum(view_projection, Vec3::new(1.0, 1.0, 0.0));
+            }
+            renderer.profiler.begin_scope("ui");
+            let ui_meshes = debug_ui
+                .run(|ctx| ui::scene_windows(ctx, &queue, &device, &mut renderer, &mut meshes));
+            renderer.profiler.end_scope();
+            renderer.profiler.begin_scope("render");
             let mut encoder =
                 device.create_command_encoder(&CommandEncoderDescriptor { label: None });
             renderer.render(&device, &mut encoder, &meshes, &frame.view);
+            debug_ui.render(&device, &queue, &mut encoder, ui_meshes, &frame.view);
+            renderer.profiler.end_scope();
+            renderer.profiler.begin_scope("submit");
             queue.submit(iter::once(encoder.finish()));
+            renderer.profiler.end_scope();
+            renderer.write_captures(&device);
+            renderer.read_picks();
         }
         _ => {}
     });
 }
+
+fn wave_plane(resolution: u32) -> Geometry {
+    Primitive::Plane {
+        size: 2.0,
+        subdivisions: resolution,
+    }
+    .geometry()
+}
+
+/// Splits every quad of a plane along its other diagonal.
+fn flip_diagonals(indices: &[u16]) -> Vec<u16> {
+    indices
+        .chunks(6)
+        .flat_map(|quad| {
+            if quad[3] == quad[0] {
+                let [a, b, d, c] = [quad[0], quad[1], quad[2], quad[5]];
+                [a, b, c, b, d, c]
+            } else {
+                let [a, b, c, d] = [quad[0], quad[1], quad[2], quad[4]];
+                [a, b, d, a, d, c]
+            }
+        })
+        .collect()
+}
+
+/// Moves the vertices of a flat plane up and down with two waves crossing it.
+fn ripple(vertices: &[Vertex], time: f32) -> Vec<Vertex> {
+    vertices
+        .iter()
+        .map(|vertex| {
+            let [x, _, z] = vertex.position;
+            let height = 0.05 * ((6.0 * x + 2.0 * time).sin() + (5.0 * z + 1.3 * time).sin());
+            let slope_x = 0.3 * (6.0 * x + 2.0 * time).cos();
+            let slope_z = 0.25 * (5.0 * z + 1.3 * time).cos();
+            let normal = Vec3::new(-slope_x, 1.0, -slope_z).normalized();
+            Vertex {
+                position: [x, height, z],
+                normal: normal.into(),
+                uv: vertex.uv,
+            }
+        })
+        .collect()
+}
+
+/// Bounding spheres and axes of every instance, and a small cube at each light.
+fn draw_gizmos(renderer: &mut Renderer, meshes: &[Mesh]) {
+    for mesh in meshes {
+        for instance in &mesh.instances {
+            let (center, radius) = mesh.bounds().transformed_sphere(instance);
+            renderer
+                .debug_draw
+                .sphere(center, radius, Vec3::new(0.0, 1.0, 0.5));
+            renderer.debug_draw.axes(instance, 1.0);
+        }
+    }
+    for light in &renderer.lights {
+        let half_size = Vec3::broadcast(0.05);
+        renderer.debug_draw.aabb(
+            light.position - half_size,
+            light.position + half_size,
+            light.color,
+        );
+    }
+}
+
+/// A grid of small colored lights just above the meshes, for testing scenes with many lights.
+fn light_grid() -> Vec<PointLight> {
+    const SIZE: usize = 32;
+    (0..SIZE * SIZE)
+        .map(|i| {
+            let (x, z) = ((i % SIZE) as f32, (i / SIZE) as f32);
+            let hue = i as f32 / (SIZE * SIZE) as f32 * 6.0;
+            PointLight {
+                position: Vec3::new(
+                    x / SIZE as f32 * 4.0 - 2.0,
+                    0.0,
+                    z / SIZE as f32 * 4.0 - 2.0,
+                ),
+                range: 0.5,
+                color: Vec3::new(
+                    (hue - 3.0).abs() - 1.0,
+                    2.0 - (hue - 2.0).abs(),
+                    2.0 - (hue - 4.0).abs(),
+                )
+                .clamped(Vec3::zero(), Vec3::one()),
+                intensity: 0.5,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,265 @@
+use crate::culling::Bounds;
+use crate::objects::{Geometry, Vertex};
+use bytemuck::{Pod, Zeroable};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::ops::Range;
+use std::str;
+use ultraviolet::Vec3;
+
+const MAGIC: [u8; 4] = *b"MWMF";
+const VERSION: u32 = 1;
+
+/// A mesh file is laid out as the header, `lod_count` index ranges stored as `[u32; 2]`,
+/// the vertices, the `u16` indices padded to 4 bytes, and finally the UTF-8 texture path.
+/// Everything is in the native byte order and 4 byte aligned so it can be used straight from a
+/// memory map, so files only load on machines with the byte order of the one that wrote them.
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct Header {
+    magic: [u8; 4],
+    version: u32,
+    vertex_count: u32,
+    index_count: u32,
+    lod_count: u32,
+    texture_path_length: u32,
+    bounding_sphere: [f32; 4],
+}
+
+pub struct MeshFile<'a> {
+    pub vertices: &'a [Vertex],
+    pub indices: &'a [u16],
+    pub lods: Vec<Range<u32>>,
+    pub bounds: Bounds,
+    pub texture_path: &'a str,
+}
+
+#[derive(Debug)]
+pub enum MeshFileError {
+    Io(io::Error),
+    InvalidMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    Misaligned,
+    NoLods,
+    LodOutOfRange,
+    IndexOutOfRange,
+    InvalidTexturePath,
+    Texture(ddsfile::Error),
+}
+
+impl fmt::Display for MeshFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{}", error),
+            Self::InvalidMagic => write!(f, "not a meshweaver mesh file"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported mesh file version {}", version)
+            }
+            Self::Truncated => write!(f, "mesh file is truncated"),
+            Self::Misaligned => write!(f, "mesh file data is not 4 byte aligned"),
+            Self::NoLods => write!(f, "mesh file has no levels of detail"),
+            Self::LodOutOfRange => write!(f, "level of detail is out of the index range"),
+            Self::IndexOutOfRange => write!(f, "index is out of the vertex range"),
+            Self::InvalidTexturePath => write!(f, "texture path is not valid UTF-8"),
+            Self::Texture(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for MeshFileError {}
+
+impl From<io::Error> for MeshFileError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<ddsfile::Error> for MeshFileError {
+    fn from(error: ddsfile::Error) -> Self {
+        Self::Texture(error)
+    }
+}
+
+pub fn parse(bytes: &[u8]) -> Result<MeshFile<'_>, MeshFileError> {
+    let mut remaining = bytes;
+    let header = *take::<Header>(&mut remaining, 1)?
+        .first()
+        .ok_or(MeshFileError::Truncated)?;
+    if header.magic != MAGIC {
+        return Err(MeshFileError::InvalidMagic);
+    }
+    if header.version != VERSION {
+        return Err(MeshFileError::UnsupportedVersion(header.version));
+    }
+
+    if header.lod_count == 0 {
+        return Err(MeshFileError::NoLods);
+    }
+
+    let lods: Vec<_> = take::<[u32; 2]>(&mut remaining, header.lod_count as usize)?
+        .iter()
+        .map(|&[start, end]| start..end)
+        .collect();
+    if lods
+        .iter()
+        .any(|lod| lod.start > lod.end || lod.end > header.index_count)
+    {
+        return Err(MeshFileError::LodOutOfRange);
+    }
+    let vertices = take::<Vertex>(&mut remaining, header.vertex_count as usize)?;
+    let indices = take::<u16>(&mut remaining, header.index_count as usize)?;
+    if indices
+        .iter()
+        .any(|&index| u32::from(index) >= header.vertex_count)
+    {
+        return Err(MeshFileError::IndexOutOfRange);
+    }
+    take::<u16>(&mut remaining, header.index_count as usize % 2)?;
+    let texture_path = take::<u8>(&mut remaining, header.texture_path_length as usize)?;
+    let texture_path =
+        str::from_utf8(texture_path).map_err(|_| MeshFileError::InvalidTexturePath)?;
+
+    let [x, y, z, radius] = header.bounding_sphere;
+    Ok(MeshFile {
+        vertices,
+        indices,
+        lods,
+        bounds: Bounds {
+            center: Vec3::new(x, y, z),
+            radius,
+        },
+        texture_path,
+    })
+}
+
+pub fn write<W: Write>(writer: &mut W, geometry: &Geometry, texture_path: &str) -> io::Result<()> {
+    let header = Header {
+        magic: MAGIC,
+        version: VERSION,
+        vertex_count: geometry.vertices.len() as u32,
+        index_count: geometry.indices.len() as u32,
+        lod_count: geometry.lods.len() as u32,
+        texture_path_length: texture_path.len() as u32,
+        bounding_sphere: [
+            geometry.bounds.center.x,
+            geometry.bounds.center.y,
+            geometry.bounds.center.z,
+            geometry.bounds.radius,
+        ],
+    };
+    let lods = geometry
+        .lods
+        .iter()
+        .map(|lod| [lod.start, lod.end])
+        .collect::<Vec<[u32; 2]>>();
+
+    writer.write_all(bytemuck::bytes_of(&header))?;
+    writer.write_all(bytemuck::cast_slice(&lods))?;
+    writer.write_all(bytemuck::cast_slice(&geometry.vertices))?;
+    writer.write_all(bytemuck::cast_slice(&geometry.indices))?;
+    if geometry.indices.len() % 2 == 1 {
+        writer.write_all(&[0; 2])?;
+    }
+    writer.write_all(texture_path.as_bytes())
+}
+
+fn take<'a, T: Pod>(bytes: &mut &'a [u8], count: usize) -> Result<&'a [T], MeshFileError> {
+    let length = count * mem::size_of::<T>();
+    if bytes.len() < length {
+        return Err(MeshFileError::Truncated);
+    }
+    let (taken, rest) = bytes.split_at(length);
+    *bytes = rest;
+    bytemuck::try_cast_slice(taken).map_err(|_| MeshFileError::Misaligned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects;
+
+    fn sphere() -> Geometry {
+        let (vertices, indices) = objects::test_sphere(32, 16);
+        Geometry::from_vertices_and_indices(vertices, indices, false)
+    }
+
+    /// Writes the mesh file into 4 byte aligned memory, like a memory map's.
+    fn write_aligned(geometry: &Geometry, texture_path: &str) -> Vec<u32> {
+        let mut bytes = Vec::new();
+        write(&mut bytes, geometry, texture_path).unwrap();
+        bytes.resize(bytes.len().div_ceil(4) * 4, 0);
+        bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_ne_bytes([word[0], word[1], word[2], word[3]]))
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let geometry = sphere();
+        let words = write_aligned(&geometry, "texture.dds");
+        let mesh_file = parse(bytemuck::cast_slice(&words)).unwrap();
+
+        assert_eq!(
+            bytemuck::cast_slice::<Vertex, u8>(mesh_file.vertices),
+            bytemuck::cast_slice::<Vertex, u8>(&geometry.vertices)
+        );
+        assert_eq!(mesh_file.indices, &geometry.indices[..]);
+        assert_eq!(mesh_file.lods, geometry.lods);
+        assert_eq!(mesh_file.bounds.center, geometry.bounds.center);
+        assert_eq!(mesh_file.bounds.radius, geometry.bounds.radius);
+        assert_eq!(mesh_file.texture_path, "texture.dds");
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let words = write_aligned(&sphere(), "texture.dds");
+        let bytes = bytemuck::cast_slice::<u32, u8>(&words);
+        for &length in &[0, mem::size_of::<Header>() + 4, bytes.len() - 16] {
+            assert!(matches!(
+                parse(&bytes[..length]),
+                Err(MeshFileError::Truncated)
+            ));
+        }
+    }
+
+    fn header_mut(words: &mut [u32]) -> &mut Header {
+        &mut bytemuck::cast_slice_mut(&mut words[..mem::size_of::<Header>() / 4])[0]
+    }
+
+    #[test]
+    fn rejects_invalid_tables() {
+        let geometry = sphere();
+        let words = write_aligned(&geometry, "");
+
+        let mut no_lods = words.clone();
+        header_mut(&mut no_lods).lod_count = 0;
+        assert!(matches!(
+            parse(bytemuck::cast_slice(&no_lods)),
+            Err(MeshFileError::NoLods)
+        ));
+
+        let mut lod_out_of_range = words.clone();
+        let lods_start = mem::size_of::<Header>() / 4;
+        lod_out_of_range[lods_start + 1] = geometry.indices.len() as u32 + 1;
+        assert!(matches!(
+            parse(bytemuck::cast_slice(&lod_out_of_range)),
+            Err(MeshFileError::LodOutOfRange)
+        ));
+
+        let mut index_out_of_range = words.clone();
+        header_mut(&mut index_out_of_range).vertex_count -= 1;
+        // Moves the indices one vertex earlier, with the last vertex's bytes as their padding
+        let vertices_start = lods_start + geometry.lods.len() * 2;
+        let vertex_words = mem::size_of::<Vertex>() / 4;
+        let last_vertex = vertices_start + (geometry.vertices.len() - 1) * vertex_words;
+        index_out_of_range.drain(last_vertex..last_vertex + vertex_words);
+        assert!(matches!(
+            parse(bytemuck::cast_slice(&index_out_of_range)),
+            Err(MeshFileError::IndexOutOfRange)
+        ));
+    }
+}
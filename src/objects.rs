@@ -1,7 +1,23 @@
+use crate::bvh::MeshBvh;
+use crate::culling::{Bounds, Frustum, LodSelector};
+use crate::gltf_loader;
+use crate::mesh_file::{self, MeshFileError};
+use crate::optimize::{self, OptimizationStats};
+use crate::simplify;
+use crate::skinning::Skin;
 use bytemuck::{Pod, Zeroable};
 use ddsfile::Dds;
+use memmap::Mmap;
 use obj::{load_obj, TexturedVertex};
-use std::io::{BufRead, Read};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::iter;
+use std::mem;
+use std::ops::Range;
+use std::path::Path;
 use ultraviolet::{Mat4, Similarity3};
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use wgpu::*;
@@ -9,43 +25,368 @@ use wgpu::*;
 #[repr(C)]
 #[derive(Copy, Clone, Pod, Zeroable)]
 pub struct Vertex {
-    position: [f32; 3],
-    normal: [f32; 3],
-    uv: [f32; 2],
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+/// Closed unit sphere with `segments` around and `rings` from pole to pole, without seams.
+#[cfg(test)]
+pub fn test_sphere(segments: u16, rings: u16) -> (Vec<Vertex>, Vec<u16>) {
+    let index = |ring: u16, segment: u16| 1 + (ring - 1) * segments + segment % segments;
+    let mut vertices = vec![[0.0, 1.0, 0.0]];
+    for ring in 1..rings {
+        let polar = ring as f32 / rings as f32 * std::f32::consts::PI;
+        for segment in 0..segments {
+            let azimuth = segment as f32 / segments as f32 * std::f32::consts::TAU;
+            vertices.push([
+                polar.sin() * azimuth.cos(),
+                polar.cos(),
+                polar.sin() * azimuth.sin(),
+            ]);
+        }
+    }
+    vertices.push([0.0, -1.0, 0.0]);
+    let south = vertices.len() as u16 - 1;
+
+    let mut indices = Vec::new();
+    for segment in 0..segments {
+        indices.extend_from_slice(&[0, index(1, segment + 1), index(1, segment)]);
+        for ring in 1..rings - 1 {
+            let (a, b) = (index(ring, segment), index(ring, segment + 1));
+            let (c, d) = (index(ring + 1, segment), index(ring + 1, segment + 1));
+            indices.extend_from_slice(&[a, b, d, a, d, c]);
+        }
+        let (a, b) = (index(rings - 1, segment), index(rings - 1, segment + 1));
+        indices.extend_from_slice(&[a, b, south]);
+    }
+    let vertices = vertices
+        .into_iter()
+        .map(|position| Vertex {
+            position,
+            normal: position,
+            uv: [0.0, 0.0],
+        })
+        .collect();
+    (vertices, indices)
+}
+
+/// Joints and weights of a skinned vertex, in a second vertex buffer next to its `Vertex`. As
+/// large as a `Vertex`, see `Mesh::skin_vertices`.
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct SkinVertex {
+    /// Indices into the skeleton's joints.
+    pub joints: [u32; 4],
+    /// Add up to 1, or are all 0 for vertices no joint moves.
+    pub weights: [f32; 4],
+}
+
+/// CPU side geometry of a mesh, with the index buffers of every level of detail concatenated.
+pub struct Geometry {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u16>,
+    pub lods: Vec<Range<u32>>,
+    pub bounds: Bounds,
+    pub optimization_stats: Option<OptimizationStats>,
+}
+
+impl Geometry {
+    pub fn from_obj<F: BufRead>(file: F, optimize: bool) -> Self {
+        let obj = load_obj::<TexturedVertex, _, u16>(file).unwrap();
+        let vertices = obj
+            .vertices
+            .into_iter()
+            .map(|vertex| Vertex {
+                position: vertex.position,
+                normal: vertex.normal,
+                uv: [vertex.texture[0], vertex.texture[1]],
+            })
+            .collect::<Vec<Vertex>>();
+        Self::from_vertices_and_indices(vertices, obj.indices, optimize)
+    }
+
+    /// Runs the optional optimization pass, then generates the levels of detail and bounds.
+    pub fn from_vertices_and_indices(
+        mut vertices: Vec<Vertex>,
+        mut indices: Vec<u16>,
+        optimize: bool,
+    ) -> Self {
+        let optimization_stats = if optimize {
+            Some(optimize::optimize_mesh(&mut vertices, &mut indices))
+        } else {
+            None
+        };
+        let (indices, lods) = simplify::generate_lods(&vertices, &indices);
+        let bounds = Bounds::from_vertices(&vertices);
+        Self {
+            vertices,
+            indices,
+            lods,
+            bounds,
+            optimization_stats,
+        }
+    }
+}
+
+/// How the alpha channel of a mesh's texture is used.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AlphaMode {
+    /// Alpha is ignored.
+    Opaque,
+    /// Fragments with an alpha below `cutoff` are discarded.
+    Mask { cutoff: f32 },
+    /// Alpha controls how many MSAA samples are covered, smoothing the edges of cutouts. Falls
+    /// back to `Mask` with a cutoff of 0.5 without multisampling.
+    AlphaToCoverage,
+    /// Blended over the opaque meshes, drawn back to front after them without writing depth.
+    Blend,
+}
+
+impl AlphaMode {
+    /// Alpha below which the shaders discard fragments, 0 keeps everything.
+    pub fn cutoff(self) -> f32 {
+        match self {
+            Self::Mask { cutoff } => cutoff,
+            Self::Opaque | Self::AlphaToCoverage | Self::Blend => 0.0,
+        }
+    }
 }
 
 pub struct Mesh {
     data: MeshData,
     pub instances: Vec<Similarity3>,
+    pub alpha_mode: AlphaMode,
 }
 
 impl Mesh {
+    /// With `retain_geometry` a copy of the geometry is kept on the CPU after uploading it, see
+    /// `geometry`.
     pub fn from_obj_and_texture<F: BufRead, T: Read>(
         queue: &Queue,
         device: &Device,
         file: F,
         dxt5_texture: &mut T,
+        optimize: bool,
+        retain_geometry: bool,
+    ) -> Self {
+        Self::with_texture(
+            device,
+            Geometry::from_obj(file, optimize),
+            load_dxt5_texture(queue, device, dxt5_texture).unwrap(),
+            retain_geometry,
+        )
+    }
+
+    /// Uploads generated geometry, such as a `Primitive`'s, with a plain white texture.
+    pub fn from_geometry(
+        queue: &Queue,
+        device: &Device,
+        geometry: Geometry,
+        retain_geometry: bool,
+    ) -> Self {
+        Self::with_texture(
+            device,
+            geometry,
+            white_texture(queue, device),
+            retain_geometry,
+        )
+    }
+
+    /// Uploads generated geometry with a DXT5 texture.
+    pub fn from_geometry_and_texture<T: Read>(
+        queue: &Queue,
+        device: &Device,
+        geometry: Geometry,
+        dxt5_texture: &mut T,
+        retain_geometry: bool,
+    ) -> Self {
+        Self::with_texture(
+            device,
+            geometry,
+            load_dxt5_texture(queue, device, dxt5_texture).unwrap(),
+            retain_geometry,
+        )
+    }
+
+    /// A mesh with room for `vertex_capacity` vertices and `index_capacity` indices and nothing to
+    /// draw yet, for a `DynamicMesh` to fill.
+    pub fn with_capacity(
+        queue: &Queue,
+        device: &Device,
+        vertex_capacity: usize,
+        index_capacity: usize,
+    ) -> Self {
+        let geometry = Geometry {
+            vertices: vec![Vertex::zeroed(); vertex_capacity],
+            indices: vec![0; index_capacity],
+            lods: iter::once(0..0).collect(),
+            bounds: Bounds::from_vertices(&[]),
+            optimization_stats: None,
+        };
+        Self::with_texture(device, geometry, white_texture(queue, device), false)
+    }
+
+    fn with_texture(
+        device: &Device,
+        geometry: Geometry,
+        texture: TextureView,
+        retain_geometry: bool,
     ) -> Self {
+        let mut data = MeshData::new(
+            device,
+            &geometry.vertices,
+            &[],
+            &geometry.indices,
+            geometry.lods.clone(),
+            geometry.bounds,
+            texture,
+        );
+        data.optimization_stats = geometry.optimization_stats;
+        if retain_geometry {
+            data.geometry = Some(geometry);
+        }
         Self {
-            data: MeshData::from_obj_and_texture(queue, device, file, dxt5_texture),
+            data,
             instances: Vec::new(),
+            alpha_mode: AlphaMode::Opaque,
         }
     }
 
-    pub fn create_instances_bind_group(
-        &self,
+    /// Loads a mesh written by `meshweaver convert`. The file is memory mapped and its vertex and
+    /// index data is uploaded without any parsing, and only copied with `retain_geometry`. The
+    /// texture path is relative to the file.
+    pub fn from_binary(
+        queue: &Queue,
         device: &Device,
-        instances_bind_group_layout: &BindGroupLayout,
-    ) -> BindGroup {
-        let instance_data = self
+        path: &Path,
+        retain_geometry: bool,
+    ) -> Result<Self, MeshFileError> {
+        let file = File::open(path)?;
+        let mapped_file = unsafe { Mmap::map(&file)? };
+        let mesh_file = mesh_file::parse(&mapped_file)?;
+        let texture_path = path
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(mesh_file.texture_path);
+        let mut texture = BufReader::new(File::open(texture_path)?);
+        let mut data = MeshData::new(
+            device,
+            mesh_file.vertices,
+            &[],
+            mesh_file.indices,
+            mesh_file.lods.clone(),
+            mesh_file.bounds,
+            load_dxt5_texture(queue, device, &mut texture)?,
+        );
+        if retain_geometry {
+            data.geometry = Some(Geometry {
+                vertices: mesh_file.vertices.to_vec(),
+                indices: mesh_file.indices.to_vec(),
+                lods: mesh_file.lods,
+                bounds: mesh_file.bounds,
+                optimization_stats: None,
+            });
+        }
+        Ok(Self {
+            data,
+            instances: Vec::new(),
+            alpha_mode: AlphaMode::Opaque,
+        })
+    }
+
+    /// Loads every mesh of a glTF or GLB file's scene into a single mesh with a plain white
+    /// texture. If the scene has a skin, its skeleton and animations are loaded along with it and
+    /// the instances are posed by `animate`.
+    pub fn from_gltf(queue: &Queue, device: &Device, path: &Path) -> Result<Self, Box<dyn Error>> {
+        let scene = gltf_loader::load_skinned_gltf(path)?;
+        // Not optimized, that would reorder the vertices without their skin vertices
+        let geometry = Geometry::from_vertices_and_indices(scene.vertices, scene.indices, false);
+        let mut data = MeshData::new(
+            device,
+            &geometry.vertices,
+            &scene.skin_vertices,
+            &geometry.indices,
+            geometry.lods,
+            geometry.bounds,
+            white_texture(queue, device),
+        );
+        data.skin = scene.skin;
+        Ok(Self {
+            data,
+            instances: Vec::new(),
+            alpha_mode: AlphaMode::Opaque,
+        })
+    }
+
+    /// Returns the indices of the instances whose bounding sphere intersects the frustum,
+    /// grouped by the level of detail they should be drawn with.
+    pub fn cull_instances(&self, frustum: &Frustum, lod_selector: &LodSelector) -> Vec<Vec<u32>> {
+        let lod_count = self.data.lods.len();
+        self.instances
+            .par_iter()
+            .enumerate()
+            .fold(
+                || vec![Vec::new(); lod_count],
+                |mut lods, (i, transform)| {
+                    let (center, radius) = self.data.bounds.transformed_sphere(transform);
+                    if frustum.intersects_sphere(center, radius) {
+                        lods[lod_selector.select(center, radius, lod_count)].push(i as u32);
+                    }
+                    lods
+                },
+            )
+            .reduce(
+                || vec![Vec::new(); lod_count],
+                |mut lods, other| {
+                    for (lod, other) in lods.iter_mut().zip(other) {
+                        lod.extend(other);
+                    }
+                    lods
+                },
+            )
+    }
+
+    /// The instance transforms, followed by the joint matrices of each instance if the mesh is
+    /// skinned. The joint matrices share the buffer to stay within the four storage buffers per
+    /// shader stage the wireframe view already uses.
+    pub fn create_transforms_buffer(&self, device: &Device) -> Buffer {
+        let mut instance_data = self
             .instances
             .iter()
             .map(|transform| transform.into_homogeneous_matrix())
             .collect::<Vec<Mat4>>();
-        let instance_buffer = device.create_buffer_init(&BufferInitDescriptor {
+        if let Some(skin) = &self.data.skin {
+            for instance in 0..self.instances.len() {
+                instance_data.extend_from_slice(skin.instance_joint_matrices(instance));
+            }
+        }
+        device.create_buffer_init(&BufferInitDescriptor {
             label: None,
             contents: bytemuck::cast_slice(&instance_data),
             usage: BufferUsage::STORAGE,
+        })
+    }
+
+    /// `alpha_mode` is the mode actually rendered with, which can differ from `self.alpha_mode`
+    /// when the renderer falls back to another one.
+    pub fn create_instances_bind_group(
+        &self,
+        device: &Device,
+        instances_bind_group_layout: &BindGroupLayout,
+        transforms_buffer: &Buffer,
+        visible_instances_buffer: &Buffer,
+        alpha_mode: AlphaMode,
+    ) -> BindGroup {
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::bytes_of(&MeshUniforms {
+                alpha_cutoff: alpha_mode.cutoff(),
+                joint_count: self.data.skin.as_ref().map_or(0, Skin::joint_count) as u32,
+                first_joint: self.instances.len() as u32,
+                skin_offset: self.data.skin_offset,
+            }),
+            usage: BufferUsage::UNIFORM,
         });
         device.create_bind_group(&BindGroupDescriptor {
             label: None,
@@ -53,7 +394,7 @@ impl Mesh {
             entries: &[
                 BindGroupEntry {
                     binding: 0,
-                    resource: BindingResource::Buffer(instance_buffer.slice(..)),
+                    resource: BindingResource::Buffer(transforms_buffer.slice(..)),
                 },
                 BindGroupEntry {
                     binding: 1,
@@ -63,6 +404,14 @@ impl Mesh {
                     binding: 2,
                     resource: BindingResource::Sampler(&self.data.sampler),
                 },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Buffer(visible_instances_buffer.slice(..)),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::Buffer(uniform_buffer.slice(..)),
+                },
             ],
         })
     }
@@ -75,79 +424,165 @@ impl Mesh {
         &self.data.index_buffer
     }
 
-    pub fn index_count(&self) -> u32 {
-        self.data.index_count
+    /// The `SkinVertex` of each vertex, for the second vertex buffer. Meshes without a skin return
+    /// their vertices instead, which are never read as joints as the mesh has none.
+    pub fn skin_vertices(&self) -> BufferSlice<'_> {
+        let offset = self.data.skin_offset as usize * mem::size_of::<Vertex>();
+        self.data.vertex_buffer.slice(offset as BufferAddress..)
+    }
+
+    pub fn skin_mut(&mut self) -> Option<&mut Skin> {
+        self.data.skin.as_mut()
+    }
+
+    /// Advances the animation of each instance by `delta_time` seconds, if the mesh is skinned.
+    pub fn animate(&mut self, delta_time: f32) {
+        if let Some(skin) = &mut self.data.skin {
+            skin.update(delta_time, self.instances.len());
+        }
+    }
+
+    /// Replaces the vertex and index buffers with larger ones, which have to be written again.
+    pub fn reallocate_buffers(
+        &mut self,
+        device: &Device,
+        vertex_capacity: usize,
+        index_capacity: usize,
+    ) {
+        let (vertex_buffer, index_buffer) =
+            create_geometry_buffers(device, vertex_capacity, index_capacity);
+        self.data.vertex_buffer = vertex_buffer;
+        self.data.index_buffer = index_buffer;
+    }
+
+    /// Describes the geometry written straight into the buffers: `indices` are all drawn as a
+    /// single level of detail, and the bounds and BVH are rebuilt from the same vertices.
+    pub fn set_written_geometry(&mut self, vertices: &[Vertex], indices: &[u16]) {
+        self.data.lods = iter::once(0..indices.len() as u32).collect();
+        self.data.bounds = Bounds::from_vertices(vertices);
+        self.data.bvh = MeshBvh::new(vertices, indices);
+    }
+
+    /// Like `set_written_geometry` when only vertices were written, which keeps the triangles of
+    /// the BVH and only refits its bounds.
+    pub fn set_written_vertices(&mut self, vertices: &[Vertex]) {
+        self.data.bounds = Bounds::from_vertices(vertices);
+        self.data.bvh.refit(vertices);
+    }
+
+    /// Ranges of the index buffer holding each level of detail, from most to least detailed.
+    pub fn lods(&self) -> &[Range<u32>] {
+        &self.data.lods
+    }
+
+    pub fn bounds(&self) -> &Bounds {
+        &self.data.bounds
+    }
+
+    /// Triangles of the most detailed level of detail, for ray casts on the CPU.
+    pub fn bvh(&self) -> &MeshBvh {
+        &self.data.bvh
+    }
+
+    /// Before and after statistics of the load time optimization pass, if it was run.
+    pub fn optimization_stats(&self) -> Option<OptimizationStats> {
+        self.data.optimization_stats
+    }
+
+    /// The CPU copy of the geometry, if the mesh was loaded with `retain_geometry`.
+    pub fn geometry(&self) -> Option<&Geometry> {
+        self.data.geometry.as_ref()
+    }
+
+    /// Edits a range of the retained vertices and writes them over the same range of the vertex
+    /// buffer, then updates the bounds and BVH. Every level of detail shares the vertices.
+    /// Panics if the mesh doesn't retain its geometry, or if `range` is out of its vertices'
+    /// bounds.
+    pub fn edit_vertices(
+        &mut self,
+        queue: &Queue,
+        range: Range<usize>,
+        edit: impl FnOnce(&mut [Vertex]),
+    ) {
+        let data = &mut self.data;
+        let geometry = data
+            .geometry
+            .as_mut()
+            .expect("the mesh doesn't retain its geometry");
+        edit(&mut geometry.vertices[range.clone()]);
+        queue.write_buffer(
+            &data.vertex_buffer,
+            (range.start * mem::size_of::<Vertex>()) as BufferAddress,
+            bytemuck::cast_slice(&geometry.vertices[range]),
+        );
+        geometry.bounds = Bounds::from_vertices(&geometry.vertices);
+        data.bounds = geometry.bounds;
+        data.bvh = MeshBvh::new(
+            &geometry.vertices,
+            most_detailed_lod(&geometry.indices, &geometry.lods),
+        );
     }
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct MeshUniforms {
+    alpha_cutoff: f32,
+    /// 0 for meshes without a skin.
+    joint_count: u32,
+    /// Index of the first joint matrix in the transforms buffer.
+    first_joint: u32,
+    /// Index of the first `SkinVertex` in the vertex buffer.
+    skin_offset: u32,
+}
+
 struct MeshData {
     vertex_buffer: Buffer,
     index_buffer: Buffer,
-    index_count: u32,
+    lods: Vec<Range<u32>>,
+    bounds: Bounds,
+    optimization_stats: Option<OptimizationStats>,
+    bvh: MeshBvh,
+    geometry: Option<Geometry>,
+    skin: Option<Skin>,
+    skin_offset: u32,
 
     texture: TextureView,
     sampler: Sampler,
 }
 
 impl MeshData {
-    fn from_obj_and_texture<F: BufRead, T: Read>(
-        queue: &Queue,
+    /// `skin_vertices` are empty for meshes without a skin.
+    fn new(
         device: &Device,
-        file: F,
-        dxt5_texture: &mut T,
+        vertices: &[Vertex],
+        skin_vertices: &[SkinVertex],
+        indices: &[u16],
+        lods: Vec<Range<u32>>,
+        bounds: Bounds,
+        texture: TextureView,
     ) -> Self {
-        let obj = load_obj::<TexturedVertex, _, u16>(file).unwrap();
-        let vertices = obj
-            .vertices
-            .into_iter()
-            .map(|vertex| Vertex {
-                position: vertex.position,
-                normal: vertex.normal,
-                uv: [vertex.texture[0], vertex.texture[1]],
-            })
-            .collect::<Vec<Vertex>>();
+        // Skinned meshes keep their skin vertices right after the vertices
+        let mut vertex_data = Cow::Borrowed(bytemuck::cast_slice(vertices));
+        if !skin_vertices.is_empty() {
+            vertex_data
+                .to_mut()
+                .extend_from_slice(bytemuck::cast_slice(skin_vertices));
+        }
         let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: None,
-            contents: bytemuck::cast_slice(&vertices),
-            usage: BufferUsage::VERTEX,
+            contents: &vertex_data,
+            // Also read as storage buffers by the wireframe debug view, and written to by
+            // `Mesh::edit_vertices` and `DynamicMesh`
+            usage: VERTEX_BUFFER_USAGE,
         });
         let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: None,
-            contents: bytemuck::cast_slice(&obj.indices),
-            usage: BufferUsage::INDEX,
+            contents: bytemuck::cast_slice(indices),
+            usage: INDEX_BUFFER_USAGE,
         });
-        let index_count = obj.indices.len() as u32;
+        let bvh = MeshBvh::new(vertices, most_detailed_lod(indices, &lods));
 
-        let texture_data = Dds::read(dxt5_texture).unwrap();
-        let texture_size = Extent3d {
-            width: texture_data.get_width(),
-            height: texture_data.get_height(),
-            depth: texture_data.get_depth(),
-        };
-        let texture = device.create_texture(&TextureDescriptor {
-            label: None,
-            size: texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::Bc3RgbaUnormSrgb,
-            usage: TextureUsage::SAMPLED | TextureUsage::COPY_DST,
-        });
-        queue.write_texture(
-            TextureCopyView {
-                texture: &texture,
-                mip_level: 0,
-                origin: Origin3d::ZERO,
-            },
-            texture_data.get_data(0).unwrap(),
-            TextureDataLayout {
-                offset: 0,
-                bytes_per_row: texture_data.get_width() * 4,
-                rows_per_image: 0,
-            },
-            texture_size,
-        );
-        let texture = texture.create_view(&TextureViewDescriptor::default());
         let sampler = device.create_sampler(&SamplerDescriptor {
             label: None,
             address_mode_u: AddressMode::ClampToEdge,
@@ -165,10 +600,114 @@ impl MeshData {
         Self {
             vertex_buffer,
             index_buffer,
-            index_count,
+            lods,
+            bounds,
+            optimization_stats: None,
+            bvh,
+            geometry: None,
+            skin: None,
+            skin_offset: if skin_vertices.is_empty() {
+                0
+            } else {
+                vertices.len() as u32
+            },
 
             texture,
             sampler,
         }
     }
 }
+
+const VERTEX_BUFFER_USAGE: BufferUsage = BufferUsage::from_bits_truncate(
+    BufferUsage::VERTEX.bits() | BufferUsage::STORAGE.bits() | BufferUsage::COPY_DST.bits(),
+);
+const INDEX_BUFFER_USAGE: BufferUsage = BufferUsage::from_bits_truncate(
+    BufferUsage::INDEX.bits() | BufferUsage::STORAGE.bits() | BufferUsage::COPY_DST.bits(),
+);
+
+fn create_geometry_buffers(
+    device: &Device,
+    vertex_capacity: usize,
+    index_capacity: usize,
+) -> (Buffer, Buffer) {
+    let vertex_buffer = device.create_buffer(&BufferDescriptor {
+        label: None,
+        size: (vertex_capacity * mem::size_of::<Vertex>()) as BufferAddress,
+        usage: VERTEX_BUFFER_USAGE,
+        mapped_at_creation: false,
+    });
+    // Copies have to be a multiple of 4 bytes, so there's always room for an even count
+    let index_buffer = device.create_buffer(&BufferDescriptor {
+        label: None,
+        size: ((index_capacity + index_capacity % 2) * mem::size_of::<u16>()) as BufferAddress,
+        usage: INDEX_BUFFER_USAGE,
+        mapped_at_creation: false,
+    });
+    (vertex_buffer, index_buffer)
+}
+
+fn most_detailed_lod<'a>(indices: &'a [u16], lods: &[Range<u32>]) -> &'a [u16] {
+    lods.first()
+        .map_or(&[], |lod| &indices[lod.start as usize..lod.end as usize])
+}
+
+fn load_dxt5_texture<T: Read>(
+    queue: &Queue,
+    device: &Device,
+    dxt5_texture: &mut T,
+) -> Result<TextureView, ddsfile::Error> {
+    let texture_data = Dds::read(dxt5_texture)?;
+    Ok(create_dxt5_texture(
+        queue,
+        device,
+        texture_data.get_width(),
+        texture_data.get_height(),
+        texture_data.get_data(0)?,
+    ))
+}
+
+/// A single white block, for meshes without a texture of their own.
+fn white_texture(queue: &Queue, device: &Device) -> TextureView {
+    // Both alpha endpoints and both color endpoints are white
+    let block = [255, 255, 0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 0];
+    create_dxt5_texture(queue, device, 4, 4, &block)
+}
+
+fn create_dxt5_texture(
+    queue: &Queue,
+    device: &Device,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> TextureView {
+    let texture_size = Extent3d {
+        width,
+        height,
+        depth: 1,
+    };
+    let texture = device.create_texture(&TextureDescriptor {
+        label: None,
+        size: texture_size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Bc3RgbaUnormSrgb,
+        usage: TextureUsage::SAMPLED | TextureUsage::COPY_DST,
+    });
+    queue.write_texture(
+        TextureCopyView {
+            texture: &texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+        },
+        data,
+        TextureDataLayout {
+            offset: 0,
+            // 16 bytes per block of 4x4 pixels
+            bytes_per_row: width * 4,
+            rows_per_image: 0,
+        },
+        texture_size,
+    );
+    texture.create_view(&TextureViewDescriptor::default())
+}
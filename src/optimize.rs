@@ -0,0 +1,219 @@
+use crate::objects::Vertex;
+use std::collections::HashMap;
+use std::fmt;
+use ultraviolet::Vec3;
+
+/// Size of the simulated post-transform vertex cache, matching common desktop GPUs.
+const CACHE_SIZE: usize = 16;
+
+#[derive(Copy, Clone, Debug)]
+pub struct OptimizationStats {
+    pub vertex_count_before: usize,
+    pub vertex_count_after: usize,
+    pub acmr_before: f32,
+    pub acmr_after: f32,
+}
+
+impl fmt::Display for OptimizationStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {} vertices, ACMR {:.3} -> {:.3}",
+            self.vertex_count_before, self.vertex_count_after, self.acmr_before, self.acmr_after,
+        )
+    }
+}
+
+/// Welds duplicate vertices, reorders triangles for the vertex cache (Tipsify) and then for
+/// overdraw, and finally reorders vertices in the order they are first fetched.
+pub fn optimize_mesh(vertices: &mut Vec<Vertex>, indices: &mut Vec<u16>) -> OptimizationStats {
+    let vertex_count_before = vertices.len();
+    let acmr_before = acmr(indices, vertices.len());
+
+    deduplicate_vertices(vertices, indices);
+    let clusters = optimize_vertex_cache(indices, vertices.len());
+    optimize_overdraw(vertices, indices, &clusters);
+    optimize_vertex_fetch(vertices, indices);
+
+    OptimizationStats {
+        vertex_count_before,
+        vertex_count_after: vertices.len(),
+        acmr_before,
+        acmr_after: acmr(indices, vertices.len()),
+    }
+}
+
+/// Average number of vertices transformed per triangle with a FIFO cache of `CACHE_SIZE`.
+pub fn acmr(indices: &[u16], vertex_count: usize) -> f32 {
+    if indices.is_empty() {
+        return 0.0;
+    }
+    let mut cache_timestamps = vec![None; vertex_count];
+    let mut timestamp = 0usize;
+    for &index in indices {
+        let cached = match cache_timestamps[index as usize] {
+            Some(cached_at) => timestamp - cached_at < CACHE_SIZE,
+            None => false,
+        };
+        if !cached {
+            cache_timestamps[index as usize] = Some(timestamp);
+            timestamp += 1;
+        }
+    }
+    timestamp as f32 / (indices.len() / 3) as f32
+}
+
+fn deduplicate_vertices(vertices: &mut Vec<Vertex>, indices: &mut [u16]) {
+    let mut unique_vertices = Vec::with_capacity(vertices.len());
+    let mut unique_index_of = HashMap::new();
+    let remap = vertices
+        .iter()
+        .map(|vertex| {
+            let key = bytemuck::cast::<Vertex, [u32; 8]>(*vertex);
+            *unique_index_of.entry(key).or_insert_with(|| {
+                unique_vertices.push(*vertex);
+                (unique_vertices.len() - 1) as u16
+            })
+        })
+        .collect::<Vec<u16>>();
+    for index in indices.iter_mut() {
+        *index = remap[*index as usize];
+    }
+    *vertices = unique_vertices;
+}
+
+/// Tipsify, from "Fast Triangle Reordering for Vertex Locality and Reduced Overdraw" by Sander,
+/// Nehab and Barczak. Returns the triangle offsets at which the cache was flushed, which are used
+/// as cluster boundaries by `optimize_overdraw`.
+fn optimize_vertex_cache(indices: &mut Vec<u16>, vertex_count: usize) -> Vec<usize> {
+    let triangle_count = indices.len() / 3;
+    let mut adjacency = vec![Vec::new(); vertex_count];
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for &vertex in corners {
+            adjacency[vertex as usize].push(triangle);
+        }
+    }
+    let mut live_triangles = adjacency
+        .iter()
+        .map(|triangles| triangles.len())
+        .collect::<Vec<usize>>();
+    let mut cache_timestamps = vec![0; vertex_count];
+    let mut emitted = vec![false; triangle_count];
+    let mut dead_ends = Vec::new();
+    let mut timestamp = CACHE_SIZE + 1;
+    let mut cursor = 0;
+
+    let mut output = Vec::with_capacity(indices.len());
+    let mut cluster_starts = vec![0];
+    let mut fanning_vertex = (0..vertex_count).find(|&vertex| live_triangles[vertex] > 0);
+    while let Some(vertex) = fanning_vertex {
+        let mut candidates = Vec::new();
+        for &triangle in &adjacency[vertex] {
+            if emitted[triangle] {
+                continue;
+            }
+            emitted[triangle] = true;
+            for &corner in &indices[triangle * 3..triangle * 3 + 3] {
+                output.push(corner);
+                dead_ends.push(corner);
+                candidates.push(corner);
+                live_triangles[corner as usize] -= 1;
+                if timestamp - cache_timestamps[corner as usize] > CACHE_SIZE {
+                    cache_timestamps[corner as usize] = timestamp;
+                    timestamp += 1;
+                }
+            }
+        }
+
+        // Prefer the candidate that will still be in the cache after emitting all of its
+        // remaining triangles, picking the oldest such vertex.
+        fanning_vertex = candidates
+            .iter()
+            .filter(|&&candidate| live_triangles[candidate as usize] > 0)
+            .filter_map(|&candidate| {
+                let age = timestamp - cache_timestamps[candidate as usize];
+                if age + 2 * live_triangles[candidate as usize] <= CACHE_SIZE {
+                    Some((age, candidate as usize))
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|&(age, _)| age)
+            .map(|(_, candidate)| candidate)
+            .or_else(|| {
+                cluster_starts.push(output.len() / 3);
+                while let Some(dead_end) = dead_ends.pop() {
+                    if live_triangles[dead_end as usize] > 0 {
+                        return Some(dead_end as usize);
+                    }
+                }
+                while cursor < vertex_count {
+                    if live_triangles[cursor] > 0 {
+                        return Some(cursor);
+                    }
+                    cursor += 1;
+                }
+                None
+            });
+    }
+
+    *indices = output;
+    cluster_starts.dedup();
+    cluster_starts.retain(|&start| start < triangle_count);
+    cluster_starts
+}
+
+/// Sorts the clusters produced by `optimize_vertex_cache` so the ones facing away from the mesh
+/// center are drawn first and occlude the rest, keeping each cluster's cache-friendly order.
+fn optimize_overdraw(vertices: &[Vertex], indices: &mut Vec<u16>, cluster_starts: &[usize]) {
+    let position = |index: u16| Vec3::from(vertices[index as usize].position);
+    let mesh_centroid = indices
+        .iter()
+        .fold(Vec3::zero(), |sum, &index| sum + position(index))
+        / indices.len().max(1) as f32;
+
+    let triangle_count = indices.len() / 3;
+    let mut clusters = cluster_starts
+        .iter()
+        .zip(cluster_starts.iter().skip(1).chain(Some(&triangle_count)))
+        .map(|(&start, &end)| {
+            let (centroid_sum, normal_sum) = indices[start * 3..end * 3].chunks_exact(3).fold(
+                (Vec3::zero(), Vec3::zero()),
+                |(centroid_sum, normal_sum), triangle| {
+                    let (p0, p1, p2) = (
+                        position(triangle[0]),
+                        position(triangle[1]),
+                        position(triangle[2]),
+                    );
+                    (
+                        centroid_sum + p0 + p1 + p2,
+                        normal_sum + (p1 - p0).cross(p2 - p0),
+                    )
+                },
+            );
+            let centroid = centroid_sum / ((end - start) * 3) as f32;
+            let occlusion_potential = (centroid - mesh_centroid).dot(normal_sum.normalized());
+            (occlusion_potential, start..end)
+        })
+        .collect::<Vec<_>>();
+    clusters.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    *indices = clusters
+        .into_iter()
+        .flat_map(|(_, triangles)| indices[triangles.start * 3..triangles.end * 3].to_vec())
+        .collect();
+}
+
+/// Reorders vertices in the order the index buffer first references them and drops unused ones.
+fn optimize_vertex_fetch(vertices: &mut Vec<Vertex>, indices: &mut [u16]) {
+    let mut remap = vec![None; vertices.len()];
+    let mut reordered_vertices = Vec::with_capacity(vertices.len());
+    for index in indices.iter_mut() {
+        let new_index = *remap[*index as usize].get_or_insert_with(|| {
+            reordered_vertices.push(vertices[*index as usize]);
+            (reordered_vertices.len() - 1) as u16
+        });
+        *index = new_index;
+    }
+    *vertices = reordered_vertices;
+}
@@ -0,0 +1,310 @@
+use crate::objects::{SkinVertex, Vertex};
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+
+/// Mesh and instance IDs, the mesh ID is one more than its index so 0 means nothing was hit.
+const ID_FORMAT: TextureFormat = TextureFormat::Rg32Uint;
+
+/// What was drawn at a pixel.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Pick {
+    /// Index into the meshes passed to `Renderer::render`.
+    pub mesh: usize,
+    /// Index into the mesh's instances.
+    pub instance: u32,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PickResult {
+    pub x: u32,
+    pub y: u32,
+    /// `None` where only the background was drawn.
+    pub pick: Option<Pick>,
+}
+
+/// A pixel copied into a buffer, waiting for the GPU to finish before it can be read.
+struct PendingPick {
+    buffer: Buffer,
+    x: u32,
+    y: u32,
+}
+
+/// Draws mesh and instance IDs into an integer texture on the frames a pick was requested, and
+/// reads the requested pixels back without stalling the frame. Results arrive a frame or more
+/// after the request, once the GPU has caught up.
+pub struct Picking {
+    width: u32,
+    height: u32,
+    id_texture: Texture,
+    id_view: TextureView,
+    depth_texture: TextureView,
+    mesh_bind_group_layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+
+    requests: Vec<(u32, u32)>,
+    pending: Vec<PendingPick>,
+    sender: Sender<PickResult>,
+    /// Behind a mutex so the renderer stays shareable with the culling threads.
+    receiver: Mutex<Receiver<PickResult>>,
+}
+
+impl Picking {
+    pub fn new(
+        device: &Device,
+        camera_bind_group_layout: &BindGroupLayout,
+        instances_bind_group_layout: &BindGroupLayout,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let mesh_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::UniformBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[
+                camera_bind_group_layout,
+                instances_bind_group_layout,
+                &mesh_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            vertex_stage: ProgrammableStageDescriptor {
+                module: &device.create_shader_module(include_spirv!("../shaders/pick_vert.spv")),
+                entry_point: "main",
+            },
+            fragment_stage: Some(ProgrammableStageDescriptor {
+                module: &device.create_shader_module(include_spirv!("../shaders/pick_frag.spv")),
+                entry_point: "main",
+            }),
+            rasterization_state: Some(RasterizationStateDescriptor {
+                front_face: FrontFace::Ccw,
+                cull_mode: CullMode::Back,
+                clamp_depth: false,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: PrimitiveTopology::TriangleList,
+            color_states: &[ColorStateDescriptor {
+                format: ID_FORMAT,
+                alpha_blend: BlendDescriptor::REPLACE,
+                color_blend: BlendDescriptor::REPLACE,
+                write_mask: ColorWrite::ALL,
+            }],
+            depth_stencil_state: Some(DepthStencilStateDescriptor {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Less,
+                stencil: StencilStateDescriptor::default(),
+            }),
+            vertex_state: VertexStateDescriptor {
+                index_format: IndexFormat::Uint16,
+                vertex_buffers: &[
+                    VertexBufferDescriptor {
+                        stride: mem::size_of::<Vertex>() as BufferAddress,
+                        step_mode: InputStepMode::Vertex,
+                        attributes: &vertex_attr_array![0 => Float3, 1 => Float3, 2 => Float3],
+                    },
+                    VertexBufferDescriptor {
+                        stride: mem::size_of::<SkinVertex>() as BufferAddress,
+                        step_mode: InputStepMode::Vertex,
+                        attributes: &vertex_attr_array![3 => Uint4, 4 => Float4],
+                    },
+                ],
+            },
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        let (id_texture, id_view) = create_texture(device, ID_FORMAT, width, height);
+        let (_, depth_texture) = create_texture(device, TextureFormat::Depth32Float, width, height);
+        let (sender, receiver) = mpsc::channel();
+
+        Self {
+            width,
+            height,
+            id_texture,
+            id_view,
+            depth_texture,
+            mesh_bind_group_layout,
+            pipeline,
+
+            requests: Vec::new(),
+            pending: Vec::new(),
+            sender,
+            receiver: Mutex::new(receiver),
+        }
+    }
+
+    pub fn set_screen_size(&mut self, device: &Device, width: u32, height: u32) {
+        let (id_texture, id_view) = create_texture(device, ID_FORMAT, width, height);
+        let (_, depth_texture) = create_texture(device, TextureFormat::Depth32Float, width, height);
+        self.id_texture = id_texture;
+        self.id_view = id_view;
+        self.depth_texture = depth_texture;
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Picks the pixel at `x`, `y` from the top left in the next rendered frame. Pixels outside
+    /// the screen are ignored.
+    pub fn request(&mut self, x: u32, y: u32) {
+        if x < self.width && y < self.height {
+            self.requests.push((x, y));
+        }
+    }
+
+    /// Whether the next frame has to draw the IDs.
+    pub fn has_requests(&self) -> bool {
+        !self.requests.is_empty()
+    }
+
+    /// Clears the IDs and starts drawing them with the pipeline set, the caller binds the camera
+    /// and instances at groups 0 and 1 and each mesh's `mesh_bind_group` at group 2.
+    pub fn begin_pass<'a>(&'a self, encoder: &'a mut CommandEncoder) -> RenderPass<'a> {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[RenderPassColorAttachmentDescriptor {
+                attachment: &self.id_view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::TRANSPARENT),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachmentDescriptor {
+                attachment: &self.depth_texture,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(1.0),
+                    store: false,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass
+    }
+
+    /// `mesh` is the index the picks report.
+    pub fn mesh_bind_group(&self, device: &Device, mesh: usize) -> BindGroup {
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::bytes_of(&(mesh as u32 + 1)),
+            usage: BufferUsage::UNIFORM,
+        });
+        device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.mesh_bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::Buffer(buffer.slice(..)),
+            }],
+        })
+    }
+
+    /// Records copies of the requested pixels, after the pass from `begin_pass`.
+    pub fn copy_requested(&mut self, device: &Device, encoder: &mut CommandEncoder) {
+        for (x, y) in self.requests.drain(..) {
+            let buffer = device.create_buffer(&BufferDescriptor {
+                label: None,
+                size: COPY_BYTES_PER_ROW_ALIGNMENT as BufferAddress,
+                usage: BufferUsage::MAP_READ | BufferUsage::COPY_DST,
+                mapped_at_creation: false,
+            });
+            encoder.copy_texture_to_buffer(
+                TextureCopyView {
+                    texture: &self.id_texture,
+                    mip_level: 0,
+                    origin: Origin3d { x, y, z: 0 },
+                },
+                BufferCopyView {
+                    buffer: &buffer,
+                    layout: TextureDataLayout {
+                        offset: 0,
+                        bytes_per_row: COPY_BYTES_PER_ROW_ALIGNMENT,
+                        rows_per_image: 0,
+                    },
+                },
+                Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth: 1,
+                },
+            );
+            self.pending.push(PendingPick { buffer, x, y });
+        }
+    }
+
+    /// Starts reading the pixels copied so far, has to be called after the command buffer with
+    /// the copies was submitted. Each is read on its own thread once the GPU is done.
+    pub fn read_pending(&mut self) {
+        for pending in self.pending.drain(..) {
+            let sender = self.sender.clone();
+            thread::spawn(move || {
+                let slice = pending.buffer.slice(..);
+                if pollster::block_on(slice.map_async(MapMode::Read)).is_err() {
+                    return;
+                }
+                let data = slice.get_mapped_range();
+                let id =
+                    |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+                let pick = match (id(0), id(4)) {
+                    (0, _) => None,
+                    (mesh_id, instance) => Some(Pick {
+                        mesh: mesh_id as usize - 1,
+                        instance,
+                    }),
+                };
+                // The receiver only goes away with the renderer
+                let _ = sender.send(PickResult {
+                    x: pending.x,
+                    y: pending.y,
+                    pick,
+                });
+            });
+        }
+    }
+
+    /// The picks read back since the last call, in the order they finished.
+    pub fn results(&self) -> Vec<PickResult> {
+        self.receiver.lock().unwrap().try_iter().collect()
+    }
+}
+
+fn create_texture(
+    device: &Device,
+    format: TextureFormat,
+    width: u32,
+    height: u32,
+) -> (Texture, TextureView) {
+    let texture = device.create_texture(&TextureDescriptor {
+        label: None,
+        size: Extent3d {
+            width,
+            height,
+            depth: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format,
+        usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::COPY_SRC,
+    });
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    (texture, view)
+}
@@ -0,0 +1,546 @@
+use crate::antialiasing::{Antialiasing, Fxaa, Taa};
+use crate::bloom::{Bloom, BloomSettings};
+use bytemuck::{Pod, Zeroable};
+use std::time::Instant;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+
+/// Format the scene is rendered in before post processing.
+pub const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Exposure {
+    /// Fixed exposure in stops, 0 leaves the scene unchanged.
+    Manual(f32),
+    /// Exposes for the average scene luminance measured by the histogram pass, offset by
+    /// `compensation` stops. `adaptation_rate` controls how fast it follows changes, per second.
+    Auto {
+        compensation: f32,
+        adaptation_rate: f32,
+    },
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Tonemapper {
+    /// Clamps to 0..1.
+    None,
+    Aces,
+    Agx,
+}
+
+/// Settings of the post-process passes. Manual exposure skips the histogram pass and `None` bloom
+/// skips the bloom pass, tonemapping always runs as it's what converts the scene to the output.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PostProcessSettings {
+    pub exposure: Exposure,
+    pub tonemapper: Tonemapper,
+    /// Gamma adjustment applied after tonemapping, on top of the swapchain's sRGB encoding.
+    pub gamma: f32,
+    /// Disabled when `None`.
+    pub bloom: Option<BloomSettings>,
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        Self {
+            exposure: Exposure::Auto {
+                compensation: 0.0,
+                adaptation_rate: 1.5,
+            },
+            tonemapper: Tonemapper::Aces,
+            gamma: 1.0,
+            bloom: Some(BloomSettings::default()),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct HistogramUniforms {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    pixel_count: u32,
+    size: [u32; 2],
+    _padding: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct TonemapUniforms {
+    exposure: f32,
+    auto_exposure: u32,
+    tonemapper: u32,
+    gamma: f32,
+    bloom_intensity: f32,
+    _padding: [u32; 3],
+}
+
+/// Takes the resolved HDR scene through temporal antialiasing, auto exposure, bloom, tonemapping
+/// and FXAA into the swapchain. The order of the passes is fixed, `PostProcessSettings` and the
+/// antialiasing mode only configure them or skip those that are optional.
+pub struct PostProcess {
+    hdr_texture: TextureView,
+    width: u32,
+    height: u32,
+    sampler: Sampler,
+
+    auto_exposure_bind_group_layout: BindGroupLayout,
+    histogram_pipeline: ComputePipeline,
+    exposure_pipeline: ComputePipeline,
+    histogram_buffer: Buffer,
+    auto_exposure_buffer: Buffer,
+    last_frame: Instant,
+
+    bloom: Bloom,
+    taa: Taa,
+    fxaa: Fxaa,
+
+    tonemap_bind_group_layout: BindGroupLayout,
+    tonemap_pipeline: RenderPipeline,
+}
+
+impl PostProcess {
+    const MIN_LOG_LUMINANCE: f32 = -10.0;
+    const LOG_LUMINANCE_RANGE: f32 = 14.0;
+    const HISTOGRAM_BINS: usize = 256;
+
+    pub fn new(device: &Device, output_format: TextureFormat, width: u32, height: u32) -> Self {
+        let auto_exposure_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStage::COMPUTE,
+                        ty: BindingType::SampledTexture {
+                            dimension: TextureViewDimension::D2,
+                            component_type: TextureComponentType::Float,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStage::COMPUTE,
+                        ty: BindingType::Sampler { comparison: false },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStage::COMPUTE,
+                        ty: BindingType::UniformBuffer {
+                            dynamic: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStage::COMPUTE,
+                        ty: BindingType::StorageBuffer {
+                            dynamic: false,
+                            min_binding_size: None,
+                            readonly: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStage::COMPUTE,
+                        ty: BindingType::StorageBuffer {
+                            dynamic: false,
+                            min_binding_size: None,
+                            readonly: false,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let auto_exposure_pipeline_layout =
+            device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&auto_exposure_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let histogram_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&auto_exposure_pipeline_layout),
+            compute_stage: ProgrammableStageDescriptor {
+                module: &device.create_shader_module(include_spirv!("../shaders/histogram.spv")),
+                entry_point: "main",
+            },
+        });
+        let exposure_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&auto_exposure_pipeline_layout),
+            compute_stage: ProgrammableStageDescriptor {
+                module: &device.create_shader_module(include_spirv!("../shaders/exposure.spv")),
+                entry_point: "main",
+            },
+        });
+        let histogram_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&[0u32; Self::HISTOGRAM_BINS]),
+            usage: BufferUsage::STORAGE,
+        });
+        // average_luminance, auto_exposure_scale
+        let auto_exposure_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&[0.18f32, 1.0]),
+            usage: BufferUsage::STORAGE,
+        });
+
+        let tonemap_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStage::FRAGMENT,
+                        ty: BindingType::SampledTexture {
+                            dimension: TextureViewDimension::D2,
+                            component_type: TextureComponentType::Float,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStage::FRAGMENT,
+                        ty: BindingType::Sampler { comparison: false },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStage::FRAGMENT,
+                        ty: BindingType::UniformBuffer {
+                            dynamic: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStage::FRAGMENT,
+                        ty: BindingType::StorageBuffer {
+                            dynamic: false,
+                            min_binding_size: None,
+                            readonly: true,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStage::FRAGMENT,
+                        ty: BindingType::SampledTexture {
+                            dimension: TextureViewDimension::D2,
+                            component_type: TextureComponentType::Float,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let tonemap_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&tonemap_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let tonemap_pipeline = create_fullscreen_pipeline(
+            device,
+            &tonemap_pipeline_layout,
+            &device.create_shader_module(include_spirv!("../shaders/tonemap.spv")),
+            output_format,
+            BlendDescriptor::REPLACE,
+            1,
+        );
+
+        Self {
+            hdr_texture: create_hdr_texture(device, width, height),
+            width,
+            height,
+            sampler: create_linear_sampler(device),
+
+            auto_exposure_bind_group_layout,
+            histogram_pipeline,
+            exposure_pipeline,
+            histogram_buffer,
+            auto_exposure_buffer,
+            last_frame: Instant::now(),
+
+            bloom: Bloom::new(device, width, height),
+            taa: Taa::new(device, width, height),
+            fxaa: Fxaa::new(device, output_format, width, height),
+
+            tonemap_bind_group_layout,
+            tonemap_pipeline,
+        }
+    }
+
+    /// Single sampled texture the scene is rendered or resolved into.
+    pub fn hdr_texture(&self) -> &TextureView {
+        &self.hdr_texture
+    }
+
+    /// Subpixel offset the scene's projection should be shifted by for temporal antialiasing.
+    pub fn taa_jitter(&self) -> (f32, f32) {
+        self.taa.jitter()
+    }
+
+    /// Discards the frames temporal antialiasing accumulated, which aren't reprojected so they
+    /// only line up with the current frame while the camera stays where it was.
+    pub fn invalidate_taa_history(&mut self) {
+        self.taa.invalidate_history();
+    }
+
+    pub fn set_screen_size(&mut self, device: &Device, width: u32, height: u32) {
+        self.hdr_texture = create_hdr_texture(device, width, height);
+        self.width = width;
+        self.height = height;
+        self.bloom.set_screen_size(device, width, height);
+        self.taa.set_screen_size(device, width, height);
+        self.fxaa.set_screen_size(device, width, height);
+    }
+
+    pub fn render(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        settings: &PostProcessSettings,
+        antialiasing: Antialiasing,
+        render_target: &TextureView,
+    ) {
+        let delta_time = self.last_frame.elapsed().as_secs_f32();
+        self.last_frame = Instant::now();
+
+        let scene = if antialiasing == Antialiasing::Taa {
+            self.taa.resolve(device, encoder, &self.hdr_texture);
+            self.taa.output()
+        } else {
+            self.taa.invalidate_history();
+            &self.hdr_texture
+        };
+
+        if let Exposure::Auto {
+            adaptation_rate, ..
+        } = settings.exposure
+        {
+            self.measure_luminance(
+                device,
+                encoder,
+                scene,
+                1.0 - (-delta_time * adaptation_rate).exp(),
+            );
+        }
+
+        if let Some(bloom_settings) = &settings.bloom {
+            self.bloom.render(device, encoder, bloom_settings, scene);
+        }
+
+        let (exposure, auto_exposure) = match settings.exposure {
+            Exposure::Manual(stops) => (stops.exp2(), false),
+            Exposure::Auto { compensation, .. } => (compensation.exp2(), true),
+        };
+        let tonemap_uniforms = TonemapUniforms {
+            exposure,
+            auto_exposure: auto_exposure as u32,
+            tonemapper: settings.tonemapper as u32,
+            gamma: settings.gamma,
+            bloom_intensity: settings.bloom.map_or(0.0, |bloom| bloom.intensity),
+            _padding: [0; 3],
+        };
+        let tonemap_uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::bytes_of(&tonemap_uniforms),
+            usage: BufferUsage::UNIFORM,
+        });
+        let tonemap_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.tonemap_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(scene),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&self.sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Buffer(tonemap_uniform_buffer.slice(..)),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Buffer(self.auto_exposure_buffer.slice(..)),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(self.bloom.texture()),
+                },
+            ],
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[RenderPassColorAttachmentDescriptor {
+                attachment: if antialiasing == Antialiasing::Fxaa {
+                    self.fxaa.ldr_texture()
+                } else {
+                    render_target
+                },
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.tonemap_pipeline);
+        render_pass.set_bind_group(0, &tonemap_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+        drop(render_pass);
+
+        if antialiasing == Antialiasing::Fxaa {
+            self.fxaa.render(device, encoder, render_target);
+        }
+    }
+
+    /// Builds a luminance histogram of the scene, then moves the average luminance towards the
+    /// histogram's average by `adaptation`.
+    fn measure_luminance(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        scene: &TextureView,
+        adaptation: f32,
+    ) {
+        let uniforms = HistogramUniforms {
+            min_log_luminance: Self::MIN_LOG_LUMINANCE,
+            log_luminance_range: Self::LOG_LUMINANCE_RANGE,
+            adaptation,
+            pixel_count: self.width * self.height,
+            size: [self.width, self.height],
+            _padding: [0; 2],
+        };
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::bytes_of(&uniforms),
+            usage: BufferUsage::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.auto_exposure_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(scene),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&self.sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Buffer(uniform_buffer.slice(..)),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Buffer(self.histogram_buffer.slice(..)),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::Buffer(self.auto_exposure_buffer.slice(..)),
+                },
+            ],
+        });
+
+        let mut compute_pass = encoder.begin_compute_pass();
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.set_pipeline(&self.histogram_pipeline);
+        compute_pass.dispatch(self.width.div_ceil(16), self.height.div_ceil(16), 1);
+        compute_pass.set_pipeline(&self.exposure_pipeline);
+        compute_pass.dispatch(1, 1, 1);
+    }
+}
+
+pub fn create_linear_sampler(device: &Device) -> Sampler {
+    device.create_sampler(&SamplerDescriptor {
+        label: None,
+        address_mode_u: AddressMode::ClampToEdge,
+        address_mode_v: AddressMode::ClampToEdge,
+        address_mode_w: AddressMode::ClampToEdge,
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        mipmap_filter: FilterMode::Nearest,
+        lod_min_clamp: 0.0,
+        lod_max_clamp: f32::MAX,
+        compare: None,
+        anisotropy_clamp: None,
+    })
+}
+
+fn create_hdr_texture(device: &Device, width: u32, height: u32) -> TextureView {
+    device
+        .create_texture(&TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::SAMPLED,
+        })
+        .create_view(&TextureViewDescriptor::default())
+}
+
+/// Pipeline drawing a single triangle covering the screen with `fullscreen.vert`.
+pub fn create_fullscreen_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    fragment_shader: &ShaderModule,
+    format: TextureFormat,
+    color_blend: BlendDescriptor,
+    sample_count: u32,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        vertex_stage: ProgrammableStageDescriptor {
+            module: &device.create_shader_module(include_spirv!("../shaders/fullscreen.spv")),
+            entry_point: "main",
+        },
+        fragment_stage: Some(ProgrammableStageDescriptor {
+            module: fragment_shader,
+            entry_point: "main",
+        }),
+        rasterization_state: Some(RasterizationStateDescriptor {
+            front_face: FrontFace::Ccw,
+            cull_mode: CullMode::None,
+            clamp_depth: false,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+            depth_bias_clamp: 0.0,
+        }),
+        primitive_topology: PrimitiveTopology::TriangleList,
+        color_states: &[ColorStateDescriptor {
+            format,
+            alpha_blend: BlendDescriptor::REPLACE,
+            color_blend,
+            write_mask: ColorWrite::ALL,
+        }],
+        depth_stencil_state: None,
+        vertex_state: VertexStateDescriptor {
+            index_format: IndexFormat::Uint16,
+            vertex_buffers: &[],
+        },
+        sample_count,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: false,
+    })
+}
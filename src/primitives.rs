@@ -0,0 +1,565 @@
+use crate::objects::{Geometry, Mesh, Vertex};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::f32::consts::{PI, TAU};
+use ultraviolet::{Vec2, Vec3};
+use wgpu::*;
+
+/// Procedurally generated shapes, centered on the origin with Y up. Sizes are full extents and
+/// segment counts are clamped to the minimum that still makes a closed shape.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Primitive {
+    /// `segments` around the Y axis and `rings` from pole to pole.
+    UvSphere {
+        radius: f32,
+        segments: u32,
+        rings: u32,
+    },
+    /// An icosahedron with each triangle split into four `subdivisions` times, evenly spread
+    /// triangles without the pinched poles of a UV sphere.
+    Icosphere {
+        radius: f32,
+        subdivisions: u32,
+    },
+    Cube {
+        size: f32,
+    },
+    /// Square in the XZ plane facing up, with `subdivisions` quads along each side.
+    Plane {
+        size: f32,
+        subdivisions: u32,
+    },
+    Cylinder {
+        radius: f32,
+        height: f32,
+        segments: u32,
+    },
+    Cone {
+        radius: f32,
+        height: f32,
+        segments: u32,
+    },
+    /// Ring around the Y axis. `radius` is to the center of the tube, `segments` go around the
+    /// ring and `tube_segments` around the tube.
+    Torus {
+        radius: f32,
+        tube_radius: f32,
+        segments: u32,
+        tube_segments: u32,
+    },
+    /// Cylinder with hemispherical ends, `height` is between the centers of the hemispheres.
+    /// Each hemisphere has `rings` rings.
+    Capsule {
+        radius: f32,
+        height: f32,
+        segments: u32,
+        rings: u32,
+    },
+}
+
+impl Primitive {
+    /// One of each shape, about a unit in size.
+    pub const DEFAULTS: [Self; 8] = [
+        Self::UvSphere {
+            radius: 0.5,
+            segments: 32,
+            rings: 16,
+        },
+        Self::Icosphere {
+            radius: 0.5,
+            subdivisions: 3,
+        },
+        Self::Cube { size: 1.0 },
+        Self::Plane {
+            size: 1.0,
+            subdivisions: 1,
+        },
+        Self::Cylinder {
+            radius: 0.5,
+            height: 1.0,
+            segments: 32,
+        },
+        Self::Cone {
+            radius: 0.5,
+            height: 1.0,
+            segments: 32,
+        },
+        Self::Torus {
+            radius: 0.375,
+            tube_radius: 0.125,
+            segments: 32,
+            tube_segments: 16,
+        },
+        Self::Capsule {
+            radius: 0.25,
+            height: 0.5,
+            segments: 32,
+            rings: 8,
+        },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::UvSphere { .. } => "UV sphere",
+            Self::Icosphere { .. } => "Icosphere",
+            Self::Cube { .. } => "Cube",
+            Self::Plane { .. } => "Plane",
+            Self::Cylinder { .. } => "Cylinder",
+            Self::Cone { .. } => "Cone",
+            Self::Torus { .. } => "Torus",
+            Self::Capsule { .. } => "Capsule",
+        }
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        let mut builder = Builder::default();
+        match *self {
+            Self::UvSphere {
+                radius,
+                segments,
+                rings,
+            } => {
+                let rings = rings.max(2);
+                let profile = (0..=rings)
+                    .map(|ring| {
+                        let angle = ring as f32 / rings as f32 * PI - PI / 2.0;
+                        ProfilePoint::on_circle(
+                            radius,
+                            angle,
+                            0.0,
+                            1.0 - ring as f32 / rings as f32,
+                        )
+                    })
+                    .collect::<Vec<ProfilePoint>>();
+                builder.lathe(&profile, segments);
+            }
+            Self::Icosphere {
+                radius,
+                subdivisions,
+            } => builder.icosphere(radius, subdivisions),
+            Self::Cube { size } => {
+                let half_size = size / 2.0;
+                for &normal in &[
+                    Vec3::unit_x(),
+                    -Vec3::unit_x(),
+                    Vec3::unit_y(),
+                    -Vec3::unit_y(),
+                    Vec3::unit_z(),
+                    -Vec3::unit_z(),
+                ] {
+                    // Up in texture space is +Y on the sides and towards -Z on top
+                    let up = if normal.y == 0.0 {
+                        Vec3::unit_y()
+                    } else {
+                        -Vec3::unit_z() * normal.y
+                    };
+                    builder.grid(
+                        normal * half_size,
+                        up.cross(normal) * half_size,
+                        up * half_size,
+                        normal,
+                        1,
+                    );
+                }
+            }
+            Self::Plane { size, subdivisions } => builder.grid(
+                Vec3::zero(),
+                Vec3::unit_x() * (size / 2.0),
+                -Vec3::unit_z() * (size / 2.0),
+                Vec3::unit_y(),
+                subdivisions.max(1),
+            ),
+            Self::Cylinder {
+                radius,
+                height,
+                segments,
+            } => {
+                let (bottom, top) = (-height / 2.0, height / 2.0);
+                builder.lathe(&ProfilePoint::bottom_cap(radius, bottom), segments);
+                builder.lathe(
+                    &[
+                        ProfilePoint::new(radius, bottom, Vec2::unit_x(), 1.0),
+                        ProfilePoint::new(radius, top, Vec2::unit_x(), 0.0),
+                    ],
+                    segments,
+                );
+                builder.lathe(&ProfilePoint::top_cap(radius, top), segments);
+            }
+            Self::Cone {
+                radius,
+                height,
+                segments,
+            } => {
+                let (bottom, top) = (-height / 2.0, height / 2.0);
+                let normal = Vec2::new(height, radius).normalized();
+                builder.lathe(&ProfilePoint::bottom_cap(radius, bottom), segments);
+                builder.lathe(
+                    &[
+                        ProfilePoint::new(radius, bottom, normal, 1.0),
+                        ProfilePoint::new(0.0, top, normal, 0.0),
+                    ],
+                    segments,
+                );
+            }
+            Self::Torus {
+                radius,
+                tube_radius,
+                segments,
+                tube_segments,
+            } => {
+                let tube_segments = tube_segments.max(3);
+                // Starts on the inside of the ring so the texture's seam is hidden there
+                let profile = (0..=tube_segments)
+                    .map(|i| {
+                        let angle = i as f32 / tube_segments as f32 * TAU - PI;
+                        ProfilePoint::on_circle(
+                            tube_radius,
+                            angle,
+                            radius,
+                            1.0 - i as f32 / tube_segments as f32,
+                        )
+                    })
+                    .collect::<Vec<ProfilePoint>>();
+                builder.lathe(&profile, segments);
+            }
+            Self::Capsule {
+                radius,
+                height,
+                segments,
+                rings,
+            } => {
+                // The texture is spread evenly along the profile, so it isn't stretched on the
+                // cylinder
+                let rings = rings.max(1);
+                let quarter_circle = PI / 2.0 * radius;
+                let length = 2.0 * quarter_circle + height;
+                let mut profile = Vec::new();
+                for (i, center) in [-height / 2.0, height / 2.0].iter().enumerate() {
+                    for ring in 0..=rings {
+                        let fraction = ring as f32 / rings as f32;
+                        let distance =
+                            i as f32 * (quarter_circle + height) + fraction * quarter_circle;
+                        let angle = (i as f32 + fraction) * PI / 2.0 - PI / 2.0;
+                        let mut point =
+                            ProfilePoint::on_circle(radius, angle, 0.0, 1.0 - distance / length);
+                        point.y += center;
+                        profile.push(point);
+                    }
+                }
+                builder.lathe(&profile, segments);
+            }
+        }
+        Geometry::from_vertices_and_indices(builder.vertices, builder.indices, false)
+    }
+
+    /// Builds the geometry and uploads it with a plain white texture.
+    pub fn mesh(&self, queue: &Queue, device: &Device, retain_geometry: bool) -> Mesh {
+        Mesh::from_geometry(queue, device, self.geometry(), retain_geometry)
+    }
+}
+
+/// A point of a profile revolved around the Y axis, with its distance from the axis as `radius`.
+#[derive(Copy, Clone)]
+struct ProfilePoint {
+    radius: f32,
+    y: f32,
+    /// Radial and vertical components of the normal.
+    normal: Vec2,
+    v: f32,
+}
+
+impl ProfilePoint {
+    fn new(radius: f32, y: f32, normal: Vec2, v: f32) -> Self {
+        Self {
+            radius,
+            y,
+            normal,
+            v,
+        }
+    }
+
+    /// A point on a circle in the profile's plane, `angle` is counterclockwise from the outside.
+    fn on_circle(radius: f32, angle: f32, center: f32, v: f32) -> Self {
+        let normal = Vec2::new(angle.cos(), angle.sin());
+        Self::new(center + normal.x * radius, normal.y * radius, normal, v)
+    }
+
+    fn bottom_cap(radius: f32, y: f32) -> [Self; 2] {
+        [
+            Self::new(0.0, y, -Vec2::unit_y(), 0.5),
+            Self::new(radius, y, -Vec2::unit_y(), 1.0),
+        ]
+    }
+
+    fn top_cap(radius: f32, y: f32) -> [Self; 2] {
+        [
+            Self::new(radius, y, Vec2::unit_y(), 0.0),
+            Self::new(0.0, y, Vec2::unit_y(), 0.5),
+        ]
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    vertices: Vec<Vertex>,
+    indices: Vec<u16>,
+}
+
+impl Builder {
+    fn vertex(&mut self, position: Vec3, normal: Vec3, uv: Vec2) -> u16 {
+        self.vertices.push(Vertex {
+            position: position.into(),
+            normal: normal.into(),
+            uv: uv.into(),
+        });
+        u16::try_from(self.vertices.len() - 1).expect("too many vertices for 16-bit indices")
+    }
+
+    /// Revolves the profile around the Y axis. The surface faces the right of the profile when
+    /// looking at it from outside with the points going upwards. Triangles collapsed onto the
+    /// axis are left out, but the vertices are kept, so poles get a texture coordinate for each
+    /// segment.
+    fn lathe(&mut self, profile: &[ProfilePoint], segments: u32) {
+        let segments = segments.max(3);
+        let first = self.vertices.len() as u16;
+        for point in profile {
+            for segment in 0..=segments {
+                let u = segment as f32 / segments as f32;
+                let (sin, cos) = (u * TAU).sin_cos();
+                self.vertex(
+                    Vec3::new(point.radius * sin, point.y, point.radius * cos),
+                    Vec3::new(point.normal.x * sin, point.normal.y, point.normal.x * cos),
+                    Vec2::new(u, point.v),
+                );
+            }
+        }
+
+        let ring_size = segments as u16 + 1;
+        for (i, pair) in profile.windows(2).enumerate() {
+            let bottom = first + i as u16 * ring_size;
+            let top = bottom + ring_size;
+            for segment in 0..segments as u16 {
+                let (a, b) = (bottom + segment, bottom + segment + 1);
+                let (c, d) = (top + segment, top + segment + 1);
+                if pair[0].radius > 0.0 {
+                    self.indices.extend_from_slice(&[a, b, d]);
+                }
+                if pair[1].radius > 0.0 {
+                    self.indices.extend_from_slice(&[a, d, c]);
+                }
+            }
+        }
+    }
+
+    /// A flat grid around `center` spanning `right` and `up` in each direction, which face
+    /// `normal` when counterclockwise.
+    fn grid(&mut self, center: Vec3, right: Vec3, up: Vec3, normal: Vec3, subdivisions: u32) {
+        let first = self.vertices.len() as u16;
+        for row in 0..=subdivisions {
+            for column in 0..=subdivisions {
+                let uv = Vec2::new(column as f32, row as f32) / subdivisions as f32;
+                self.vertex(
+                    center + right * (uv.x * 2.0 - 1.0) + up * (uv.y * 2.0 - 1.0),
+                    normal,
+                    Vec2::new(uv.x, 1.0 - uv.y),
+                );
+            }
+        }
+
+        let row_size = subdivisions as u16 + 1;
+        for row in 0..subdivisions as u16 {
+            for column in 0..subdivisions as u16 {
+                let a = first + row * row_size + column;
+                let (b, c, d) = (a + 1, a + row_size, a + row_size + 1);
+                self.indices.extend_from_slice(&[a, b, d, a, d, c]);
+            }
+        }
+    }
+
+    fn icosphere(&mut self, radius: f32, subdivisions: u32) {
+        let t = (1.0 + 5f32.sqrt()) / 2.0;
+        let mut positions = [
+            (-1.0, t, 0.0),
+            (1.0, t, 0.0),
+            (-1.0, -t, 0.0),
+            (1.0, -t, 0.0),
+            (0.0, -1.0, t),
+            (0.0, 1.0, t),
+            (0.0, -1.0, -t),
+            (0.0, 1.0, -t),
+            (t, 0.0, -1.0),
+            (t, 0.0, 1.0),
+            (-t, 0.0, -1.0),
+            (-t, 0.0, 1.0),
+        ]
+        .iter()
+        .map(|&(x, y, z)| Vec3::new(x, y, z).normalized())
+        .collect::<Vec<Vec3>>();
+        let mut triangles = vec![
+            [0, 11, 5],
+            [0, 5, 1],
+            [0, 1, 7],
+            [0, 7, 10],
+            [0, 10, 11],
+            [1, 5, 9],
+            [5, 11, 4],
+            [11, 10, 2],
+            [10, 7, 6],
+            [7, 1, 8],
+            [3, 9, 4],
+            [3, 4, 2],
+            [3, 2, 6],
+            [3, 6, 8],
+            [3, 8, 9],
+            [4, 9, 5],
+            [2, 4, 11],
+            [6, 2, 10],
+            [8, 6, 7],
+            [9, 8, 1],
+        ];
+
+        for _ in 0..subdivisions {
+            let mut midpoints = HashMap::new();
+            let mut midpoint = |a: usize, b: usize| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    positions.push(((positions[a] + positions[b]) / 2.0).normalized());
+                    positions.len() - 1
+                })
+            };
+            triangles = triangles
+                .iter()
+                .flat_map(|&[a, b, c]| {
+                    let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                    vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+                })
+                .collect();
+        }
+
+        // Same mapping as the UV sphere. Triangles crossing the seam get copies of the vertices
+        // on the low side shifted past 1, and the poles, where u is undefined, get a copy in each
+        // of their triangles with u between the other two vertices'
+        let uv = |position: Vec3| {
+            Vec2::new(
+                (position.x.atan2(position.z) / TAU).rem_euclid(1.0),
+                position.y.clamp(-1.0, 1.0).acos() / PI,
+            )
+        };
+        let is_pole = |position: Vec3| position.x.abs() < 1e-6 && position.z.abs() < 1e-6;
+        let first = self.vertices.len() as u16;
+        for &position in &positions {
+            self.vertex(position * radius, position, uv(position));
+        }
+        let mut seam_copies = HashMap::new();
+        for triangle in triangles {
+            let sides = triangle
+                .iter()
+                .filter(|&&i| !is_pole(positions[i]))
+                .map(|&i| uv(positions[i]).x)
+                .collect::<Vec<f32>>();
+            let crosses_seam = sides.iter().cloned().fold(f32::MIN, f32::max)
+                - sides.iter().cloned().fold(f32::MAX, f32::min)
+                > 0.5;
+            let shifted = |u: f32| if crosses_seam && u < 0.5 { u + 1.0 } else { u };
+            let pole_u = sides.iter().map(|&u| shifted(u)).sum::<f32>() / sides.len() as f32;
+            for &i in &triangle {
+                let position = positions[i];
+                let uv = uv(position);
+                let index = if is_pole(position) {
+                    self.vertex(position * radius, position, Vec2::new(pole_u, uv.y))
+                } else if shifted(uv.x) != uv.x {
+                    *seam_copies.entry(i).or_insert_with(|| {
+                        self.vertex(position * radius, position, uv + Vec2::unit_x())
+                    })
+                } else {
+                    first + i as u16
+                };
+                self.indices.push(index);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_valid(primitive: Primitive) {
+        let geometry = primitive.geometry();
+        assert!(
+            !geometry.indices.is_empty(),
+            "{:?} has no triangles",
+            primitive
+        );
+        for lod in &geometry.lods {
+            assert_eq!((lod.end - lod.start) % 3, 0, "{:?}", primitive);
+        }
+        for &index in &geometry.indices {
+            assert!(
+                (index as usize) < geometry.vertices.len(),
+                "{:?} has index {} of {} vertices",
+                primitive,
+                index,
+                geometry.vertices.len()
+            );
+        }
+        for vertex in &geometry.vertices {
+            let length = Vec3::from(vertex.normal).mag();
+            assert!(
+                (length - 1.0).abs() < 1e-4,
+                "{:?} has a normal of length {}",
+                primitive,
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        for &primitive in &Primitive::DEFAULTS {
+            assert_valid(primitive);
+        }
+    }
+
+    #[test]
+    fn clamped_segment_counts_are_valid() {
+        for primitive in &[
+            Primitive::UvSphere {
+                radius: 1.0,
+                segments: 0,
+                rings: 0,
+            },
+            Primitive::Icosphere {
+                radius: 1.0,
+                subdivisions: 0,
+            },
+            Primitive::Plane {
+                size: 1.0,
+                subdivisions: 0,
+            },
+            Primitive::Cylinder {
+                radius: 1.0,
+                height: 1.0,
+                segments: 0,
+            },
+            Primitive::Cone {
+                radius: 1.0,
+                height: 1.0,
+                segments: 0,
+            },
+            Primitive::Torus {
+                radius: 1.0,
+                tube_radius: 0.5,
+                segments: 0,
+                tube_segments: 0,
+            },
+            Primitive::Capsule {
+                radius: 1.0,
+                height: 1.0,
+                segments: 0,
+                rings: 0,
+            },
+        ] {
+            assert_valid(*primitive);
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use bytemuck::{Pod, Zeroable};
+use std::collections::VecDeque;
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+
+/// Mesh draws recorded in a frame, counted on the CPU. Indirect draws of instances culled on the
+/// GPU only count as draw calls, their instances and triangles are never read back.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct DrawStats {
+    pub draw_calls: u32,
+    pub indirect_draw_calls: u32,
+    pub instances: u32,
+    pub triangles: u32,
+}
+
+impl DrawStats {
+    pub fn add_draw(&mut self, index_count: u32, instance_count: u32) {
+        self.draw_calls += 1;
+        self.instances += instance_count;
+        self.triangles += index_count / 3 * instance_count;
+    }
+
+    pub fn add_indirect_draw(&mut self) {
+        self.draw_calls += 1;
+        self.indirect_draw_calls += 1;
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Scope {
+    pub name: &'static str,
+    /// Time since the start of the frame.
+    pub start: Duration,
+    pub duration: Duration,
+    /// Number of scopes this one is nested in.
+    pub depth: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct FrameProfile {
+    /// Time since the profiler was created.
+    pub start: Duration,
+    /// Time until the next frame started.
+    pub duration: Duration,
+    pub scopes: Vec<Scope>,
+    pub draw_stats: DrawStats,
+}
+
+/// CPU timings of the last frames, split into named scopes.
+///
+/// wgpu 0.6 doesn't expose timestamp queries, so the GPU isn't timed pass by pass. Time spent
+/// waiting for the GPU shows up in the scopes that block on it instead, acquiring the swapchain
+/// frame in particular.
+pub struct Profiler {
+    pub show_overlay: bool,
+    created: Instant,
+    frame_start: Option<Instant>,
+    open_scopes: Vec<(&'static str, Instant)>,
+    scopes: Vec<Scope>,
+    draw_stats: DrawStats,
+    history: VecDeque<FrameProfile>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {
+            show_overlay: false,
+            created: Instant::now(),
+            frame_start: None,
+            open_scopes: Vec::new(),
+            scopes: Vec::new(),
+            draw_stats: DrawStats::default(),
+            history: VecDeque::with_capacity(Self::HISTORY_LENGTH),
+        }
+    }
+}
+
+impl Profiler {
+    pub const HISTORY_LENGTH: usize = 240;
+
+    /// Ends the current frame, if any, and starts timing the next.
+    pub fn begin_frame(&mut self) {
+        let now = Instant::now();
+        if let Some(frame_start) = self.frame_start {
+            assert!(self.open_scopes.is_empty(), "unclosed profiler scope");
+            if self.history.len() == Self::HISTORY_LENGTH {
+                self.history.pop_front();
+            }
+            self.history.push_back(FrameProfile {
+                start: frame_start - self.created,
+                duration: now - frame_start,
+                scopes: mem::take(&mut self.scopes),
+                draw_stats: mem::take(&mut self.draw_stats),
+            });
+        }
+        self.frame_start = Some(now);
+    }
+
+    /// Starts a scope, nested in any that is still open. Scopes outside a frame are ignored.
+    pub fn begin_scope(&mut self, name: &'static str) {
+        self.open_scopes.push((name, Instant::now()));
+    }
+
+    pub fn end_scope(&mut self) {
+        let (name, start) = self.open_scopes.pop().expect("no profiler scope to end");
+        if let Some(frame_start) = self.frame_start {
+            self.scopes.push(Scope {
+                name,
+                start: start - frame_start,
+                duration: start.elapsed(),
+                depth: self.open_scopes.len() as u32,
+            });
+        }
+    }
+
+    pub fn set_draw_stats(&mut self, draw_stats: DrawStats) {
+        self.draw_stats = draw_stats;
+    }
+
+    /// The completed frames, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &FrameProfile> {
+        self.history.iter()
+    }
+
+    pub fn last_frame(&self) -> Option<&FrameProfile> {
+        self.history.back()
+    }
+
+    /// Average frame time and duration of each top level scope over the history, along with the
+    /// draw stats of the last frame.
+    pub fn summary(&self) -> Summary {
+        let mut scopes: Vec<(&'static str, Duration)> = Vec::new();
+        let mut frame_time = Duration::from_secs(0);
+        for frame in &self.history {
+            frame_time += frame.duration;
+            for scope in frame.scopes.iter().filter(|scope| scope.depth == 0) {
+                match scopes.iter_mut().find(|(name, _)| *name == scope.name) {
+                    Some((_, duration)) => *duration += scope.duration,
+                    None => scopes.push((scope.name, scope.duration)),
+                }
+            }
+        }
+        let frame_count = self.history.len().max(1) as u32;
+        Summary {
+            frame_time: frame_time / frame_count,
+            scopes: scopes
+                .into_iter()
+                .map(|(name, duration)| (name, duration / frame_count))
+                .collect(),
+            draw_stats: self.last_frame().map(|frame| frame.draw_stats),
+        }
+    }
+
+    /// Writes the history in the Chrome trace event format, for chrome://tracing or Perfetto.
+    pub fn write_chrome_trace(&self, path: &Path) -> io::Result<()> {
+        let mut events = Vec::new();
+        for frame in &self.history {
+            events.push(trace_event("frame", frame.start, frame.duration));
+            for scope in &frame.scopes {
+                events.push(trace_event(
+                    scope.name,
+                    frame.start + scope.start,
+                    scope.duration,
+                ));
+            }
+            events.push(format!(
+                r#"{{"name":"draws","ph":"C","ts":{},"pid":0,"tid":0,"args":{{"draw calls":{},"instances":{},"triangles":{}}}}}"#,
+                frame.start.as_micros(),
+                frame.draw_stats.draw_calls,
+                frame.draw_stats.instances,
+                frame.draw_stats.triangles
+            ));
+        }
+        fs::write(
+            path,
+            format!(r#"{{"traceEvents":[{}]}}"#, events.join(",\n")),
+        )
+    }
+}
+
+fn trace_event(name: &str, start: Duration, duration: Duration) -> String {
+    format!(
+        r#"{{"name":"{}","ph":"X","ts":{},"dur":{},"pid":0,"tid":0}}"#,
+        name,
+        start.as_micros(),
+        duration.as_micros()
+    )
+}
+
+pub struct Summary {
+    pub frame_time: Duration,
+    pub scopes: Vec<(&'static str, Duration)>,
+    pub draw_stats: Option<DrawStats>,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let milliseconds = |duration: Duration| duration.as_secs_f32() * 1000.0;
+        let mut scopes = String::new();
+        for (name, duration) in &self.scopes {
+            write!(scopes, ", {} {:.2} ms", name, milliseconds(*duration))?;
+        }
+        write!(f, "frame {:.2} ms{}", milliseconds(self.frame_time), scopes)?;
+        if let Some(draw_stats) = self.draw_stats {
+            write!(
+                f,
+                ", {} draw calls ({} indirect), {} instances, {} triangles",
+                draw_stats.draw_calls,
+                draw_stats.indirect_draw_calls,
+                draw_stats.instances,
+                draw_stats.triangles
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct OverlayVertex {
+    position: [f32; 2],
+    color: [f32; 4],
+}
+
+/// Draws the profiler's history as a graph in the bottom left corner, one column per frame with
+/// the top level scopes stacked in it.
+pub struct ProfilerOverlay {
+    pipeline: RenderPipeline,
+}
+
+impl ProfilerOverlay {
+    const COLUMN_WIDTH: f32 = 2.0;
+    const PIXELS_PER_MILLISECOND: f32 = 4.0;
+    const MARGIN: f32 = 10.0;
+    const SCOPE_COLORS: [[f32; 4]; 6] = [
+        [0.9, 0.3, 0.3, 0.9],
+        [0.3, 0.8, 0.3, 0.9],
+        [0.3, 0.5, 0.9, 0.9],
+        [0.9, 0.8, 0.2, 0.9],
+        [0.8, 0.3, 0.9, 0.9],
+        [0.2, 0.8, 0.8, 0.9],
+    ];
+
+    pub fn new(device: &Device, format: TextureFormat) -> Self {
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            vertex_stage: ProgrammableStageDescriptor {
+                module: &device.create_shader_module(include_spirv!("../shaders/overlay.spv")),
+                entry_point: "main",
+            },
+            fragment_stage: Some(ProgrammableStageDescriptor {
+                module: &device.create_shader_module(include_spirv!("../shaders/vertex_color.spv")),
+                entry_point: "main",
+            }),
+            rasterization_state: Some(RasterizationStateDescriptor {
+                front_face: FrontFace::Ccw,
+                cull_mode: CullMode::None,
+                clamp_depth: false,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: PrimitiveTopology::TriangleList,
+            color_states: &[ColorStateDescriptor {
+                format,
+                alpha_blend: BlendDescriptor::REPLACE,
+                color_blend: BlendDescriptor {
+                    src_factor: BlendFactor::SrcAlpha,
+                    dst_factor: BlendFactor::OneMinusSrcAlpha,
+                    operation: BlendOperation::Add,
+                },
+                write_mask: ColorWrite::ALL,
+            }],
+            depth_stencil_state: None,
+            vertex_state: VertexStateDescriptor {
+                index_format: IndexFormat::Uint16,
+                vertex_buffers: &[VertexBufferDescriptor {
+                    stride: mem::size_of::<OverlayVertex>() as BufferAddress,
+                    step_mode: InputStepMode::Vertex,
+                    attributes: &vertex_attr_array![0 => Float2, 1 => Float4],
+                }],
+            },
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Self { pipeline }
+    }
+
+    pub fn render(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        profiler: &Profiler,
+        target: &TextureView,
+        screen_width: u32,
+        screen_height: u32,
+    ) {
+        let mut vertices = Vec::new();
+        // Rectangles are given in pixels from the bottom left corner
+        let mut rectangle = |x: f32, y: f32, width: f32, height: f32, color: [f32; 4]| {
+            let to_clip = |px: f32, py: f32| OverlayVertex {
+                position: [
+                    px / screen_width as f32 * 2.0 - 1.0,
+                    py / screen_height as f32 * 2.0 - 1.0,
+                ],
+                color,
+            };
+            let (left, right, bottom, top) = (x, x + width, y, y + height);
+            vertices.extend_from_slice(&[
+                to_clip(left, bottom),
+                to_clip(right, bottom),
+                to_clip(right, top),
+                to_clip(left, bottom),
+                to_clip(right, top),
+                to_clip(left, top),
+            ]);
+        };
+
+        let graph_width = Profiler::HISTORY_LENGTH as f32 * Self::COLUMN_WIDTH;
+        let graph_height = 40.0 * Self::PIXELS_PER_MILLISECOND;
+        let height = |duration: Duration| {
+            (duration.as_secs_f32() * 1000.0 * Self::PIXELS_PER_MILLISECOND).min(graph_height)
+        };
+        rectangle(
+            Self::MARGIN,
+            Self::MARGIN,
+            graph_width,
+            graph_height,
+            [0.0, 0.0, 0.0, 0.6],
+        );
+        let mut scope_names: Vec<&'static str> = Vec::new();
+        for (i, frame) in profiler.history().enumerate() {
+            let x = Self::MARGIN + i as f32 * Self::COLUMN_WIDTH;
+            rectangle(
+                x,
+                Self::MARGIN,
+                Self::COLUMN_WIDTH,
+                height(frame.duration),
+                [0.5, 0.5, 0.5, 0.9],
+            );
+            let mut y = Self::MARGIN;
+            for scope in frame.scopes.iter().filter(|scope| scope.depth == 0) {
+                let color_index = match scope_names.iter().position(|name| *name == scope.name) {
+                    Some(index) => index,
+                    None => {
+                        scope_names.push(scope.name);
+                        scope_names.len() - 1
+                    }
+                };
+                let scope_height = height(scope.duration).min(graph_height + Self::MARGIN - y);
+                rectangle(
+                    x,
+                    y,
+                    Self::COLUMN_WIDTH,
+                    scope_height,
+                    Self::SCOPE_COLORS[color_index % Self::SCOPE_COLORS.len()],
+                );
+                y += scope_height;
+            }
+        }
+        // Lines at 60 and 30 FPS
+        for &(milliseconds, color) in &[
+            (1000.0 / 60.0, [0.2, 1.0, 0.2, 0.8]),
+            (1000.0 / 30.0, [1.0, 0.8, 0.2, 0.8]),
+        ] {
+            rectangle(
+                Self::MARGIN,
+                Self::MARGIN + milliseconds * Self::PIXELS_PER_MILLISECOND,
+                graph_width,
+                1.0,
+                color,
+            );
+        }
+
+        let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&vertices),
+            usage: BufferUsage::VERTEX,
+        });
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[RenderPassColorAttachmentDescriptor {
+                attachment: target,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        render_pass.draw(0..vertices.len() as u32, 0..1);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::bvh::{Aabb, Bvh};
+use crate::objects::Mesh;
+use ultraviolet::Vec3;
+
+/// The nearest triangle hit by a ray.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Hit {
+    /// Index into the meshes the scene was built from.
+    pub mesh: usize,
+    /// Index into the mesh's instances.
+    pub instance: u32,
+    /// Index of the triangle in the mesh's most detailed level of detail.
+    pub triangle: u32,
+    /// Distance along the ray, in multiples of its direction.
+    pub t: f32,
+    /// Weights of the triangle's three vertices at the hit.
+    pub barycentrics: Vec3,
+}
+
+/// Top level BVH over the instances of every mesh, for ray casts on the CPU. Each mesh has its
+/// own BVH in its space, so this only has to be rebuilt after instances were added or moved.
+pub struct Scene<'a> {
+    meshes: &'a [Mesh],
+    /// Mesh and instance of each of the BVH's primitives.
+    instances: Vec<(usize, u32)>,
+    bvh: Bvh,
+}
+
+impl<'a> Scene<'a> {
+    pub fn new(meshes: &'a [Mesh]) -> Self {
+        let instances = meshes
+            .iter()
+            .enumerate()
+            .flat_map(|(mesh, data)| (0..data.instances.len() as u32).map(move |i| (mesh, i)))
+            .collect::<Vec<(usize, u32)>>();
+        let bounds = instances
+            .iter()
+            .map(|&(mesh, instance)| {
+                let mesh = &meshes[mesh];
+                mesh.bvh()
+                    .bounds()
+                    .transformed(&mesh.instances[instance as usize])
+            })
+            .collect::<Vec<Aabb>>();
+        Self {
+            meshes,
+            instances,
+            bvh: Bvh::new(&bounds),
+        }
+    }
+
+    /// The nearest triangle of any instance the ray hits. Triangles are hit from both sides.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3) -> Option<Hit> {
+        self.bvh
+            .raycast(origin, direction, f32::INFINITY, |primitive, max_t| {
+                let (mesh, instance) = self.instances[primitive as usize];
+                let mesh = &self.meshes[mesh];
+                // The ray keeps its parametrization in the mesh's space, so distances carry over
+                let inverse = mesh.instances[instance as usize].inversed();
+                let local_origin = inverse.transform_vec(origin);
+                let local_direction = inverse.rotation * direction * inverse.scale;
+                let hit = mesh.bvh().raycast(local_origin, local_direction, max_t)?;
+                Some((hit.t, hit))
+            })
+            .map(|(primitive, hit)| {
+                let (mesh, instance) = self.instances[primitive as usize];
+                Hit {
+                    mesh,
+                    instance,
+                    triangle: hit.triangle,
+                    t: hit.t,
+                    barycentrics: hit.barycentrics,
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_scene_misses() {
+        let scene = Scene::new(&[]);
+        assert!(scene
+            .raycast(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0))
+            .is_none());
+    }
+}
@@ -1,35 +1,258 @@
-use crate::objects::{Mesh, Vertex};
+use crate::antialiasing::Antialiasing;
+use crate::capture::{CaptureFormat, FrameCapture};
+use crate::culling::{CullingMode, Frustum, GpuCulledInstances, GpuCulling, LodSelector};
+use crate::debug_draw::DebugDraw;
+use crate::deferred::Deferred;
+use crate::environment::Environment;
+use crate::lights::{LightClusters, PointLight};
+use crate::objects::{AlphaMode, Mesh, SkinVertex, Vertex};
+use crate::picking::{PickResult, Picking};
+use crate::postprocess::{
+    create_fullscreen_pipeline, create_linear_sampler, Exposure, PostProcess, PostProcessSettings,
+    Tonemapper, HDR_FORMAT,
+};
+use crate::profiler::{DrawStats, Profiler, ProfilerOverlay};
+use crate::ssao::{Ssao, SsaoSettings};
+use bytemuck::{Pod, Zeroable};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::error::Error;
+use std::fmt;
 use std::mem;
+use std::ops::Range;
+use std::path::PathBuf;
 use ultraviolet::projection::rh_yup::perspective_wgpu_dx;
-use ultraviolet::{Mat4, Vec3};
+use ultraviolet::{Mat4, Vec3, Vec4};
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use wgpu::*;
 
+/// Distances of the camera's near and far clip planes.
+pub const Z_NEAR: f32 = 0.1;
+pub const Z_FAR: f32 = 100.0;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RenderPath {
+    /// Each mesh is lit as it's drawn.
+    Forward,
+    /// Opaque meshes are drawn into a G-buffer and lit in a single fullscreen pass, which scales
+    /// better to many lights. Blended meshes are still drawn forward on top.
+    Deferred,
+}
+
+/// Replaces the shading with an intermediate value for debugging. Debug views are drawn with the
+/// forward path without post-processing, so the values aren't changed by exposure or tonemapping.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DebugView {
+    None,
+    Normals,
+    /// Texture coordinates wrapped to 0..1 in red and green.
+    Uvs,
+    /// World position wrapped to 0..1 in each unit.
+    WorldPosition,
+    Depth,
+    Albedo,
+    /// Shading with a white albedo.
+    Lighting,
+    /// How many times each pixel is drawn.
+    Overdraw,
+    /// The edges of the front facing triangles, hidden ones show through the triangles in front.
+    Wireframe,
+}
+
+impl DebugView {
+    pub const ALL: [Self; 9] = [
+        Self::None,
+        Self::Normals,
+        Self::Uvs,
+        Self::WorldPosition,
+        Self::Depth,
+        Self::Albedo,
+        Self::Lighting,
+        Self::Overdraw,
+        Self::Wireframe,
+    ];
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RenderSettings {
+    pub render_path: RenderPath,
+    pub antialiasing: Antialiasing,
+    /// `None` disables screen-space ambient occlusion and skips its prepass.
+    pub ambient_occlusion: Option<SsaoSettings>,
+    pub debug_view: DebugView,
+    /// Draws the triangle edges over the shaded meshes.
+    pub wireframe_overlay: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            render_path: RenderPath::Forward,
+            antialiasing: Antialiasing::Msaa(4),
+            ambient_occlusion: Some(SsaoSettings::default()),
+            debug_view: DebugView::None,
+            wireframe_overlay: false,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// See `supported_sample_counts` for `allow_unvalidated_msaa`.
+    pub fn validate(&self, allow_unvalidated_msaa: bool) -> Result<(), RenderSettingsError> {
+        let sample_count = self.antialiasing.sample_count();
+        if !supported_sample_counts(allow_unvalidated_msaa).contains(&sample_count) {
+            Err(RenderSettingsError::UnsupportedSampleCount(sample_count))
+        } else if self.render_path == RenderPath::Deferred && sample_count != 1 {
+            Err(RenderSettingsError::DeferredMultisampling)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RenderSettingsError {
+    UnsupportedSampleCount(u32),
+    DeferredMultisampling,
+}
+
+impl fmt::Display for RenderSettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnsupportedSampleCount(sample_count) => {
+                write!(f, "{}x MSAA is not supported by this adapter", sample_count)
+            }
+            Self::DeferredMultisampling => {
+                write!(f, "MSAA is not supported by the deferred render path")
+            }
+        }
+    }
+}
+
+impl Error for RenderSettingsError {}
+
+/// MSAA sample counts usable for the HDR color and depth targets, the ones WebGPU guarantees.
+/// wgpu doesn't report per format sample counts yet, so 2x and 8x can't be checked and are only
+/// allowed with `allow_unvalidated_msaa`, on adapters without them creating the pipelines fails.
+pub fn supported_sample_counts(allow_unvalidated_msaa: bool) -> &'static [u32] {
+    if allow_unvalidated_msaa {
+        &[1, 2, 4, 8]
+    } else {
+        &[1, 4]
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Camera {
+    pub position: Vec3,
+    /// Point the camera looks at, the up direction is always +Y.
+    pub target: Vec3,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            position: Vec3::new(0.0, 1.0, 2.0),
+            target: Vec3::zero(),
+        }
+    }
+}
+
+impl Camera {
+    fn view_matrix(&self) -> Mat4 {
+        Mat4::look_at(self.position, self.target, Vec3::unit_y())
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct CameraUniforms {
+    view_projection: Mat4,
+    inverse_view_projection: Mat4,
+    position: Vec3,
+    /// Index of the `DebugView`, only read by shader.frag.
+    debug_view: u32,
+}
+
+impl CameraUniforms {
+    fn new(view_projection: Mat4, position: Vec3, debug_view: DebugView) -> Self {
+        Self {
+            view_projection,
+            inverse_view_projection: view_projection.inversed(),
+            position,
+            debug_view: debug_view as u32,
+        }
+    }
+}
+
 pub struct Renderer {
+    pub culling_mode: CullingMode,
+    pub post_process_settings: PostProcessSettings,
+    pub lights: Vec<PointLight>,
+    pub profiler: Profiler,
+    pub debug_draw: DebugDraw,
+    render_settings: RenderSettings,
+    allow_unvalidated_msaa: bool,
+    screen_width: u32,
+    screen_height: u32,
+
     camera_bind_group_layout: BindGroupLayout,
     instances_bind_group_layout: BindGroupLayout,
+    environment_bind_group_layout: BindGroupLayout,
 
+    render_pipeline_layout: PipelineLayout,
     render_pipeline: RenderPipeline,
+    alpha_to_coverage_pipeline: RenderPipeline,
+    blend_pipeline: RenderPipeline,
+    overdraw_pipeline: RenderPipeline,
+    geometry_bind_group_layout: BindGroupLayout,
+    wireframe_pipeline_layout: PipelineLayout,
+    wireframe_pipeline: RenderPipeline,
+    wireframe_overlay_pipeline: RenderPipeline,
+    skybox_pipeline_layout: PipelineLayout,
+    skybox_pipeline: RenderPipeline,
+    gpu_culling: GpuCulling,
+    light_clusters: LightClusters,
+    ssao: Ssao,
+    deferred: Deferred,
+    post_process: PostProcess,
+    frame_capture: FrameCapture,
+    profiler_overlay: ProfilerOverlay,
+    picking: Picking,
 
     depth_texture: TextureView,
-    msaa_texture: TextureView,
+    /// Only used with more than one sample, otherwise the scene is rendered straight into the
+    /// post-process HDR texture.
+    msaa_texture: Option<TextureView>,
 
+    camera: Camera,
     view_matrix: Mat4,
     projection_matrix: Mat4,
     camera_uniform_buffer: Buffer,
     camera_bind_group: BindGroup,
+    frustum: Frustum,
+    lod_selector: LodSelector,
 
-    light_bind_group: BindGroup,
+    environment: Environment,
+    environment_sampler: Sampler,
 }
 
 impl Renderer {
-    pub fn new(device: &Device, screen_width: f32, screen_height: f32) -> Self {
+    pub fn new(
+        queue: &Queue,
+        device: &Device,
+        allow_unvalidated_msaa: bool,
+        screen_width: f32,
+        screen_height: f32,
+    ) -> Self {
+        let render_settings = RenderSettings::default();
+        let sample_count = render_settings.antialiasing.sample_count();
+
         let camera_bind_group_layout =
             device.create_bind_group_layout(&BindGroupLayoutDescriptor {
                 label: None,
                 entries: &[BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: ShaderStage::VERTEX,
+                    visibility: ShaderStage::VERTEX | ShaderStage::FRAGMENT,
                     ty: BindingType::UniformBuffer {
                         dynamic: false,
                         min_binding_size: None,
@@ -67,115 +290,169 @@ impl Renderer {
                         ty: BindingType::Sampler { comparison: false },
                         count: None,
                     },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStage::VERTEX,
+                        ty: BindingType::StorageBuffer {
+                            dynamic: false,
+                            min_binding_size: None,
+                            readonly: true,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStage::VERTEX | ShaderStage::FRAGMENT,
+                        ty: BindingType::UniformBuffer {
+                            dynamic: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
             });
-        let light_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: None,
-            entries: &[BindGroupLayoutEntry {
-                binding: 0,
-                visibility: ShaderStage::FRAGMENT,
-                ty: BindingType::UniformBuffer {
-                    dynamic: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
-        });
+        let light_clusters = LightClusters::new(device, screen_width as u32, screen_height as u32);
+        let environment_bind_group_layout = Environment::create_bind_group_layout(device);
 
         let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: None,
             bind_group_layouts: &[
                 &camera_bind_group_layout,
                 &instances_bind_group_layout,
-                &light_bind_group_layout,
+                light_clusters.bind_group_layout(),
+                &environment_bind_group_layout,
             ],
             push_constant_ranges: &[],
         });
-        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: None,
-            layout: Some(&render_pipeline_layout),
-            vertex_stage: ProgrammableStageDescriptor {
-                module: &device.create_shader_module(include_spirv!("../shaders/vert.spv")),
-                entry_point: "main",
-            },
-            fragment_stage: Some(ProgrammableStageDescriptor {
-                module: &device.create_shader_module(include_spirv!("../shaders/frag.spv")),
-                entry_point: "main",
-            }),
-            rasterization_state: Some(RasterizationStateDescriptor {
-                front_face: FrontFace::Ccw,
-                cull_mode: CullMode::Back,
-                clamp_depth: false,
-                depth_bias: 0,
-                depth_bias_slope_scale: 0.0,
-                depth_bias_clamp: 0.0,
-            }),
-            primitive_topology: PrimitiveTopology::TriangleList,
-            color_states: &[ColorStateDescriptor {
-                format: TextureFormat::Bgra8UnormSrgb,
-                alpha_blend: BlendDescriptor::REPLACE,
-                color_blend: BlendDescriptor::REPLACE,
-                write_mask: ColorWrite::ALL,
-            }],
-            depth_stencil_state: Some(DepthStencilStateDescriptor {
-                format: TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: CompareFunction::Less,
-                stencil: StencilStateDescriptor::default(),
-            }),
-            vertex_state: VertexStateDescriptor {
-                index_format: IndexFormat::Uint16,
-                vertex_buffers: &[VertexBufferDescriptor {
-                    stride: mem::size_of::<Vertex>() as BufferAddress,
-                    step_mode: InputStepMode::Vertex,
-                    attributes: &vertex_attr_array![0 => Float3, 1 => Float3, 2 => Float3],
-                }],
+        let render_pipeline = create_render_pipeline(
+            device,
+            &render_pipeline_layout,
+            sample_count,
+            SceneBlend::Alpha(AlphaMode::Opaque),
+        );
+        let alpha_to_coverage_pipeline = create_render_pipeline(
+            device,
+            &render_pipeline_layout,
+            sample_count,
+            SceneBlend::Alpha(AlphaMode::AlphaToCoverage),
+        );
+        let blend_pipeline = create_render_pipeline(
+            device,
+            &render_pipeline_layout,
+            sample_count,
+            SceneBlend::Alpha(AlphaMode::Blend),
+        );
+        let overdraw_pipeline = create_render_pipeline(
+            device,
+            &render_pipeline_layout,
+            sample_count,
+            SceneBlend::Overdraw,
+        );
+        let geometry_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStage::VERTEX,
+            ty: BindingType::StorageBuffer {
+                dynamic: false,
+                min_binding_size: None,
+                readonly: true,
             },
-            sample_count: 8,
-            sample_mask: !0,
-            alpha_to_coverage_enabled: false,
-        });
-
-        let depth_texture = device
-            .create_texture(&TextureDescriptor {
-                label: None,
-                size: Extent3d {
-                    width: screen_width as u32,
-                    height: screen_height as u32,
-                    depth: 1,
-                },
-                mip_level_count: 1,
-                sample_count: 8,
-                dimension: TextureDimension::D2,
-                format: TextureFormat::Depth32Float,
-                usage: TextureUsage::OUTPUT_ATTACHMENT,
-            })
-            .create_view(&TextureViewDescriptor::default());
-        let msaa_texture = device
-            .create_texture(&TextureDescriptor {
+            count: None,
+        };
+        let geometry_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
                 label: None,
-                size: Extent3d {
-                    width: screen_width as u32,
-                    height: screen_height as u32,
-                    depth: 1,
-                },
-                mip_level_count: 1,
-                sample_count: 8,
-                dimension: TextureDimension::D2,
-                format: TextureFormat::Bgra8UnormSrgb,
-                usage: TextureUsage::OUTPUT_ATTACHMENT,
-            })
-            .create_view(&TextureViewDescriptor::default());
+                entries: &[geometry_entry(0), geometry_entry(1)],
+            });
+        let wireframe_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[
+                &camera_bind_group_layout,
+                &instances_bind_group_layout,
+                &geometry_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let wireframe_pipeline = create_wireframe_pipeline(
+            device,
+            &wireframe_pipeline_layout,
+            sample_count,
+            Wireframe::Only,
+        );
+        let wireframe_overlay_pipeline = create_wireframe_pipeline(
+            device,
+            &wireframe_pipeline_layout,
+            sample_count,
+            Wireframe::Overlay,
+        );
+        let skybox_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&camera_bind_group_layout, &environment_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let skybox_pipeline = create_skybox_pipeline(device, &skybox_pipeline_layout, sample_count);
+        let gpu_culling = GpuCulling::new(device);
+        let ssao = Ssao::new(
+            device,
+            &camera_bind_group_layout,
+            &instances_bind_group_layout,
+            screen_width as u32,
+            screen_height as u32,
+        );
+        let deferred = Deferred::new(
+            device,
+            [
+                &camera_bind_group_layout,
+                &instances_bind_group_layout,
+                light_clusters.bind_group_layout(),
+                &environment_bind_group_layout,
+            ],
+            screen_width as u32,
+            screen_height as u32,
+        );
+        let post_process = PostProcess::new(
+            device,
+            TextureFormat::Bgra8UnormSrgb,
+            screen_width as u32,
+            screen_height as u32,
+        );
+        let frame_capture = FrameCapture::new(
+            device,
+            TextureFormat::Bgra8UnormSrgb,
+            screen_width as u32,
+            screen_height as u32,
+        );
+        let picking = Picking::new(
+            device,
+            &camera_bind_group_layout,
+            &instances_bind_group_layout,
+            screen_width as u32,
+            screen_height as u32,
+        );
 
-        let view_matrix = Mat4::look_at(
-            Vec3::new(0.0, 1.0, 2.0),
-            Vec3::new(0.0, 0.0, 0.0),
-            Vec3::unit_y(),
+        let depth_texture = create_depth_texture(
+            device,
+            screen_width as u32,
+            screen_height as u32,
+            sample_count,
         );
-        let projection_matrix = perspective_wgpu_dx(45.0, screen_width / screen_height, 0.1, 100.0);
+        let msaa_texture = create_msaa_texture(
+            device,
+            screen_width as u32,
+            screen_height as u32,
+            sample_count,
+        );
+
+        let camera = Camera::default();
+        let view_matrix = camera.view_matrix();
+        let projection_matrix =
+            perspective_wgpu_dx(45.0, screen_width / screen_height, Z_NEAR, Z_FAR);
         let camera_uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: None,
-            contents: (projection_matrix * view_matrix).as_byte_slice(),
+            contents: bytemuck::bytes_of(&CameraUniforms::new(
+                projection_matrix * view_matrix,
+                camera.position,
+                render_settings.debug_view,
+            )),
             usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
         });
         let camera_bind_group = device.create_bind_group(&BindGroupDescriptor {
@@ -186,51 +463,366 @@ impl Renderer {
                 resource: BindingResource::Buffer(camera_uniform_buffer.slice(..)),
             }],
         });
+        let frustum = Frustum::from_view_projection(projection_matrix * view_matrix);
+        let lod_selector = LodSelector::new(camera.position, projection_matrix);
 
-        let light_position = Vec3::new(-15.0, 15.0, 0.0);
-        let light_uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: None,
-            contents: light_position.as_byte_slice(),
-            usage: BufferUsage::UNIFORM,
-        });
-        let light_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: None,
-            layout: &light_bind_group_layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: BindingResource::Buffer(light_uniform_buffer.slice(..)),
-            }],
-        });
+        let environment = Environment::default_sky(device, queue);
+        let environment_sampler = create_linear_sampler(device);
 
         Self {
+            culling_mode: CullingMode::Gpu,
+            post_process_settings: PostProcessSettings::default(),
+            lights: vec![PointLight {
+                position: Vec3::new(-15.0, 15.0, 0.0),
+                range: 100.0,
+                color: Vec3::one(),
+                intensity: 450.0,
+            }],
+            profiler: Profiler::default(),
+            debug_draw: DebugDraw::new(device, &camera_bind_group_layout, sample_count),
+            render_settings,
+            allow_unvalidated_msaa,
+            screen_width: screen_width as u32,
+            screen_height: screen_height as u32,
+
             camera_bind_group_layout,
             instances_bind_group_layout,
+            environment_bind_group_layout,
 
+            render_pipeline_layout,
             render_pipeline,
+            alpha_to_coverage_pipeline,
+            blend_pipeline,
+            overdraw_pipeline,
+            geometry_bind_group_layout,
+            wireframe_pipeline_layout,
+            wireframe_pipeline,
+            wireframe_overlay_pipeline,
+            skybox_pipeline_layout,
+            skybox_pipeline,
+            gpu_culling,
+            light_clusters,
+            ssao,
+            deferred,
+            post_process,
+            frame_capture,
+            profiler_overlay: ProfilerOverlay::new(device, TextureFormat::Bgra8UnormSrgb),
+            picking,
 
             depth_texture,
             msaa_texture,
 
+            camera,
             view_matrix,
             projection_matrix,
             camera_uniform_buffer,
             camera_bind_group,
+            frustum,
+            lod_selector,
 
-            light_bind_group,
+            environment,
+            environment_sampler,
         }
     }
 
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.environment = environment;
+    }
+
+    /// Whether MSAA sample counts the adapter might not support are allowed, see
+    /// `supported_sample_counts`.
+    pub fn allow_unvalidated_msaa(&self) -> bool {
+        self.allow_unvalidated_msaa
+    }
+
+    pub fn camera(&self) -> Camera {
+        self.camera
+    }
+
+    /// The camera's unjittered view projection matrix.
+    pub fn view_projection(&self) -> Mat4 {
+        self.projection_matrix * self.view_matrix
+    }
+
+    /// Origin on the near plane and direction of the ray through the pixel `x`, `y` from the top
+    /// left, normalized so ray distances are in world units.
+    pub fn screen_ray(&self, x: u32, y: u32) -> (Vec3, Vec3) {
+        let inverse_view_projection = self.view_projection().inversed();
+        let ndc_x = (x as f32 + 0.5) / self.screen_width as f32 * 2.0 - 1.0;
+        let ndc_y = 1.0 - (y as f32 + 0.5) / self.screen_height as f32 * 2.0;
+        let unproject = |depth: f32| {
+            let point = inverse_view_projection * Vec4::new(ndc_x, ndc_y, depth, 1.0);
+            point.xyz() / point.w
+        };
+        let near = unproject(0.0);
+        (near, (unproject(1.0) - near).normalized())
+    }
+
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera = camera;
+        self.view_matrix = camera.view_matrix();
+        self.frustum = Frustum::from_view_projection(self.projection_matrix * self.view_matrix);
+        self.lod_selector = LodSelector::new(camera.position, self.projection_matrix);
+        self.post_process.invalidate_taa_history();
+    }
+
+    pub fn render_settings(&self) -> RenderSettings {
+        self.render_settings
+    }
+
+    /// Rebuilds the scene pipeline and attachments if the sample count changes.
+    pub fn set_render_settings(
+        &mut self,
+        device: &Device,
+        render_settings: RenderSettings,
+    ) -> Result<(), RenderSettingsError> {
+        render_settings.validate(self.allow_unvalidated_msaa)?;
+        let sample_count = render_settings.antialiasing.sample_count();
+        if sample_count != self.render_settings.antialiasing.sample_count() {
+            self.render_pipeline = create_render_pipeline(
+                device,
+                &self.render_pipeline_layout,
+                sample_count,
+                SceneBlend::Alpha(AlphaMode::Opaque),
+            );
+            self.alpha_to_coverage_pipeline = create_render_pipeline(
+                device,
+                &self.render_pipeline_layout,
+                sample_count,
+                SceneBlend::Alpha(AlphaMode::AlphaToCoverage),
+            );
+            self.blend_pipeline = create_render_pipeline(
+                device,
+                &self.render_pipeline_layout,
+                sample_count,
+                SceneBlend::Alpha(AlphaMode::Blend),
+            );
+            self.overdraw_pipeline = create_render_pipeline(
+                device,
+                &self.render_pipeline_layout,
+                sample_count,
+                SceneBlend::Overdraw,
+            );
+            self.wireframe_pipeline = create_wireframe_pipeline(
+                device,
+                &self.wireframe_pipeline_layout,
+                sample_count,
+                Wireframe::Only,
+            );
+            self.wireframe_overlay_pipeline = create_wireframe_pipeline(
+                device,
+                &self.wireframe_pipeline_layout,
+                sample_count,
+                Wireframe::Overlay,
+            );
+            self.skybox_pipeline =
+                create_skybox_pipeline(device, &self.skybox_pipeline_layout, sample_count);
+            self.debug_draw.set_sample_count(device, sample_count);
+            self.depth_texture =
+                create_depth_texture(device, self.screen_width, self.screen_height, sample_count);
+            self.msaa_texture =
+                create_msaa_texture(device, self.screen_width, self.screen_height, sample_count);
+        }
+        self.render_settings = render_settings;
+        Ok(())
+    }
+
     pub fn render(
-        &self,
+        &mut self,
         device: &Device,
         encoder: &mut CommandEncoder,
         meshes: &[Mesh],
         render_target: &TextureView,
     ) {
-        encoder.begin_render_pass(&RenderPassDescriptor {
+        let debug_view = self.render_settings.debug_view;
+        let wireframe = match debug_view {
+            DebugView::Wireframe => Some(Wireframe::Only),
+            _ if self.render_settings.wireframe_overlay => Some(Wireframe::Overlay),
+            _ => None,
+        };
+        let render_path = match debug_view {
+            DebugView::None => self.render_settings.render_path,
+            _ => RenderPath::Forward,
+        };
+
+        self.profiler.begin_scope("culling");
+        // Picks report indices into the unfiltered meshes
+        let mesh_ids = (0..meshes.len())
+            .filter(|&i| !meshes[i].instances.is_empty())
+            .collect::<Vec<usize>>();
+        let meshes = mesh_ids.iter().map(|&i| &meshes[i]).collect::<Vec<&Mesh>>();
+        let transforms_buffers = meshes
+            .iter()
+            .map(|mesh| mesh.create_transforms_buffer(device))
+            .collect::<Vec<Buffer>>();
+        // Blended meshes are always culled on the CPU, as their instances have to be sorted, and so
+        // is everything when drawing wireframes, which don't draw indexed
+        let cpu_culled_instances = meshes
+            .par_iter()
+            .map(|mesh| {
+                if self.culling_mode == CullingMode::Cpu
+                    || mesh.alpha_mode == AlphaMode::Blend
+                    || wireframe.is_some()
+                {
+                    Some(mesh.cull_instances(&self.frustum, &self.lod_selector))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<Option<Vec<Vec<u32>>>>>();
+        let instance_draws = meshes
+            .iter()
+            .zip(&transforms_buffers)
+            .zip(cpu_culled_instances)
+            .map(|((mesh, transforms_buffer), lods)| match lods {
+                Some(lods) => InstanceDraw::direct(device, mesh, lods, self.camera.position),
+                None => Some(InstanceDraw::Indirect(self.gpu_culling.cull(
+                    device,
+                    encoder,
+                    mesh,
+                    &self.frustum,
+                    &self.lod_selector,
+                    transforms_buffer,
+                ))),
+            })
+            .collect::<Vec<Option<InstanceDraw>>>();
+        self.profiler.end_scope();
+        let mut draw_stats = DrawStats::default();
+
+        let mut view_projection = self.projection_matrix * self.view_matrix;
+        if self.render_settings.antialiasing == Antialiasing::Taa {
+            let (jitter_x, jitter_y) = self.post_process.taa_jitter();
+            view_projection = Mat4::from_translation(Vec3::new(
+                jitter_x * 2.0 / self.screen_width as f32,
+                jitter_y * 2.0 / self.screen_height as f32,
+                0.0,
+            )) * view_projection;
+        }
+        let camera_staging_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::bytes_of(&CameraUniforms::new(
+                view_projection,
+                self.camera.position,
+                self.render_settings.debug_view,
+            )),
+            usage: BufferUsage::COPY_SRC,
+        });
+        encoder.copy_buffer_to_buffer(
+            &camera_staging_buffer,
+            0,
+            &self.camera_uniform_buffer,
+            0,
+            mem::size_of::<CameraUniforms>() as BufferAddress,
+        );
+
+        let mesh_bind_groups = meshes
+            .iter()
+            .zip(&transforms_buffers)
+            .zip(&instance_draws)
+            .map(|((mesh, transforms_buffer), instance_draw)| {
+                instance_draw.as_ref().map(|instance_draw| {
+                    mesh.create_instances_bind_group(
+                        device,
+                        &self.instances_bind_group_layout,
+                        transforms_buffer,
+                        instance_draw.visible_instances_buffer(),
+                        self.rendered_alpha_mode(mesh),
+                    )
+                })
+            })
+            .collect::<Vec<Option<BindGroup>>>();
+
+        self.profiler.begin_scope("ssao");
+        match &self.render_settings.ambient_occlusion {
+            Some(ssao_settings) => {
+                let mut prepass = self.ssao.begin_prepass(encoder);
+                prepass.set_bind_group(0, &self.camera_bind_group, &[]);
+                for ((mesh, mesh_bind_group), instance_draw) in
+                    meshes.iter().zip(&mesh_bind_groups).zip(&instance_draws)
+                {
+                    // Blended meshes don't write depth, so they don't occlude anything either
+                    if mesh.alpha_mode == AlphaMode::Blend {
+                        continue;
+                    }
+                    if let (Some(mesh_bind_group), Some(instance_draw)) =
+                        (mesh_bind_group, instance_draw)
+                    {
+                        prepass.set_bind_group(1, mesh_bind_group, &[]);
+                        draw_mesh(&mut prepass, mesh, instance_draw, &mut draw_stats);
+                    }
+                }
+                drop(prepass);
+                self.ssao
+                    .render(device, encoder, ssao_settings, &self.camera_bind_group);
+            }
+            None => self.ssao.clear(encoder),
+        }
+        self.profiler.end_scope();
+        let environment_bind_group = self.environment.create_bind_group(
+            device,
+            &self.environment_bind_group_layout,
+            &self.environment_sampler,
+            self.ssao.output(),
+        );
+
+        self.profiler.begin_scope("lights");
+        let lights_bind_group = self.light_clusters.update(
+            device,
+            encoder,
+            &self.lights,
+            self.view_matrix,
+            self.projection_matrix,
+        );
+        self.profiler.end_scope();
+
+        self.profiler.begin_scope("scene");
+        // Opaque meshes fill the depth buffer first, then blended ones are drawn back to front on
+        // top. Instances of different blended meshes aren't interleaved, the meshes are ordered by
+        // their farthest instance.
+        let mut opaque_meshes = Vec::new();
+        let mut blended_meshes = Vec::new();
+        for (i, mesh) in meshes.iter().enumerate() {
+            if mesh_bind_groups[i].is_none() {
+                continue;
+            }
+            match mesh.alpha_mode {
+                AlphaMode::Blend => blended_meshes.push(i),
+                _ => opaque_meshes.push(i),
+            }
+        }
+        let farthest_instance = |i: usize| {
+            (0..meshes[i].instances.len() as u32)
+                .map(|instance| instance_distance(meshes[i], instance, self.camera.position))
+                .fold(0.0, f32::max)
+        };
+        blended_meshes.sort_by(|&a, &b| farthest_instance(b).total_cmp(&farthest_instance(a)));
+
+        if render_path == RenderPath::Deferred {
+            let mut geometry_pass = self.deferred.begin_geometry_pass(encoder);
+            geometry_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            for &i in &opaque_meshes {
+                if let (Some(mesh_bind_group), Some(instance_draw)) =
+                    (&mesh_bind_groups[i], &instance_draws[i])
+                {
+                    geometry_pass.set_bind_group(1, mesh_bind_group, &[]);
+                    draw_mesh(
+                        &mut geometry_pass,
+                        meshes[i],
+                        instance_draw,
+                        &mut draw_stats,
+                    );
+                }
+            }
+        }
+
+        let (color_attachment, resolve_target) = match &self.msaa_texture {
+            Some(msaa_texture) => (msaa_texture, Some(self.post_process.hdr_texture())),
+            None => (self.post_process.hdr_texture(), None),
+        };
+        let mut skybox_pass = encoder.begin_render_pass(&RenderPassDescriptor {
             color_attachments: &[RenderPassColorAttachmentDescriptor {
-                attachment: &self.msaa_texture,
-                resolve_target: Some(render_target),
+                attachment: color_attachment,
+                resolve_target,
                 ops: Operations {
                     load: LoadOp::Clear(Color::BLACK),
                     store: true,
@@ -238,75 +830,306 @@ impl Renderer {
             }],
             depth_stencil_attachment: None,
         });
+        skybox_pass.set_pipeline(&self.skybox_pipeline);
+        skybox_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        skybox_pass.set_bind_group(1, &environment_bind_group, &[]);
+        if debug_view == DebugView::None {
+            skybox_pass.draw(0..3, 0..1);
+        }
+        drop(skybox_pass);
 
-        for mesh in meshes {
-            let mesh_bind_group =
-                mesh.create_instances_bind_group(device, &self.instances_bind_group_layout);
+        let geometry_bind_groups = meshes
+            .iter()
+            .map(|mesh| {
+                if wireframe.is_some() {
+                    Some(device.create_bind_group(&BindGroupDescriptor {
+                        label: None,
+                        layout: &self.geometry_bind_group_layout,
+                        entries: &[
+                            BindGroupEntry {
+                                binding: 0,
+                                resource: BindingResource::Buffer(mesh.vertex_buffer().slice(..)),
+                            },
+                            BindGroupEntry {
+                                binding: 1,
+                                resource: BindingResource::Buffer(mesh.index_buffer().slice(..)),
+                            },
+                        ],
+                    }))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<Option<BindGroup>>>();
+        let forward_passes = match render_path {
+            RenderPath::Forward => vec![
+                (&opaque_meshes, &self.depth_texture, LoadOp::Clear(1.0)),
+                (&blended_meshes, &self.depth_texture, LoadOp::Load),
+            ],
+            RenderPath::Deferred => {
+                self.deferred.render_lighting(
+                    device,
+                    encoder,
+                    &self.camera_bind_group,
+                    &lights_bind_group,
+                    &environment_bind_group,
+                    color_attachment,
+                );
+                vec![(&blended_meshes, self.deferred.depth_texture(), LoadOp::Load)]
+            }
+        };
+        for (mesh_indices, depth_texture, depth_load) in forward_passes {
             let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
                 color_attachments: &[RenderPassColorAttachmentDescriptor {
-                    attachment: &self.msaa_texture,
-                    resolve_target: Some(render_target),
+                    attachment: color_attachment,
+                    resolve_target,
                     ops: Operations {
                         load: LoadOp::Load,
                         store: true,
                     },
                 }],
                 depth_stencil_attachment: Some(RenderPassDepthStencilAttachmentDescriptor {
-                    attachment: &self.depth_texture,
+                    attachment: depth_texture,
                     depth_ops: Some(Operations {
-                        load: LoadOp::Clear(1.0),
+                        load: depth_load,
                         store: true,
                     }),
                     stencil_ops: None,
                 }),
             });
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_vertex_buffer(0, mesh.vertex_buffer().slice(..));
-            render_pass.set_index_buffer(mesh.index_buffer().slice(..));
             render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-            render_pass.set_bind_group(1, &mesh_bind_group, &[]);
-            render_pass.set_bind_group(2, &self.light_bind_group, &[]);
-            render_pass.draw_indexed(0..mesh.index_count(), 0, 0..mesh.instances.len() as u32);
+            render_pass.set_bind_group(2, &lights_bind_group, &[]);
+            render_pass.set_bind_group(3, &environment_bind_group, &[]);
+            for &i in mesh_indices {
+                let (mesh_bind_group, instance_draw) =
+                    match (&mesh_bind_groups[i], &instance_draws[i]) {
+                        (Some(mesh_bind_group), Some(instance_draw)) => {
+                            (mesh_bind_group, instance_draw)
+                        }
+                        _ => continue,
+                    };
+                render_pass.set_bind_group(1, mesh_bind_group, &[]);
+                if wireframe == Some(Wireframe::Only) {
+                    render_pass.set_pipeline(&self.wireframe_pipeline);
+                    render_pass.set_bind_group(2, geometry_bind_groups[i].as_ref().unwrap(), &[]);
+                    draw_mesh_unindexed(
+                        &mut render_pass,
+                        meshes[i],
+                        instance_draw,
+                        &mut draw_stats,
+                    );
+                    continue;
+                }
+                render_pass.set_pipeline(match (debug_view, self.rendered_alpha_mode(meshes[i])) {
+                    (DebugView::Overdraw, _) => &self.overdraw_pipeline,
+                    (_, AlphaMode::Opaque) | (_, AlphaMode::Mask { .. }) => &self.render_pipeline,
+                    (_, AlphaMode::AlphaToCoverage) => &self.alpha_to_coverage_pipeline,
+                    (_, AlphaMode::Blend) => &self.blend_pipeline,
+                });
+                draw_mesh(&mut render_pass, meshes[i], instance_draw, &mut draw_stats);
+            }
+        }
+        // The wireframe overlay and debug lines are depth tested against the finished scene
+        let has_debug_lines = self.debug_draw.prepare(device);
+        if wireframe == Some(Wireframe::Overlay) || has_debug_lines {
+            let depth_texture = match render_path {
+                RenderPath::Forward => &self.depth_texture,
+                RenderPath::Deferred => self.deferred.depth_texture(),
+            };
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                color_attachments: &[RenderPassColorAttachmentDescriptor {
+                    attachment: color_attachment,
+                    resolve_target,
+                    ops: Operations {
+                        load: LoadOp::Load,
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachmentDescriptor {
+                    attachment: depth_texture,
+                    depth_ops: Some(Operations {
+                        load: LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            if wireframe == Some(Wireframe::Overlay) {
+                render_pass.set_pipeline(&self.wireframe_overlay_pipeline);
+                for &i in opaque_meshes.iter().chain(&blended_meshes) {
+                    if let (Some(mesh_bind_group), Some(instance_draw)) =
+                        (&mesh_bind_groups[i], &instance_draws[i])
+                    {
+                        render_pass.set_bind_group(1, mesh_bind_group, &[]);
+                        render_pass.set_bind_group(
+                            2,
+                            geometry_bind_groups[i].as_ref().unwrap(),
+                            &[],
+                        );
+                        draw_mesh_unindexed(
+                            &mut render_pass,
+                            meshes[i],
+                            instance_draw,
+                            &mut draw_stats,
+                        );
+                    }
+                }
+            }
+            self.debug_draw.draw(&mut render_pass);
+        }
+        self.profiler.end_scope();
+
+        if self.picking.has_requests() {
+            self.profiler.begin_scope("picking");
+            let pick_bind_groups = mesh_ids
+                .iter()
+                .map(|&id| self.picking.mesh_bind_group(device, id))
+                .collect::<Vec<BindGroup>>();
+            let mut pick_pass = self.picking.begin_pass(encoder);
+            pick_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            for i in 0..meshes.len() {
+                if let (Some(mesh_bind_group), Some(instance_draw)) =
+                    (&mesh_bind_groups[i], &instance_draws[i])
+                {
+                    pick_pass.set_bind_group(1, mesh_bind_group, &[]);
+                    pick_pass.set_bind_group(2, &pick_bind_groups[i], &[]);
+                    draw_mesh(&mut pick_pass, meshes[i], instance_draw, &mut draw_stats);
+                }
+            }
+            drop(pick_pass);
+            self.picking.copy_requested(device, encoder);
+            self.profiler.end_scope();
+        }
+        self.profiler.set_draw_stats(draw_stats);
+
+        self.profiler.begin_scope("post-process");
+        let captures = self.frame_capture.begin_frame();
+        let post_process_target = if captures
+            .iter()
+            .any(|(_, format)| *format == CaptureFormat::Png)
+        {
+            self.frame_capture.ldr_target()
+        } else {
+            render_target
+        };
+        let post_process_settings = match debug_view {
+            DebugView::None => self.post_process_settings,
+            _ => PostProcessSettings {
+                exposure: Exposure::Manual(0.0),
+                tonemapper: Tonemapper::None,
+                gamma: 1.0,
+                bloom: None,
+            },
+        };
+        self.post_process.render(
+            device,
+            encoder,
+            &post_process_settings,
+            self.render_settings.antialiasing,
+            post_process_target,
+        );
+        if !captures.is_empty() {
+            self.frame_capture.copy_frame(
+                device,
+                encoder,
+                captures,
+                self.post_process.hdr_texture(),
+                render_target,
+            );
+        }
+        // Drawn last so it's left out of captures
+        if self.profiler.show_overlay {
+            self.profiler_overlay.render(
+                device,
+                encoder,
+                &self.profiler,
+                render_target,
+                self.screen_width,
+                self.screen_height,
+            );
+        }
+        self.profiler.end_scope();
+    }
+
+    /// Saves the next rendered frame to `path`.
+    pub fn capture_frame(&mut self, path: PathBuf, format: CaptureFormat) {
+        self.frame_capture.capture(path, format);
+    }
+
+    /// Saves the next `frame_count` rendered frames as a numbered image sequence in `directory`.
+    pub fn record_frames(&mut self, directory: PathBuf, frame_count: u32, format: CaptureFormat) {
+        self.frame_capture.record(directory, frame_count, format);
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.frame_capture.is_recording()
+    }
+
+    /// Starts writing the frames captured by `render` in the background, call it once the
+    /// command buffer `render` recorded into has been submitted.
+    pub fn write_captures(&mut self, device: &Device) {
+        self.frame_capture.write_pending(device);
+    }
+
+    /// Reads back the mesh and instance drawn at the pixel `x`, `y` from the top left in the next
+    /// rendered frame, the result arrives through `picks` a frame or more later.
+    pub fn pick(&mut self, x: u32, y: u32) {
+        self.picking.request(x, y);
+    }
+
+    /// Starts reading back the picks requested for `render`, call it once the command buffer
+    /// `render` recorded into has been submitted.
+    pub fn read_picks(&mut self) {
+        self.picking.read_pending();
+    }
+
+    /// The picks that finished reading back since the last call.
+    pub fn picks(&self) -> Vec<PickResult> {
+        self.picking.results()
+    }
+
+    /// Alpha-to-coverage needs multisampling, otherwise it's replaced with alpha testing.
+    fn rendered_alpha_mode(&self, mesh: &Mesh) -> AlphaMode {
+        match mesh.alpha_mode {
+            AlphaMode::AlphaToCoverage if self.render_settings.antialiasing.sample_count() == 1 => {
+                AlphaMode::Mask { cutoff: 0.5 }
+            }
+            alpha_mode => alpha_mode,
         }
     }
 
     pub fn set_screen_size(&mut self, queue: &Queue, device: &Device, width: f32, height: f32) {
-        self.depth_texture = device
-            .create_texture(&TextureDescriptor {
-                label: None,
-                size: Extent3d {
-                    width: width as u32,
-                    height: height as u32,
-                    depth: 1,
-                },
-                mip_level_count: 1,
-                sample_count: 8,
-                dimension: TextureDimension::D2,
-                format: TextureFormat::Depth32Float,
-                usage: TextureUsage::OUTPUT_ATTACHMENT,
-            })
-            .create_view(&TextureViewDescriptor::default());
-        self.msaa_texture = device
-            .create_texture(&TextureDescriptor {
-                label: None,
-                size: Extent3d {
-                    width: width as u32,
-                    height: height as u32,
-                    depth: 1,
-                },
-                mip_level_count: 1,
-                sample_count: 8,
-                dimension: TextureDimension::D2,
-                format: TextureFormat::Bgra8UnormSrgb,
-                usage: TextureUsage::OUTPUT_ATTACHMENT,
-            })
-            .create_view(&TextureViewDescriptor::default());
+        let sample_count = self.render_settings.antialiasing.sample_count();
+        self.screen_width = width as u32;
+        self.screen_height = height as u32;
+        self.depth_texture =
+            create_depth_texture(device, width as u32, height as u32, sample_count);
+        self.msaa_texture = create_msaa_texture(device, width as u32, height as u32, sample_count);
+        self.ssao
+            .set_screen_size(device, width as u32, height as u32);
+        self.deferred
+            .set_screen_size(device, width as u32, height as u32);
+        self.light_clusters
+            .set_screen_size(width as u32, height as u32);
+        self.frame_capture
+            .set_screen_size(device, width as u32, height as u32);
+        self.picking
+            .set_screen_size(device, width as u32, height as u32);
+        self.post_process
+            .set_screen_size(device, width as u32, height as u32);
 
-        self.projection_matrix = perspective_wgpu_dx(45.0, width / height, 0.1, 100.0);
+        self.projection_matrix = perspective_wgpu_dx(45.0, width / height, Z_NEAR, Z_FAR);
+        self.frustum = Frustum::from_view_projection(self.projection_matrix * self.view_matrix);
+        self.lod_selector = LodSelector::new(self.camera.position, self.projection_matrix);
         queue.write_buffer(
             &self.camera_uniform_buffer,
             0,
-            (self.projection_matrix * self.view_matrix).as_byte_slice(),
+            bytemuck::bytes_of(&CameraUniforms::new(
+                self.projection_matrix * self.view_matrix,
+                self.camera.position,
+                self.render_settings.debug_view,
+            )),
         );
         self.camera_bind_group = device.create_bind_group(&BindGroupDescriptor {
             label: None,
@@ -318,3 +1141,344 @@ impl Renderer {
         });
     }
 }
+
+/// Draws every level of detail of `mesh` with the instances selected for it, the pipeline and bind
+/// groups have to be set already.
+fn draw_mesh<'a>(
+    render_pass: &mut RenderPass<'a>,
+    mesh: &'a Mesh,
+    instance_draw: &'a InstanceDraw,
+    draw_stats: &mut DrawStats,
+) {
+    render_pass.set_vertex_buffer(0, mesh.vertex_buffer().slice(..));
+    render_pass.set_vertex_buffer(1, mesh.skin_vertices());
+    render_pass.set_index_buffer(mesh.index_buffer().slice(..));
+    match instance_draw {
+        InstanceDraw::Direct { draws, .. } => {
+            for (lod, instances) in draws {
+                let indices = mesh.lods()[*lod].clone();
+                draw_stats.add_draw(indices.len() as u32, instances.len() as u32);
+                render_pass.draw_indexed(indices, 0, instances.clone());
+            }
+        }
+        InstanceDraw::Indirect(culled_instances) => {
+            for lod in 0..mesh.lods().len() as BufferAddress {
+                render_pass.draw_indexed_indirect(
+                    &culled_instances.draw_args_buffer,
+                    lod * GpuCulling::DRAW_ARGS_STRIDE,
+                );
+                draw_stats.add_indirect_draw();
+            }
+        }
+    }
+}
+
+/// Like `draw_mesh` but without the index buffer, for pipelines that fetch the indices themselves.
+/// Only supports instances culled on the CPU.
+fn draw_mesh_unindexed<'a>(
+    render_pass: &mut RenderPass<'a>,
+    mesh: &'a Mesh,
+    instance_draw: &'a InstanceDraw,
+    draw_stats: &mut DrawStats,
+) {
+    match instance_draw {
+        InstanceDraw::Direct { draws, .. } => {
+            for (lod, instances) in draws {
+                let indices = mesh.lods()[*lod].clone();
+                draw_stats.add_draw(indices.len() as u32, instances.len() as u32);
+                render_pass.draw(indices, instances.clone());
+            }
+        }
+        InstanceDraw::Indirect(_) => panic!("unindexed draws need instances culled on the CPU"),
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum SceneBlend {
+    Alpha(AlphaMode),
+    /// Adds up every fragment without depth testing, for `DebugView::Overdraw`.
+    Overdraw,
+}
+
+/// Opaque meshes and masked ones, which only discard fragments in the shader, share a pipeline.
+fn create_render_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    sample_count: u32,
+    blend: SceneBlend,
+) -> RenderPipeline {
+    let color_blend = match blend {
+        SceneBlend::Alpha(AlphaMode::Blend) => BlendDescriptor {
+            src_factor: BlendFactor::SrcAlpha,
+            dst_factor: BlendFactor::OneMinusSrcAlpha,
+            operation: BlendOperation::Add,
+        },
+        SceneBlend::Overdraw => BlendDescriptor {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::One,
+            operation: BlendOperation::Add,
+        },
+        SceneBlend::Alpha(_) => BlendDescriptor::REPLACE,
+    };
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        vertex_stage: ProgrammableStageDescriptor {
+            module: &device.create_shader_module(include_spirv!("../shaders/vert.spv")),
+            entry_point: "main",
+        },
+        fragment_stage: Some(ProgrammableStageDescriptor {
+            module: &device.create_shader_module(include_spirv!("../shaders/frag.spv")),
+            entry_point: "main",
+        }),
+        rasterization_state: Some(RasterizationStateDescriptor {
+            front_face: FrontFace::Ccw,
+            cull_mode: CullMode::Back,
+            clamp_depth: false,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+            depth_bias_clamp: 0.0,
+        }),
+        primitive_topology: PrimitiveTopology::TriangleList,
+        color_states: &[ColorStateDescriptor {
+            format: HDR_FORMAT,
+            alpha_blend: BlendDescriptor::REPLACE,
+            color_blend,
+            write_mask: ColorWrite::ALL,
+        }],
+        depth_stencil_state: Some(DepthStencilStateDescriptor {
+            format: TextureFormat::Depth32Float,
+            depth_write_enabled: !matches!(
+                blend,
+                SceneBlend::Alpha(AlphaMode::Blend) | SceneBlend::Overdraw
+            ),
+            depth_compare: match blend {
+                SceneBlend::Overdraw => CompareFunction::Always,
+                SceneBlend::Alpha(_) => CompareFunction::Less,
+            },
+            stencil: StencilStateDescriptor::default(),
+        }),
+        vertex_state: VertexStateDescriptor {
+            index_format: IndexFormat::Uint16,
+            vertex_buffers: &[
+                VertexBufferDescriptor {
+                    stride: mem::size_of::<Vertex>() as BufferAddress,
+                    step_mode: InputStepMode::Vertex,
+                    attributes: &vertex_attr_array![0 => Float3, 1 => Float3, 2 => Float3],
+                },
+                VertexBufferDescriptor {
+                    stride: mem::size_of::<SkinVertex>() as BufferAddress,
+                    step_mode: InputStepMode::Vertex,
+                    attributes: &vertex_attr_array![3 => Uint4, 4 => Float4],
+                },
+            ],
+        },
+        sample_count,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: blend == SceneBlend::Alpha(AlphaMode::AlphaToCoverage),
+    })
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Wireframe {
+    /// Replaces the shaded meshes, for `DebugView::Wireframe`.
+    Only,
+    /// Drawn over the shaded meshes, depth tested against them.
+    Overlay,
+}
+
+/// wgpu 0.6 has no polygon modes, `Features::NON_FILL_POLYGON_MODE` only arrives in 0.7, so
+/// wireframes are always drawn as triangles which discard the fragments away from their edges.
+fn create_wireframe_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    sample_count: u32,
+    wireframe: Wireframe,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        vertex_stage: ProgrammableStageDescriptor {
+            module: &device.create_shader_module(include_spirv!("../shaders/wireframe_vert.spv")),
+            entry_point: "main",
+        },
+        fragment_stage: Some(ProgrammableStageDescriptor {
+            module: &device.create_shader_module(include_spirv!("../shaders/wireframe_frag.spv")),
+            entry_point: "main",
+        }),
+        rasterization_state: Some(RasterizationStateDescriptor {
+            front_face: FrontFace::Ccw,
+            cull_mode: CullMode::Back,
+            clamp_depth: false,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+            depth_bias_clamp: 0.0,
+        }),
+        primitive_topology: PrimitiveTopology::TriangleList,
+        color_states: &[ColorStateDescriptor {
+            format: HDR_FORMAT,
+            alpha_blend: BlendDescriptor::REPLACE,
+            color_blend: BlendDescriptor::REPLACE,
+            write_mask: ColorWrite::ALL,
+        }],
+        // The overlay's triangles are at the exact same depth as the shaded ones, see shader.vert
+        depth_stencil_state: Some(DepthStencilStateDescriptor {
+            format: TextureFormat::Depth32Float,
+            depth_write_enabled: wireframe == Wireframe::Only,
+            depth_compare: match wireframe {
+                Wireframe::Only => CompareFunction::Less,
+                Wireframe::Overlay => CompareFunction::LessEqual,
+            },
+            stencil: StencilStateDescriptor::default(),
+        }),
+        vertex_state: VertexStateDescriptor {
+            index_format: IndexFormat::Uint16,
+            vertex_buffers: &[],
+        },
+        sample_count,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: false,
+    })
+}
+
+fn create_skybox_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    sample_count: u32,
+) -> RenderPipeline {
+    create_fullscreen_pipeline(
+        device,
+        layout,
+        &device.create_shader_module(include_spirv!("../shaders/skybox.spv")),
+        HDR_FORMAT,
+        BlendDescriptor::REPLACE,
+        sample_count,
+    )
+}
+
+fn create_depth_texture(
+    device: &Device,
+    width: u32,
+    height: u32,
+    sample_count: u32,
+) -> TextureView {
+    device
+        .create_texture(&TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Depth32Float,
+            usage: TextureUsage::OUTPUT_ATTACHMENT,
+        })
+        .create_view(&TextureViewDescriptor::default())
+}
+
+fn create_msaa_texture(
+    device: &Device,
+    width: u32,
+    height: u32,
+    sample_count: u32,
+) -> Option<TextureView> {
+    if sample_count == 1 {
+        return None;
+    }
+    Some(
+        device
+            .create_texture(&TextureDescriptor {
+                label: None,
+                size: Extent3d {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: TextureDimension::D2,
+                format: HDR_FORMAT,
+                usage: TextureUsage::OUTPUT_ATTACHMENT,
+            })
+            .create_view(&TextureViewDescriptor::default()),
+    )
+}
+
+enum InstanceDraw {
+    Direct {
+        visible_instances_buffer: Buffer,
+        /// Level of detail and range of `visible_instances_buffer` of each draw.
+        draws: Vec<(usize, Range<u32>)>,
+    },
+    Indirect(GpuCulledInstances),
+}
+
+impl InstanceDraw {
+    /// Uploads the instances culled on the CPU, grouped by level of detail. Those of blended meshes
+    /// are sorted back to front instead, so consecutive instances only share a draw while they use
+    /// the same level of detail.
+    fn direct(
+        device: &Device,
+        mesh: &Mesh,
+        lods: Vec<Vec<u32>>,
+        camera_position: Vec3,
+    ) -> Option<Self> {
+        let mut instances = lods
+            .into_iter()
+            .enumerate()
+            .flat_map(|(lod, instances)| instances.into_iter().map(move |instance| (lod, instance)))
+            .collect::<Vec<(usize, u32)>>();
+        if instances.is_empty() {
+            return None;
+        }
+        if mesh.alpha_mode == AlphaMode::Blend {
+            instances.sort_by(|(_, a), (_, b)| {
+                instance_distance(mesh, *b, camera_position).total_cmp(&instance_distance(
+                    mesh,
+                    *a,
+                    camera_position,
+                ))
+            });
+        }
+
+        let mut visible_instances = Vec::with_capacity(instances.len());
+        let mut draws: Vec<(usize, Range<u32>)> = Vec::new();
+        for (lod, instance) in instances {
+            let index = visible_instances.len() as u32;
+            visible_instances.push(instance);
+            match draws.last_mut() {
+                Some((last_lod, range)) if *last_lod == lod => range.end = index + 1,
+                _ => draws.push((lod, index..index + 1)),
+            }
+        }
+        Some(Self::Direct {
+            visible_instances_buffer: device.create_buffer_init(&BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&visible_instances),
+                usage: BufferUsage::STORAGE,
+            }),
+            draws,
+        })
+    }
+
+    fn visible_instances_buffer(&self) -> &Buffer {
+        match self {
+            Self::Direct {
+                visible_instances_buffer,
+                ..
+            } => visible_instances_buffer,
+            Self::Indirect(culled_instances) => &culled_instances.visible_instances_buffer,
+        }
+    }
+}
+
+/// Squared distance from the camera to the center of an instance's bounding sphere.
+fn instance_distance(mesh: &Mesh, instance: u32, camera_position: Vec3) -> f32 {
+    let (center, _) = mesh
+        .bounds()
+        .transformed_sphere(&mesh.instances[instance as usize]);
+    (center - camera_position).mag_sq()
+}
@@ -0,0 +1,339 @@
+use crate::objects::Vertex;
+use std::collections::HashMap;
+use std::ops::Range;
+use ultraviolet::Vec3;
+
+pub const MAX_LOD_COUNT: usize = 4;
+/// Fraction of the previous level's triangles kept by each level of detail. Each level is used
+/// once the instance's screen coverage halves, which quarters its area.
+const LOD_TRIANGLE_RATIO: f32 = 0.25;
+
+/// Returns the concatenated index buffer of every level of detail and the range of each level
+/// within it, starting with the original indices.
+pub fn generate_lods(vertices: &[Vertex], indices: &[u16]) -> (Vec<u16>, Vec<Range<u32>>) {
+    let mut lod_indices = indices.to_vec();
+    let mut lods = Vec::with_capacity(MAX_LOD_COUNT);
+    lods.push(0..indices.len() as u32);
+    let mut target_index_count = indices.len() as f32;
+    while lods.len() < MAX_LOD_COUNT {
+        target_index_count *= LOD_TRIANGLE_RATIO;
+        let simplified = simplify(vertices, indices, target_index_count as usize);
+        let previous_lod = lods.last().unwrap();
+        if simplified.is_empty() || simplified.len() >= previous_lod.len() {
+            break;
+        }
+        let start = lod_indices.len() as u32;
+        lod_indices.extend_from_slice(&simplified);
+        lods.push(start..lod_indices.len() as u32);
+    }
+    (lod_indices, lods)
+}
+
+/// Symmetric 4x4 matrix accumulating the squared distances to a set of planes.
+#[derive(Copy, Clone, Default)]
+struct Quadric {
+    xx: f64,
+    xy: f64,
+    xz: f64,
+    xw: f64,
+    yy: f64,
+    yz: f64,
+    yw: f64,
+    zz: f64,
+    zw: f64,
+    ww: f64,
+}
+
+impl Quadric {
+    fn from_plane(normal: Vec3, distance: f32, weight: f32) -> Self {
+        let (a, b, c, d, w) = (
+            normal.x as f64,
+            normal.y as f64,
+            normal.z as f64,
+            distance as f64,
+            weight as f64,
+        );
+        Self {
+            xx: a * a * w,
+            xy: a * b * w,
+            xz: a * c * w,
+            xw: a * d * w,
+            yy: b * b * w,
+            yz: b * c * w,
+            yw: b * d * w,
+            zz: c * c * w,
+            zw: c * d * w,
+            ww: d * d * w,
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.xx += other.xx;
+        self.xy += other.xy;
+        self.xz += other.xz;
+        self.xw += other.xw;
+        self.yy += other.yy;
+        self.yz += other.yz;
+        self.yw += other.yw;
+        self.zz += other.zz;
+        self.zw += other.zw;
+        self.ww += other.ww;
+    }
+
+    fn error(&self, point: Vec3) -> f64 {
+        let (x, y, z) = (point.x as f64, point.y as f64, point.z as f64);
+        x * x * self.xx
+            + y * y * self.yy
+            + z * z * self.zz
+            + 2.0 * (x * y * self.xy + x * z * self.xz + y * z * self.yz)
+            + 2.0 * (x * self.xw + y * self.yw + z * self.zw)
+            + self.ww
+    }
+}
+
+/// Reduces `indices` to at most `target_index_count` indices (if possible) by collapsing edges
+/// with the lowest quadric error. Vertices are only ever collapsed onto other existing vertices,
+/// so the result indexes into the same vertex buffer. Vertices on open borders are never moved.
+pub fn simplify(vertices: &[Vertex], indices: &[u16], target_index_count: usize) -> Vec<u16> {
+    let positions = vertices
+        .iter()
+        .map(|vertex| Vec3::from(vertex.position))
+        .collect::<Vec<Vec3>>();
+
+    // OBJ vertices are split along UV and normal seams, so collapse decisions are made on the
+    // first vertex sharing each position.
+    let mut first_vertex_at = HashMap::new();
+    let canonical = vertices
+        .iter()
+        .enumerate()
+        .map(|(i, vertex)| {
+            let key = [
+                vertex.position[0].to_bits(),
+                vertex.position[1].to_bits(),
+                vertex.position[2].to_bits(),
+            ];
+            *first_vertex_at.entry(key).or_insert(i as u32)
+        })
+        .collect::<Vec<u32>>();
+
+    let mut triangles = indices
+        .chunks_exact(3)
+        .map(|triangle| {
+            [
+                canonical[triangle[0] as usize],
+                canonical[triangle[1] as usize],
+                canonical[triangle[2] as usize],
+            ]
+        })
+        .filter(|triangle| !is_degenerate(triangle))
+        .collect::<Vec<[u32; 3]>>();
+    // Keep the original corners so untouched vertices retain their own normals and UVs.
+    let mut corners = indices
+        .chunks_exact(3)
+        .filter(|triangle| {
+            !is_degenerate(&[
+                canonical[triangle[0] as usize],
+                canonical[triangle[1] as usize],
+                canonical[triangle[2] as usize],
+            ])
+        })
+        .map(|triangle| [triangle[0] as u32, triangle[1] as u32, triangle[2] as u32])
+        .collect::<Vec<[u32; 3]>>();
+
+    let mut quadrics = vec![Quadric::default(); vertices.len()];
+    for triangle in &triangles {
+        let [p0, p1, p2] = triangle_positions(&positions, triangle);
+        let normal = (p1 - p0).cross(p2 - p0);
+        let double_area = normal.mag();
+        if double_area == 0.0 {
+            continue;
+        }
+        let normal = normal / double_area;
+        let quadric = Quadric::from_plane(normal, -normal.dot(p0), double_area * 0.5);
+        for &vertex in triangle {
+            quadrics[vertex as usize].add(&quadric);
+        }
+    }
+
+    let mut edge_use_counts = HashMap::new();
+    for triangle in &triangles {
+        for edge in triangle_edges(triangle) {
+            *edge_use_counts.entry(edge).or_insert(0u32) += 1;
+        }
+    }
+    let mut locked = vec![false; vertices.len()];
+    for (&(a, b), &count) in &edge_use_counts {
+        if count == 1 {
+            locked[a as usize] = true;
+            locked[b as usize] = true;
+        }
+    }
+
+    let target_triangle_count = target_index_count / 3;
+    while triangles.len() > target_triangle_count {
+        let mut adjacency = vec![Vec::new(); vertices.len()];
+        for (i, triangle) in triangles.iter().enumerate() {
+            for &vertex in triangle {
+                adjacency[vertex as usize].push(i);
+            }
+        }
+
+        let mut edges = triangles
+            .iter()
+            .flat_map(triangle_edges)
+            .collect::<Vec<(u32, u32)>>();
+        edges.sort_unstable();
+        edges.dedup();
+
+        let mut collapses = edges
+            .into_iter()
+            .filter_map(|(a, b)| {
+                let mut quadric = quadrics[a as usize];
+                quadric.add(&quadrics[b as usize]);
+                let a_to_b = (quadric.error(positions[b as usize]), a, b);
+                let b_to_a = (quadric.error(positions[a as usize]), b, a);
+                match (locked[a as usize], locked[b as usize]) {
+                    (false, false) if a_to_b.0 <= b_to_a.0 => Some(a_to_b),
+                    (false, false) => Some(b_to_a),
+                    (false, true) => Some(a_to_b),
+                    (true, false) => Some(b_to_a),
+                    (true, true) => None,
+                }
+            })
+            .collect::<Vec<(f64, u32, u32)>>();
+        collapses.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut collapse_target = vec![None; vertices.len()];
+        let mut touched = vec![false; vertices.len()];
+        let mut remaining_triangles = triangles.len();
+        for (_, from, to) in collapses {
+            if remaining_triangles <= target_triangle_count {
+                break;
+            }
+            if touched[from as usize] || touched[to as usize] {
+                continue;
+            }
+            if collapse_flips_triangle(&positions, &triangles, &adjacency[from as usize], from, to)
+            {
+                continue;
+            }
+
+            collapse_target[from as usize] = Some(to);
+            for &triangle in &adjacency[from as usize] {
+                if triangles[triangle].contains(&to) {
+                    remaining_triangles -= 1;
+                }
+                for &vertex in &triangles[triangle] {
+                    touched[vertex as usize] = true;
+                }
+            }
+            let from_quadric = quadrics[from as usize];
+            quadrics[to as usize].add(&from_quadric);
+        }
+        if remaining_triangles == triangles.len() {
+            break;
+        }
+
+        let mut kept_triangles = Vec::with_capacity(remaining_triangles);
+        let mut kept_corners = Vec::with_capacity(remaining_triangles);
+        for (triangle, corner) in triangles.iter().zip(&corners) {
+            let mut triangle = *triangle;
+            let mut corner = *corner;
+            for i in 0..3 {
+                if let Some(to) = collapse_target[triangle[i] as usize] {
+                    triangle[i] = to;
+                    corner[i] = to;
+                }
+            }
+            if !is_degenerate(&triangle) {
+                kept_triangles.push(triangle);
+                kept_corners.push(corner);
+            }
+        }
+        triangles = kept_triangles;
+        corners = kept_corners;
+    }
+
+    corners.iter().flatten().map(|&i| i as u16).collect()
+}
+
+fn is_degenerate(triangle: &[u32; 3]) -> bool {
+    triangle[0] == triangle[1] || triangle[1] == triangle[2] || triangle[2] == triangle[0]
+}
+
+fn triangle_edges(triangle: &[u32; 3]) -> [(u32, u32); 3] {
+    let edge = |a: u32, b: u32| (a.min(b), a.max(b));
+    [
+        edge(triangle[0], triangle[1]),
+        edge(triangle[1], triangle[2]),
+        edge(triangle[2], triangle[0]),
+    ]
+}
+
+fn triangle_positions(positions: &[Vec3], triangle: &[u32; 3]) -> [Vec3; 3] {
+    [
+        positions[triangle[0] as usize],
+        positions[triangle[1] as usize],
+        positions[triangle[2] as usize],
+    ]
+}
+
+/// Checks whether moving `from` onto `to` would turn any of the remaining triangles around `from`
+/// upside down.
+fn collapse_flips_triangle(
+    positions: &[Vec3],
+    triangles: &[[u32; 3]],
+    adjacent_triangles: &[usize],
+    from: u32,
+    to: u32,
+) -> bool {
+    adjacent_triangles.iter().any(|&triangle| {
+        let triangle = triangles[triangle];
+        if triangle.contains(&to) {
+            return false;
+        }
+        let [p0, p1, p2] = triangle_positions(positions, &triangle);
+        let before = (p1 - p0).cross(p2 - p0);
+        let collapsed = triangle.map(|vertex| if vertex == from { to } else { vertex });
+        let [p0, p1, p2] = triangle_positions(positions, &collapsed);
+        let after = (p1 - p0).cross(p2 - p0);
+        before.dot(after) <= 0.0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects;
+
+    #[test]
+    fn simplify_reaches_target_index_count() {
+        let (vertices, indices) = objects::test_sphere(32, 16);
+        for &fraction in &[0.5, 0.25, 0.1] {
+            let target_index_count = (indices.len() as f32 * fraction) as usize;
+            let simplified = simplify(&vertices, &indices, target_index_count);
+            assert!(
+                !simplified.is_empty() && simplified.len() <= target_index_count,
+                "{} indices for a target of {}",
+                simplified.len(),
+                target_index_count
+            );
+            assert_eq!(simplified.len() % 3, 0);
+            assert!(simplified
+                .iter()
+                .all(|&index| (index as usize) < vertices.len()));
+        }
+    }
+
+    #[test]
+    fn generate_lods_shrinks_each_level() {
+        let (vertices, original) = objects::test_sphere(32, 16);
+        let (indices, lods) = generate_lods(&vertices, &original);
+        assert!(lods.len() > 1 && lods.len() <= MAX_LOD_COUNT);
+        assert_eq!(&indices[..lods[0].end as usize], &original[..]);
+        for pair in lods.windows(2) {
+            assert!(pair[1].end - pair[1].start < pair[0].end - pair[0].start);
+        }
+        assert_eq!(lods.last().unwrap().end as usize, indices.len());
+    }
+}
@@ -0,0 +1,347 @@
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use rayon::slice::ParallelSliceMut;
+use ultraviolet::{Lerp, Mat4, Rotor3, Slerp, Vec3};
+
+/// Translation, rotation and scale of a joint relative to its parent.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct JointPose {
+    pub translation: Vec3,
+    pub rotation: Rotor3,
+    pub scale: Vec3,
+}
+
+impl JointPose {
+    fn matrix(&self) -> Mat4 {
+        Mat4::from_translation(self.translation)
+            * self.rotation.into_matrix().into_homogeneous()
+            * Mat4::from_nonuniform_scale(self.scale)
+    }
+}
+
+pub struct Joint {
+    /// `None` for the roots of the skeleton.
+    pub parent: Option<usize>,
+    /// Transform of the nodes between the parent joint, or the scene's root, and this joint.
+    pub parent_offset: Mat4,
+    pub rest_pose: JointPose,
+    /// From the mesh's space into the joint's space in the bind pose.
+    pub inverse_bind_matrix: Mat4,
+}
+
+/// The joints skinned vertices refer to, in the order of their indices.
+pub struct Skeleton {
+    joints: Vec<Joint>,
+    /// Indices of the joints with every parent before its children.
+    order: Vec<usize>,
+}
+
+impl Skeleton {
+    /// Joints can be in any order, but their parents can't form a cycle.
+    pub fn new(joints: Vec<Joint>) -> Self {
+        fn visit(joint: usize, joints: &[Joint], visited: &mut [bool], order: &mut Vec<usize>) {
+            if visited[joint] {
+                return;
+            }
+            visited[joint] = true;
+            if let Some(parent) = joints[joint].parent {
+                visit(parent, joints, visited, order);
+            }
+            order.push(joint);
+        }
+
+        let mut visited = vec![false; joints.len()];
+        let mut order = Vec::with_capacity(joints.len());
+        for joint in 0..joints.len() {
+            visit(joint, &joints, &mut visited, &mut order);
+        }
+        Self { joints, order }
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        &self.joints
+    }
+
+    pub fn rest_pose(&self) -> Vec<JointPose> {
+        self.joints.iter().map(|joint| joint.rest_pose).collect()
+    }
+
+    /// Writes the matrices moving each joint's vertices from the bind pose into `pose`, in the
+    /// mesh's space.
+    pub fn joint_matrices(&self, pose: &[JointPose], matrices: &mut [Mat4]) {
+        let mut global_transforms = vec![Mat4::identity(); self.joints.len()];
+        for &i in &self.order {
+            let joint = &self.joints[i];
+            let parent_transform = joint
+                .parent
+                .map_or_else(Mat4::identity, |parent| global_transforms[parent]);
+            global_transforms[i] = parent_transform * joint.parent_offset * pose[i].matrix();
+            matrices[i] = global_transforms[i] * joint.inverse_bind_matrix;
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Interpolation {
+    /// Holds each keyframe until the next one.
+    Step,
+    /// Linear for translations and scales, spherical linear for rotations. glTF's cubic splines
+    /// are loaded as linear between their keyframes.
+    Linear,
+}
+
+pub enum Keyframes {
+    Translations(Vec<Vec3>),
+    Rotations(Vec<Rotor3>),
+    Scales(Vec<Vec3>),
+}
+
+/// Keyframes animating one property of a joint.
+pub struct Channel {
+    pub joint: usize,
+    pub interpolation: Interpolation,
+    /// Ascending keyframe times in seconds.
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+}
+
+pub struct AnimationClip {
+    pub channels: Vec<Channel>,
+    /// Time of the last keyframe of any channel.
+    pub duration: f32,
+}
+
+impl AnimationClip {
+    pub fn new(channels: Vec<Channel>) -> Self {
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max);
+        Self { channels, duration }
+    }
+
+    /// Overwrites the properties the clip animates with their values at `time`. Before the first
+    /// and after the last keyframe of a channel, its first or last keyframe is held.
+    pub fn sample(&self, time: f32, pose: &mut [JointPose]) {
+        for channel in &self.channels {
+            if channel.times.is_empty() {
+                continue;
+            }
+            let next = channel.times.partition_point(|&t| t <= time);
+            let (a, b, t) = if next == 0 {
+                (0, 0, 0.0)
+            } else if next == channel.times.len() {
+                (next - 1, next - 1, 0.0)
+            } else {
+                let (start, end) = (channel.times[next - 1], channel.times[next]);
+                (next - 1, next, (time - start) / (end - start))
+            };
+            let t = match channel.interpolation {
+                Interpolation::Step => 0.0,
+                Interpolation::Linear => t,
+            };
+
+            let joint = &mut pose[channel.joint];
+            match &channel.keyframes {
+                Keyframes::Translations(values) => {
+                    joint.translation = values[a].lerp(values[b], t);
+                }
+                Keyframes::Rotations(values) => joint.rotation = slerp(values[a], values[b], t),
+                Keyframes::Scales(values) => joint.scale = values[a].lerp(values[b], t),
+            }
+        }
+    }
+}
+
+/// Slerp along the shorter arc. Nearly equal rotations are interpolated linearly instead, as slerp
+/// divides by the sine of the angle between them.
+fn slerp(a: Rotor3, b: Rotor3, t: f32) -> Rotor3 {
+    let b = if a.dot(b) < 0.0 { b * -1.0 } else { b };
+    if a.dot(b) > 0.9995 {
+        a.lerp(b, t).normalized()
+    } else {
+        a.slerp(b, t).normalized()
+    }
+}
+
+/// Skeleton and animation clips of a skinned mesh, and the pose each of its instances is in. The
+/// mesh's bounds and BVH stay in the bind pose.
+pub struct Skin {
+    pub skeleton: Skeleton,
+    pub clips: Vec<AnimationClip>,
+    /// Clip every instance plays on a loop, `None` holds the rest pose.
+    pub clip: Option<usize>,
+    /// Multiplies the playback speed.
+    pub speed: f32,
+    /// Playback time of each instance.
+    times: Vec<f32>,
+    /// Joint matrices of each instance, one after another.
+    joint_matrices: Vec<Mat4>,
+    rest_matrices: Vec<Mat4>,
+}
+
+impl Skin {
+    pub fn new(skeleton: Skeleton, clips: Vec<AnimationClip>) -> Self {
+        let mut rest_matrices = vec![Mat4::identity(); skeleton.joints().len()];
+        skeleton.joint_matrices(&skeleton.rest_pose(), &mut rest_matrices);
+        Self {
+            clip: if clips.is_empty() { None } else { Some(0) },
+            skeleton,
+            clips,
+            speed: 1.0,
+            times: Vec::new(),
+            joint_matrices: Vec::new(),
+            rest_matrices,
+        }
+    }
+
+    pub fn joint_count(&self) -> usize {
+        self.skeleton.joints().len()
+    }
+
+    /// Advances every instance's clip by `delta_time` seconds and poses its joints. Instances
+    /// added since the last update start from the beginning of the clip.
+    pub fn update(&mut self, delta_time: f32, instance_count: usize) {
+        self.times.resize(instance_count, 0.0);
+        self.joint_matrices
+            .resize(instance_count * self.joint_count(), Mat4::identity());
+        let clips = &self.clips;
+        let clip = match self.clip.and_then(|clip| clips.get(clip)) {
+            Some(clip) => clip,
+            None => {
+                for matrices in self.joint_matrices.chunks_mut(self.rest_matrices.len()) {
+                    matrices.copy_from_slice(&self.rest_matrices);
+                }
+                return;
+            }
+        };
+
+        for time in &mut self.times {
+            *time += delta_time * self.speed;
+            if clip.duration > 0.0 {
+                *time = time.rem_euclid(clip.duration);
+            }
+        }
+        let skeleton = &self.skeleton;
+        let rest_pose = skeleton.rest_pose();
+        self.joint_matrices
+            .par_chunks_mut(skeleton.joints().len())
+            .zip(self.times.par_iter())
+            .for_each(|(matrices, &time)| {
+                let mut pose = rest_pose.clone();
+                clip.sample(time, &mut pose);
+                skeleton.joint_matrices(&pose, matrices);
+            });
+    }
+
+    /// Joint matrices of an instance, in the rest pose until the instance was updated.
+    pub fn instance_joint_matrices(&self, instance: usize) -> &[Mat4] {
+        let joint_count = self.joint_count();
+        self.joint_matrices
+            .get(instance * joint_count..(instance + 1) * joint_count)
+            .unwrap_or(&self.rest_matrices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+    use ultraviolet::Vec4;
+
+    fn rest_pose() -> Vec<JointPose> {
+        vec![
+            JointPose {
+                translation: Vec3::zero(),
+                rotation: Rotor3::identity(),
+                scale: Vec3::one(),
+            };
+            2
+        ]
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).mag() < 1e-4, "{:?} instead of {:?}", a, b);
+    }
+
+    #[test]
+    fn sample_interpolates_and_holds_the_ends() {
+        let clip = AnimationClip::new(vec![
+            Channel {
+                joint: 0,
+                interpolation: Interpolation::Linear,
+                times: vec![1.0, 3.0],
+                keyframes: Keyframes::Translations(vec![Vec3::zero(), Vec3::new(2.0, 0.0, 0.0)]),
+            },
+            Channel {
+                joint: 1,
+                interpolation: Interpolation::Step,
+                times: vec![0.0, 2.0],
+                keyframes: Keyframes::Scales(vec![Vec3::one(), Vec3::broadcast(2.0)]),
+            },
+        ]);
+        assert_eq!(clip.duration, 3.0);
+
+        let mut pose = rest_pose();
+        clip.sample(2.5, &mut pose);
+        assert_close(pose[0].translation, Vec3::new(1.5, 0.0, 0.0));
+        assert_close(pose[1].scale, Vec3::broadcast(2.0));
+
+        clip.sample(1.5, &mut pose);
+        assert_close(pose[1].scale, Vec3::one());
+
+        clip.sample(0.0, &mut pose);
+        assert_close(pose[0].translation, Vec3::zero());
+        clip.sample(10.0, &mut pose);
+        assert_close(pose[0].translation, Vec3::new(2.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn sample_slerps_along_the_shorter_arc() {
+        let quarter_turn = Rotor3::from_rotation_xz(FRAC_PI_2);
+        for &end in &[quarter_turn, quarter_turn * -1.0] {
+            let clip = AnimationClip::new(vec![Channel {
+                joint: 0,
+                interpolation: Interpolation::Linear,
+                times: vec![0.0, 1.0],
+                keyframes: Keyframes::Rotations(vec![Rotor3::identity(), end]),
+            }]);
+            let mut pose = rest_pose();
+            clip.sample(0.5, &mut pose);
+            let halfway = Rotor3::from_rotation_xz(FRAC_PI_2 / 2.0);
+            assert_close(pose[0].rotation * Vec3::unit_x(), halfway * Vec3::unit_x());
+        }
+    }
+
+    #[test]
+    fn rest_pose_leaves_bound_vertices_in_place() {
+        let offset = Mat4::from_translation(Vec3::new(0.0, 1.0, 0.0));
+        let pose = JointPose {
+            translation: Vec3::new(0.0, 1.0, 0.0),
+            rotation: Rotor3::from_rotation_xy(0.5),
+            scale: Vec3::one(),
+        };
+        // The child comes first, joints can be in any order
+        let child_bind = offset * pose.matrix() * offset * pose.matrix();
+        let skeleton = Skeleton::new(vec![
+            Joint {
+                parent: Some(1),
+                parent_offset: offset,
+                rest_pose: pose,
+                inverse_bind_matrix: child_bind.inversed(),
+            },
+            Joint {
+                parent: None,
+                parent_offset: offset,
+                rest_pose: pose,
+                inverse_bind_matrix: (offset * pose.matrix()).inversed(),
+            },
+        ]);
+        let mut matrices = [Mat4::identity(); 2];
+        skeleton.joint_matrices(&skeleton.rest_pose(), &mut matrices);
+        let point = Vec3::new(0.25, 0.5, -1.0);
+        for matrix in &matrices {
+            let moved = *matrix * Vec4::new(point.x, point.y, point.z, 1.0);
+            assert_close(Vec3::new(moved.x, moved.y, moved.z), point);
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use crate::objects::{SkinVertex, Vertex};
+use crate::postprocess::create_fullscreen_pipeline;
+use bytemuck::{Pod, Zeroable};
+use std::mem;
+use ultraviolet::Vec3;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SsaoSettings {
+    /// World space radius of the sampled hemisphere.
+    pub radius: f32,
+    /// Depth difference below which a sample doesn't count as occluded, avoids self-occlusion.
+    pub bias: f32,
+    /// Exponent applied to the result, higher values darken occluded areas further.
+    pub intensity: f32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            radius: 0.25,
+            bias: 0.01,
+            intensity: 1.5,
+        }
+    }
+}
+
+const KERNEL_SIZE: usize = 16;
+const NORMAL_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+const AMBIENT_OCCLUSION_FORMAT: TextureFormat = TextureFormat::R8Unorm;
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct SsaoUniforms {
+    kernel: [[f32; 4]; KERNEL_SIZE],
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+    _padding: u32,
+}
+
+/// Screen-space ambient occlusion. The meshes are drawn into a single sampled depth and normal
+/// prepass, which the occlusion is computed from and then blurred.
+pub struct Ssao {
+    kernel: [[f32; 4]; KERNEL_SIZE],
+
+    depth_texture: TextureView,
+    normal_texture: TextureView,
+    raw_texture: TextureView,
+    output_texture: TextureView,
+    sampler: Sampler,
+
+    prepass_pipeline: RenderPipeline,
+    ssao_bind_group_layout: BindGroupLayout,
+    ssao_pipeline: RenderPipeline,
+    blur_bind_group_layout: BindGroupLayout,
+    blur_pipeline: RenderPipeline,
+}
+
+impl Ssao {
+    pub fn new(
+        device: &Device,
+        camera_bind_group_layout: &BindGroupLayout,
+        instances_bind_group_layout: &BindGroupLayout,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let prepass_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[camera_bind_group_layout, instances_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let prepass_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&prepass_pipeline_layout),
+            vertex_stage: ProgrammableStageDescriptor {
+                module: &device.create_shader_module(include_spirv!("../shaders/vert.spv")),
+                entry_point: "main",
+            },
+            fragment_stage: Some(ProgrammableStageDescriptor {
+                module: &device
+                    .create_shader_module(include_spirv!("../shaders/normal_prepass.spv")),
+                entry_point: "main",
+            }),
+            rasterization_state: Some(RasterizationStateDescriptor {
+                front_face: FrontFace::Ccw,
+                cull_mode: CullMode::Back,
+                clamp_depth: false,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: PrimitiveTopology::TriangleList,
+            color_states: &[ColorStateDescriptor {
+                format: NORMAL_FORMAT,
+                alpha_blend: BlendDescriptor::REPLACE,
+                color_blend: BlendDescriptor::REPLACE,
+                write_mask: ColorWrite::ALL,
+            }],
+            depth_stencil_state: Some(DepthStencilStateDescriptor {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Less,
+                stencil: StencilStateDescriptor::default(),
+            }),
+            vertex_state: VertexStateDescriptor {
+                index_format: IndexFormat::Uint16,
+                vertex_buffers: &[
+                    VertexBufferDescriptor {
+                        stride: mem::size_of::<Vertex>() as BufferAddress,
+                        step_mode: InputStepMode::Vertex,
+                        attributes: &vertex_attr_array![0 => Float3, 1 => Float3, 2 => Float3],
+                    },
+                    VertexBufferDescriptor {
+                        stride: mem::size_of::<SkinVertex>() as BufferAddress,
+                        step_mode: InputStepMode::Vertex,
+                        attributes: &vertex_attr_array![3 => Uint4, 4 => Float4],
+                    },
+                ],
+            },
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+
+        let texture_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStage::FRAGMENT,
+            ty: BindingType::SampledTexture {
+                dimension: TextureViewDimension::D2,
+                component_type: TextureComponentType::Float,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStage::FRAGMENT,
+            ty: BindingType::Sampler { comparison: false },
+            count: None,
+        };
+        let ssao_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                texture_entry(0),
+                texture_entry(1),
+                sampler_entry(2),
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let ssao_pipeline = create_fullscreen_pipeline(
+            device,
+            &device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[camera_bind_group_layout, &ssao_bind_group_layout],
+                push_constant_ranges: &[],
+            }),
+            &device.create_shader_module(include_spirv!("../shaders/ssao.spv")),
+            AMBIENT_OCCLUSION_FORMAT,
+            BlendDescriptor::REPLACE,
+            1,
+        );
+        let blur_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[texture_entry(0), sampler_entry(1)],
+        });
+        let blur_pipeline = create_fullscreen_pipeline(
+            device,
+            &device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&blur_bind_group_layout],
+                push_constant_ranges: &[],
+            }),
+            &device.create_shader_module(include_spirv!("../shaders/ssao_blur.spv")),
+            AMBIENT_OCCLUSION_FORMAT,
+            BlendDescriptor::REPLACE,
+            1,
+        );
+
+        Self {
+            kernel: create_kernel(),
+
+            depth_texture: create_texture(device, TextureFormat::Depth32Float, width, height),
+            normal_texture: create_texture(device, NORMAL_FORMAT, width, height),
+            raw_texture: create_texture(device, AMBIENT_OCCLUSION_FORMAT, width, height),
+            output_texture: create_texture(device, AMBIENT_OCCLUSION_FORMAT, width, height),
+            sampler: device.create_sampler(&SamplerDescriptor {
+                label: None,
+                address_mode_u: AddressMode::ClampToEdge,
+                address_mode_v: AddressMode::ClampToEdge,
+                address_mode_w: AddressMode::ClampToEdge,
+                mag_filter: FilterMode::Nearest,
+                min_filter: FilterMode::Nearest,
+                mipmap_filter: FilterMode::Nearest,
+                lod_min_clamp: 0.0,
+                lod_max_clamp: f32::MAX,
+                compare: None,
+                anisotropy_clamp: None,
+            }),
+
+            prepass_pipeline,
+            ssao_bind_group_layout,
+            ssao_pipeline,
+            blur_bind_group_layout,
+            blur_pipeline,
+        }
+    }
+
+    /// Blurred ambient occlusion, 1 where unoccluded.
+    pub fn output(&self) -> &TextureView {
+        &self.output_texture
+    }
+
+    pub fn set_screen_size(&mut self, device: &Device, width: u32, height: u32) {
+        self.depth_texture = create_texture(device, TextureFormat::Depth32Float, width, height);
+        self.normal_texture = create_texture(device, NORMAL_FORMAT, width, height);
+        self.raw_texture = create_texture(device, AMBIENT_OCCLUSION_FORMAT, width, height);
+        self.output_texture = create_texture(device, AMBIENT_OCCLUSION_FORMAT, width, height);
+    }
+
+    /// Starts the depth and normal prepass, the caller binds the camera at group 0 and each mesh's
+    /// instances at group 1 before drawing it.
+    pub fn begin_prepass<'a>(&'a self, encoder: &'a mut CommandEncoder) -> RenderPass<'a> {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[RenderPassColorAttachmentDescriptor {
+                attachment: &self.normal_texture,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachmentDescriptor {
+                attachment: &self.depth_texture,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.prepass_pipeline);
+        render_pass
+    }
+
+    /// Computes and blurs the occlusion from the prepass.
+    pub fn render(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        settings: &SsaoSettings,
+        camera_bind_group: &BindGroup,
+    ) {
+        let uniforms = SsaoUniforms {
+            kernel: self.kernel,
+            radius: settings.radius,
+            bias: settings.bias,
+            intensity: settings.intensity,
+            _padding: 0,
+        };
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::bytes_of(&uniforms),
+            usage: BufferUsage::UNIFORM,
+        });
+        let ssao_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.ssao_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&self.depth_texture),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&self.normal_texture),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Sampler(&self.sampler),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Buffer(uniform_buffer.slice(..)),
+                },
+            ],
+        });
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[RenderPassColorAttachmentDescriptor {
+                attachment: &self.raw_texture,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::WHITE),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.ssao_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &ssao_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+        drop(render_pass);
+
+        let blur_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.blur_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&self.raw_texture),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[RenderPassColorAttachmentDescriptor {
+                attachment: &self.output_texture,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::WHITE),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.blur_pipeline);
+        render_pass.set_bind_group(0, &blur_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    /// Leaves the output unoccluded, for when SSAO is disabled.
+    pub fn clear(&self, encoder: &mut CommandEncoder) {
+        encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[RenderPassColorAttachmentDescriptor {
+                attachment: &self.output_texture,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::WHITE),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+    }
+}
+
+/// Points in the unit hemisphere around +Z, spread with a Halton sequence and concentrated towards
+/// the center where occluders matter most.
+fn create_kernel() -> [[f32; 4]; KERNEL_SIZE] {
+    let mut kernel = [[0.0; 4]; KERNEL_SIZE];
+    for (i, point) in kernel.iter_mut().enumerate() {
+        let direction = Vec3::new(
+            halton(i as u32 + 1, 2) * 2.0 - 1.0,
+            halton(i as u32 + 1, 3) * 2.0 - 1.0,
+            halton(i as u32 + 1, 5),
+        )
+        .normalized();
+        let scale = (i + 1) as f32 / KERNEL_SIZE as f32;
+        let scale = 0.1 + 0.9 * scale * scale;
+        let point_position = direction * scale;
+        *point = [point_position.x, point_position.y, point_position.z, 0.0];
+    }
+    kernel
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+fn create_texture(device: &Device, format: TextureFormat, width: u32, height: u32) -> TextureView {
+    device
+        .create_texture(&TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsage::OUTPUT_ATTACHMENT | TextureUsage::SAMPLED,
+        })
+        .create_view(&TextureViewDescriptor::default())
+}
@@ -0,0 +1,834 @@
+use crate::antialiasing::Antialiasing;
+use crate::culling::CullingMode;
+use crate::lights::PointLight;
+use crate::objects::{AlphaMode, Mesh};
+use crate::postprocess::{Exposure, PostProcessSettings, Tonemapper};
+use crate::primitives::Primitive;
+use crate::renderer::{DebugView, RenderPath, RenderSettings, Renderer};
+use bytemuck::{Pod, Zeroable};
+use egui::{ClippedMesh, CollapsingHeader, ComboBox, CtxRef, DragValue, RawInput, ScrollArea, Ui};
+use std::iter;
+use std::mem;
+use std::time::Instant;
+use ultraviolet::{Similarity3, Vec3};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+use winit::dpi::PhysicalPosition;
+use winit::event::{
+    ElementState, ModifiersState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct UiVertex {
+    position: [f32; 2],
+    uv: [f32; 2],
+    color: [u8; 4],
+}
+
+/// An egui context fed with winit events and drawn with wgpu, on top of the finished frame.
+pub struct DebugUi {
+    pub visible: bool,
+    context: CtxRef,
+    raw_input: RawInput,
+    start_time: Instant,
+    pointer_position: egui::Pos2,
+    screen_width: u32,
+    screen_height: u32,
+    scale_factor: f32,
+
+    bind_group_layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+    sampler: Sampler,
+    /// The font atlas and the egui texture version it was created from.
+    font_texture: Option<(u64, TextureView)>,
+}
+
+impl DebugUi {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        screen_width: u32,
+        screen_height: u32,
+        scale_factor: f32,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStage::VERTEX,
+                    ty: BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::SampledTexture {
+                        dimension: TextureViewDimension::D2,
+                        component_type: TextureComponentType::Float,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Sampler { comparison: false },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            vertex_stage: ProgrammableStageDescriptor {
+                module: &device.create_shader_module(include_spirv!("../shaders/ui_vert.spv")),
+                entry_point: "main",
+            },
+            fragment_stage: Some(ProgrammableStageDescriptor {
+                module: &device.create_shader_module(include_spirv!("../shaders/ui_frag.spv")),
+                entry_point: "main",
+            }),
+            // egui doesn't use a consistent winding order
+            rasterization_state: Some(RasterizationStateDescriptor {
+                front_face: FrontFace::Ccw,
+                cull_mode: CullMode::None,
+                clamp_depth: false,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: PrimitiveTopology::TriangleList,
+            // egui's colors are premultiplied
+            color_states: &[ColorStateDescriptor {
+                format,
+                alpha_blend: BlendDescriptor {
+                    src_factor: BlendFactor::OneMinusDstAlpha,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+                color_blend: BlendDescriptor {
+                    src_factor: BlendFactor::One,
+                    dst_factor: BlendFactor::OneMinusSrcAlpha,
+                    operation: BlendOperation::Add,
+                },
+                write_mask: ColorWrite::ALL,
+            }],
+            depth_stencil_state: None,
+            vertex_state: VertexStateDescriptor {
+                index_format: IndexFormat::Uint32,
+                vertex_buffers: &[VertexBufferDescriptor {
+                    stride: mem::size_of::<UiVertex>() as BufferAddress,
+                    step_mode: InputStepMode::Vertex,
+                    attributes: &vertex_attr_array![0 => Float2, 1 => Float2, 2 => Uchar4Norm],
+                }],
+            },
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+
+        Self {
+            visible: false,
+            context: CtxRef::default(),
+            raw_input: RawInput::default(),
+            start_time: Instant::now(),
+            pointer_position: egui::Pos2::ZERO,
+            screen_width,
+            screen_height,
+            scale_factor,
+
+            bind_group_layout,
+            pipeline,
+            sampler: device.create_sampler(&SamplerDescriptor {
+                label: None,
+                address_mode_u: AddressMode::ClampToEdge,
+                address_mode_v: AddressMode::ClampToEdge,
+                address_mode_w: AddressMode::ClampToEdge,
+                mag_filter: FilterMode::Linear,
+                min_filter: FilterMode::Linear,
+                mipmap_filter: FilterMode::Nearest,
+                lod_min_clamp: 0.0,
+                lod_max_clamp: f32::MAX,
+                compare: None,
+                anisotropy_clamp: None,
+            }),
+            font_texture: None,
+        }
+    }
+
+    pub fn set_screen_size(&mut self, width: u32, height: u32, scale_factor: f32) {
+        self.screen_width = width;
+        self.screen_height = height;
+        self.scale_factor = scale_factor;
+    }
+
+    /// Queues the event for the next frame. Returns whether the UI uses it, in which case the
+    /// application should ignore it.
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        let scale_factor = self.scale_factor;
+        let to_points = |position: PhysicalPosition<f64>| {
+            egui::pos2(
+                position.x as f32 / scale_factor,
+                position.y as f32 / scale_factor,
+            )
+        };
+        let used = match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.pointer_position = to_points(*position);
+                self.raw_input
+                    .events
+                    .push(egui::Event::PointerMoved(self.pointer_position));
+                false
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.raw_input.events.push(egui::Event::PointerGone);
+                false
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let button = match button {
+                    MouseButton::Left => egui::PointerButton::Primary,
+                    MouseButton::Right => egui::PointerButton::Secondary,
+                    MouseButton::Middle => egui::PointerButton::Middle,
+                    MouseButton::Other(_) => return false,
+                };
+                self.raw_input.events.push(egui::Event::PointerButton {
+                    pos: self.pointer_position,
+                    button,
+                    pressed: *state == ElementState::Pressed,
+                    modifiers: self.raw_input.modifiers,
+                });
+                self.context.wants_pointer_input()
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.raw_input.scroll_delta += match delta {
+                    MouseScrollDelta::LineDelta(x, y) => egui::vec2(*x, *y) * 50.0,
+                    MouseScrollDelta::PixelDelta(position) => to_points(*position).to_vec2(),
+                };
+                self.context.wants_pointer_input()
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.raw_input.modifiers = convert_modifiers(*modifiers);
+                false
+            }
+            WindowEvent::ReceivedCharacter(character) => {
+                if !character.is_control() {
+                    self.raw_input
+                        .events
+                        .push(egui::Event::Text(character.to_string()));
+                }
+                self.context.wants_keyboard_input()
+            }
+            WindowEvent::KeyboardInput { input, .. } => {
+                if let Some(key) = input.virtual_keycode.and_then(convert_key) {
+                    self.raw_input.events.push(egui::Event::Key {
+                        key,
+                        pressed: input.state == ElementState::Pressed,
+                        modifiers: self.raw_input.modifiers,
+                    });
+                }
+                self.context.wants_keyboard_input()
+            }
+            _ => false,
+        };
+        self.visible && used
+    }
+
+    /// Runs a frame of the UI, building it with `build`, and returns what to draw.
+    pub fn run(&mut self, build: impl FnOnce(&CtxRef)) -> Vec<ClippedMesh> {
+        let mut raw_input = mem::take(&mut self.raw_input);
+        raw_input.screen_rect = Some(egui::Rect::from_min_size(
+            egui::Pos2::ZERO,
+            egui::vec2(
+                self.screen_width as f32 / self.scale_factor,
+                self.screen_height as f32 / self.scale_factor,
+            ),
+        ));
+        raw_input.pixels_per_point = Some(self.scale_factor);
+        raw_input.time = Some(self.start_time.elapsed().as_secs_f64());
+        self.raw_input.modifiers = raw_input.modifiers;
+
+        self.context.begin_frame(raw_input);
+        if self.visible {
+            build(&self.context);
+        }
+        let (_, shapes) = self.context.end_frame();
+        self.context.tessellate(shapes)
+    }
+
+    pub fn render(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        meshes: Vec<ClippedMesh>,
+        target: &TextureView,
+    ) {
+        if meshes.is_empty() {
+            return;
+        }
+        let texture = self.context.texture();
+        if self.font_texture.as_ref().map(|(version, _)| *version) != Some(texture.version) {
+            let pixels = texture
+                .srgba_pixels(1.0)
+                .flat_map(|color| color.to_array())
+                .collect::<Vec<u8>>();
+            self.font_texture = Some((
+                texture.version,
+                create_font_texture(
+                    queue,
+                    device,
+                    &pixels,
+                    texture.width as u32,
+                    texture.height as u32,
+                ),
+            ));
+        }
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut draws = Vec::new();
+        for ClippedMesh(clip_rect, mesh) in &meshes {
+            let index_start = indices.len() as u32;
+            indices.extend_from_slice(&mesh.indices);
+            draws.push((
+                *clip_rect,
+                index_start..indices.len() as u32,
+                vertices.len() as i32,
+            ));
+            vertices.extend(mesh.vertices.iter().map(|vertex| UiVertex {
+                position: [vertex.pos.x, vertex.pos.y],
+                uv: [vertex.uv.x, vertex.uv.y],
+                color: vertex.color.to_array(),
+            }));
+        }
+        let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&vertices),
+            usage: BufferUsage::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&indices),
+            usage: BufferUsage::INDEX,
+        });
+        let screen_size = [
+            self.screen_width as f32 / self.scale_factor,
+            self.screen_height as f32 / self.scale_factor,
+        ];
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::bytes_of(&screen_size),
+            usage: BufferUsage::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Buffer(uniform_buffer.slice(..)),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&self.font_texture.as_ref().unwrap().1),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[RenderPassColorAttachmentDescriptor {
+                attachment: target,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        render_pass.set_index_buffer(index_buffer.slice(..));
+        for (clip_rect, indices, base_vertex) in draws {
+            // The clip rectangle is in points, the scissor rectangle in pixels within the target
+            let left = (clip_rect.min.x * self.scale_factor).max(0.0) as u32;
+            let top = (clip_rect.min.y * self.scale_factor).max(0.0) as u32;
+            let right =
+                ((clip_rect.max.x * self.scale_factor).ceil() as u32).min(self.screen_width);
+            let bottom =
+                ((clip_rect.max.y * self.scale_factor).ceil() as u32).min(self.screen_height);
+            if right <= left || bottom <= top {
+                continue;
+            }
+            render_pass.set_scissor_rect(left, top, right - left, bottom - top);
+            render_pass.draw_indexed(indices, base_vertex, 0..1);
+        }
+    }
+}
+
+fn create_font_texture(
+    queue: &Queue,
+    device: &Device,
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+) -> TextureView {
+    let size = Extent3d {
+        width,
+        height,
+        depth: 1,
+    };
+    let texture = device.create_texture(&TextureDescriptor {
+        label: None,
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Rgba8UnormSrgb,
+        usage: TextureUsage::SAMPLED | TextureUsage::COPY_DST,
+    });
+    queue.write_texture(
+        TextureCopyView {
+            texture: &texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+        },
+        pixels,
+        TextureDataLayout {
+            offset: 0,
+            bytes_per_row: width * 4,
+            rows_per_image: 0,
+        },
+        size,
+    );
+    texture.create_view(&TextureViewDescriptor::default())
+}
+
+fn convert_modifiers(modifiers: ModifiersState) -> egui::Modifiers {
+    egui::Modifiers {
+        alt: modifiers.alt(),
+        ctrl: modifiers.ctrl(),
+        shift: modifiers.shift(),
+        mac_cmd: cfg!(target_os = "macos") && modifiers.logo(),
+        command: if cfg!(target_os = "macos") {
+            modifiers.logo()
+        } else {
+            modifiers.ctrl()
+        },
+    }
+}
+
+/// The keys egui uses for navigating and editing text.
+fn convert_key(key: VirtualKeyCode) -> Option<egui::Key> {
+    Some(match key {
+        VirtualKeyCode::Down => egui::Key::ArrowDown,
+        VirtualKeyCode::Left => egui::Key::ArrowLeft,
+        VirtualKeyCode::Right => egui::Key::ArrowRight,
+        VirtualKeyCode::Up => egui::Key::ArrowUp,
+        VirtualKeyCode::Escape => egui::Key::Escape,
+        VirtualKeyCode::Tab => egui::Key::Tab,
+        VirtualKeyCode::Back => egui::Key::Backspace,
+        VirtualKeyCode::Return => egui::Key::Enter,
+        VirtualKeyCode::Space => egui::Key::Space,
+        VirtualKeyCode::Insert => egui::Key::Insert,
+        VirtualKeyCode::Delete => egui::Key::Delete,
+        VirtualKeyCode::Home => egui::Key::Home,
+        VirtualKeyCode::End => egui::Key::End,
+        VirtualKeyCode::PageUp => egui::Key::PageUp,
+        VirtualKeyCode::PageDown => egui::Key::PageDown,
+        VirtualKeyCode::A => egui::Key::A,
+        VirtualKeyCode::K => egui::Key::K,
+        VirtualKeyCode::U => egui::Key::U,
+        VirtualKeyCode::W => egui::Key::W,
+        VirtualKeyCode::Z => egui::Key::Z,
+        _ => return None,
+    })
+}
+
+/// Windows for inspecting and editing the scene and the renderer's settings.
+pub fn scene_windows(
+    ctx: &CtxRef,
+    queue: &Queue,
+    device: &Device,
+    renderer: &mut Renderer,
+    meshes: &mut Vec<Mesh>,
+) {
+    egui::Window::new("Renderer").show(ctx, |ui| {
+        renderer_settings_ui(ui, device, renderer);
+    });
+    egui::Window::new("Scene").show(ctx, |ui| {
+        ScrollArea::vertical().show(ui, |ui| {
+            CollapsingHeader::new("Camera")
+                .default_open(true)
+                .show(ui, |ui| {
+                    let mut camera = renderer.camera();
+                    ui.label("Position");
+                    vec3_ui(ui, &mut camera.position);
+                    ui.label("Target");
+                    vec3_ui(ui, &mut camera.target);
+                    if camera != renderer.camera() {
+                        renderer.set_camera(camera);
+                    }
+                });
+            CollapsingHeader::new(format!("Lights ({})", renderer.lights.len())).show(ui, |ui| {
+                lights_ui(ui, &mut renderer.lights);
+            });
+            for (i, mesh) in meshes.iter_mut().enumerate() {
+                CollapsingHeader::new(format!("Mesh {} ({} instances)", i, mesh.instances.len()))
+                    .id_source(("mesh", i))
+                    .show(ui, |ui| mesh_ui(ui, queue, mesh));
+            }
+            CollapsingHeader::new("Add primitive").show(ui, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    for primitive in &Primitive::DEFAULTS {
+                        if ui.button(primitive.name()).clicked() {
+                            let mut mesh = primitive.mesh(queue, device, true);
+                            mesh.instances.push(Similarity3::identity());
+                            meshes.push(mesh);
+                        }
+                    }
+                });
+            });
+        });
+    });
+}
+
+fn renderer_settings_ui(ui: &mut Ui, device: &Device, renderer: &mut Renderer) {
+    ui.checkbox(&mut renderer.profiler.show_overlay, "Profiler overlay");
+    ui.checkbox(
+        &mut renderer.debug_draw.depth_test,
+        "Depth test debug lines",
+    );
+    ui.label(renderer.profiler.summary().to_string());
+    ui.separator();
+
+    ui.horizontal(|ui| {
+        ui.label("Culling");
+        ui.radio_value(&mut renderer.culling_mode, CullingMode::Cpu, "CPU");
+        ui.radio_value(&mut renderer.culling_mode, CullingMode::Gpu, "GPU");
+    });
+
+    // Only settings the renderer accepts are offered
+    let current = renderer.render_settings();
+    let mut settings = current;
+    ui.horizontal(|ui| {
+        ui.label("Render path");
+        for &(render_path, name) in &[
+            (RenderPath::Forward, "Forward"),
+            (RenderPath::Deferred, "Deferred"),
+        ] {
+            let valid = RenderSettings {
+                render_path,
+                ..current
+            }
+            .validate(renderer.allow_unvalidated_msaa())
+            .is_ok();
+            if ui
+                .add_enabled(
+                    valid,
+                    egui::SelectableLabel::new(settings.render_path == render_path, name),
+                )
+                .clicked()
+            {
+                settings.render_path = render_path;
+            }
+        }
+    });
+    ComboBox::from_label("Antialiasing")
+        .selected_text(format!("{:?}", settings.antialiasing))
+        .show_ui(ui, |ui| {
+            for &antialiasing in &[
+                Antialiasing::Msaa(1),
+                Antialiasing::Msaa(2),
+                Antialiasing::Msaa(4),
+                Antialiasing::Msaa(8),
+                Antialiasing::Fxaa,
+                Antialiasing::Taa,
+            ] {
+                let valid = RenderSettings {
+                    antialiasing,
+                    ..current
+                }
+                .validate(renderer.allow_unvalidated_msaa())
+                .is_ok();
+                if valid {
+                    ui.selectable_value(
+                        &mut settings.antialiasing,
+                        antialiasing,
+                        format!("{:?}", antialiasing),
+                    );
+                }
+            }
+        });
+    ComboBox::from_label("Debug view")
+        .selected_text(format!("{:?}", settings.debug_view))
+        .show_ui(ui, |ui| {
+            for &debug_view in &DebugView::ALL {
+                ui.selectable_value(
+                    &mut settings.debug_view,
+                    debug_view,
+                    format!("{:?}", debug_view),
+                );
+            }
+        });
+    ui.checkbox(&mut settings.wireframe_overlay, "Wireframe overlay");
+    let mut ambient_occlusion = settings.ambient_occlusion.is_some();
+    ui.checkbox(&mut ambient_occlusion, "Ambient occlusion");
+    settings.ambient_occlusion = match (ambient_occlusion, settings.ambient_occlusion) {
+        (true, None) => RenderSettings::default().ambient_occlusion,
+        (false, _) => None,
+        (true, ambient_occlusion) => ambient_occlusion,
+    };
+    if let Some(ssao) = &mut settings.ambient_occlusion {
+        ui.add(
+            DragValue::new(&mut ssao.radius)
+                .speed(0.01)
+                .prefix("radius "),
+        );
+        ui.add(DragValue::new(&mut ssao.bias).speed(0.001).prefix("bias "));
+        ui.add(
+            DragValue::new(&mut ssao.intensity)
+                .speed(0.01)
+                .prefix("intensity "),
+        );
+    }
+    if settings != current {
+        renderer.set_render_settings(device, settings).unwrap();
+    }
+    ui.separator();
+
+    let post_process = &mut renderer.post_process_settings;
+    ComboBox::from_label("Tonemapper")
+        .selected_text(format!("{:?}", post_process.tonemapper))
+        .show_ui(ui, |ui| {
+            for &tonemapper in &[Tonemapper::None, Tonemapper::Aces, Tonemapper::Agx] {
+                ui.selectable_value(
+                    &mut post_process.tonemapper,
+                    tonemapper,
+                    format!("{:?}", tonemapper),
+                );
+            }
+        });
+    ui.add(
+        DragValue::new(&mut post_process.gamma)
+            .speed(0.01)
+            .clamp_range(0.1..=5.0)
+            .prefix("gamma "),
+    );
+    let mut auto_exposure = matches!(post_process.exposure, Exposure::Auto { .. });
+    ui.checkbox(&mut auto_exposure, "Auto exposure");
+    match (auto_exposure, &mut post_process.exposure) {
+        (
+            true,
+            Exposure::Auto {
+                compensation,
+                adaptation_rate,
+            },
+        ) => {
+            ui.add(
+                DragValue::new(compensation)
+                    .speed(0.05)
+                    .prefix("compensation "),
+            );
+            ui.add(
+                DragValue::new(adaptation_rate)
+                    .speed(0.05)
+                    .clamp_range(0.0..=100.0)
+                    .prefix("adaptation rate "),
+            );
+        }
+        (false, Exposure::Manual(exposure)) => {
+            ui.add(DragValue::new(exposure).speed(0.05).prefix("exposure "));
+        }
+        (true, exposure) => *exposure = PostProcessSettings::default().exposure,
+        (false, exposure) => *exposure = Exposure::Manual(0.0),
+    }
+    let mut bloom = post_process.bloom.is_some();
+    ui.checkbox(&mut bloom, "Bloom");
+    match (bloom, &mut post_process.bloom) {
+        (true, Some(bloom)) => {
+            ui.add(
+                DragValue::new(&mut bloom.threshold)
+                    .speed(0.05)
+                    .prefix("threshold "),
+            );
+            ui.add(
+                DragValue::new(&mut bloom.intensity)
+                    .speed(0.01)
+                    .prefix("intensity "),
+            );
+            ui.add(
+                DragValue::new(&mut bloom.radius)
+                    .speed(0.01)
+                    .prefix("radius "),
+            );
+        }
+        (true, bloom) => *bloom = PostProcessSettings::default().bloom,
+        (false, bloom) => *bloom = None,
+    }
+}
+
+fn lights_ui(ui: &mut Ui, lights: &mut Vec<PointLight>) {
+    ui.horizontal(|ui| {
+        if ui.button("Add").clicked() {
+            lights.push(PointLight {
+                position: Vec3::new(0.0, 1.0, 0.0),
+                range: 5.0,
+                color: Vec3::one(),
+                intensity: 10.0,
+            });
+        }
+        if ui.button("Remove all").clicked() {
+            lights.clear();
+        }
+    });
+    let mut removed = None;
+    ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+        for (i, light) in lights.iter_mut().enumerate() {
+            CollapsingHeader::new(format!("Light {}", i))
+                .id_source(("light", i))
+                .show(ui, |ui| {
+                    vec3_ui(ui, &mut light.position);
+                    ui.horizontal(|ui| {
+                        let mut color = [light.color.x, light.color.y, light.color.z];
+                        ui.color_edit_button_rgb(&mut color);
+                        light.color = Vec3::from(color);
+                        ui.add(
+                            DragValue::new(&mut light.intensity)
+                                .speed(0.1)
+                                .clamp_range(0.0..=f32::MAX)
+                                .prefix("intensity "),
+                        );
+                        ui.add(
+                            DragValue::new(&mut light.range)
+                                .speed(0.05)
+                                .clamp_range(0.0..=f32::MAX)
+                                .prefix("range "),
+                        );
+                    });
+                    if ui.button("Remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+        }
+    });
+    if let Some(i) = removed {
+        lights.remove(i);
+    }
+}
+
+fn mesh_ui(ui: &mut Ui, queue: &Queue, mesh: &mut Mesh) {
+    if let Some(geometry) = mesh.geometry() {
+        ui.label(format!(
+            "{} vertices, {} triangles",
+            geometry.vertices.len(),
+            geometry.lods.first().map_or(0, |lod| lod.len() / 3)
+        ));
+        let vertex_count = geometry.vertices.len();
+        // Moves the vertices along their normals
+        ui.horizontal(|ui| {
+            for &(label, distance) in &[("Deflate", -0.01), ("Inflate", 0.01)] {
+                if ui.button(label).clicked() {
+                    mesh.edit_vertices(queue, 0..vertex_count, |vertices| {
+                        for vertex in vertices {
+                            for i in 0..3 {
+                                vertex.position[i] += vertex.normal[i] * distance;
+                            }
+                        }
+                    });
+                }
+            }
+        });
+    }
+    if let Some(skin) = mesh.skin_mut() {
+        let clip_name = |clip: Option<usize>| match clip {
+            Some(clip) => format!("Clip {}", clip),
+            None => "Rest pose".to_string(),
+        };
+        ComboBox::from_label("Animation")
+            .selected_text(clip_name(skin.clip))
+            .show_ui(ui, |ui| {
+                for clip in iter::once(None).chain((0..skin.clips.len()).map(Some)) {
+                    ui.selectable_value(&mut skin.clip, clip, clip_name(clip));
+                }
+            });
+        ui.add(
+            DragValue::new(&mut skin.speed)
+                .speed(0.01)
+                .clamp_range(0.0..=10.0)
+                .prefix("speed "),
+        );
+    }
+    ComboBox::from_label("Alpha mode")
+        .selected_text(format!("{:?}", mesh.alpha_mode))
+        .show_ui(ui, |ui| {
+            for &alpha_mode in &[
+                AlphaMode::Opaque,
+                AlphaMode::Mask { cutoff: 0.5 },
+                AlphaMode::AlphaToCoverage,
+                AlphaMode::Blend,
+            ] {
+                let selected =
+                    mem::discriminant(&mesh.alpha_mode) == mem::discriminant(&alpha_mode);
+                if ui
+                    .selectable_label(selected, format!("{:?}", alpha_mode))
+                    .clicked()
+                    && !selected
+                {
+                    mesh.alpha_mode = alpha_mode;
+                }
+            }
+        });
+    if let AlphaMode::Mask { cutoff } = &mut mesh.alpha_mode {
+        ui.add(
+            DragValue::new(cutoff)
+                .speed(0.01)
+                .clamp_range(0.0..=1.0)
+                .prefix("cutoff "),
+        );
+    }
+    let mut removed = None;
+    ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+        for (i, instance) in mesh.instances.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(i.to_string());
+                vec3_ui(ui, &mut instance.translation);
+                ui.add(
+                    DragValue::new(&mut instance.scale)
+                        .speed(0.01)
+                        .clamp_range(0.0..=f32::MAX)
+                        .prefix("scale "),
+                );
+                if ui.small_button("x").clicked() {
+                    removed = Some(i);
+                }
+            });
+        }
+    });
+    if let Some(i) = removed {
+        mesh.instances.remove(i);
+    }
+}
+
+fn vec3_ui(ui: &mut Ui, vector: &mut Vec3) {
+    ui.horizontal(|ui| {
+        ui.add(DragValue::new(&mut vector.x).speed(0.01).prefix("x "));
+        ui.add(DragValue::new(&mut vector.y).speed(0.01).prefix("y "));
+        ui.add(DragValue::new(&mut vector.z).speed(0.01).prefix("z "));
+    });
+}